@@ -0,0 +1,77 @@
+//! Hot handlers of incoming queries on representative datasets:
+//! FindNode over 10k known peers, Store of overlay nodes next to 100k values
+
+use adnl::common::KeyOption;
+use adnl::node::{AdnlNode, AdnlNodeConfig};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use dht::{bench, distance, DhtNode};
+use rand::Rng;
+use std::sync::Arc;
+
+const KEY_TAG: usize = 1;
+const PEERS: usize = 10_000;
+const VALUES: usize = 100_000;
+const VALUE_SIZE: usize = 128;
+
+/// DHT node on top of ADNL node which is never started
+fn node() -> Arc<DhtNode> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let (_, config) = AdnlNodeConfig::with_ip_address_and_key_type(
+            "127.0.0.1:4191",
+            KeyOption::KEY_ED25519,
+            vec![KEY_TAG],
+        )
+        .unwrap();
+        let adnl = AdnlNode::with_config(config).await.unwrap();
+        DhtNode::with_adnl_node(adnl, KEY_TAG).unwrap()
+    })
+}
+
+fn find_node(c: &mut Criterion) {
+    let dht = node();
+    bench::fill_buckets(&dht, PEERS).unwrap();
+    c.bench_function("find_node_10k_peers", |b| {
+        b.iter_batched(
+            || rand::thread_rng().gen(),
+            |key| bench::process_find_node(&dht, key, 10).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn store_overlay_nodes(c: &mut Criterion) {
+    let dht = node();
+    bench::fill_storage(&dht, VALUES, VALUE_SIZE).unwrap();
+    c.bench_function("store_overlay_nodes_100k_values", |b| {
+        b.iter_batched(
+            || bench::overlay_nodes_value(&dht, rand::thread_rng().gen()).unwrap(),
+            |(key, value)| bench::process_store_overlay_nodes(&dht, key, value).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn distances(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let keys: Vec<[u8; 32]> = (0..1024).map(|_| rng.gen()).collect();
+    let mut group = c.benchmark_group("distance");
+    group.bench_function("distance", |b| {
+        b.iter(|| {
+            for pair in keys.windows(2) {
+                criterion::black_box(distance::distance(&pair[0], &pair[1]));
+            }
+        })
+    });
+    group.bench_function("differing_bits", |b| {
+        b.iter(|| {
+            for pair in keys.windows(2) {
+                criterion::black_box(distance::differing_bits(&pair[0], &pair[1]).count());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, find_node, store_overlay_nodes, distances);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+//! Allocations of TL serialization used in signature checks:
+//! fresh buffer per object versus pooled buffers
+
+use adnl::common::serialize;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use ton_api::ton::pub_::publickey::Ed25519;
+use ton_api::ton::{self, dht::key::Key as DhtKey, dht::keydescription::KeyDescription};
+use ton_api::IntoBoxed;
+
+#[allow(dead_code)]
+#[path = "../src/buffer_pool.rs"]
+mod buffer_pool;
+
+use buffer_pool::BufferPool;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const OBJECTS: usize = 10_000;
+
+fn key_description() -> ton::dht::KeyDescription {
+    KeyDescription {
+        id: Ed25519 {
+            key: ton::int256([1u8; 32]),
+        }
+        .into_boxed(),
+        key: DhtKey {
+            id: ton::int256([2u8; 32]),
+            idx: 0,
+            name: ton::bytes(b"address".to_vec()),
+        },
+        signature: ton::bytes(vec![3u8; 64]),
+        update_rule: ton::dht::UpdateRule::Dht_UpdateRule_Signature,
+    }
+    .into_boxed()
+}
+
+fn allocations(f: impl Fn()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn serialization(c: &mut Criterion) {
+    let object = key_description();
+    let pool = BufferPool::new();
+    let fresh = || {
+        for _ in 0..OBJECTS {
+            criterion::black_box(serialize(&object).unwrap());
+        }
+    };
+    let pooled = || {
+        for _ in 0..OBJECTS {
+            criterion::black_box(pool.serialize(&object).unwrap());
+        }
+    };
+    println!(
+        "Allocations per {} objects: fresh {}, pooled {}",
+        OBJECTS,
+        allocations(fresh),
+        allocations(pooled)
+    );
+    let mut group = c.benchmark_group("serialization");
+    group.bench_function("fresh", |b| b.iter(fresh));
+    group.bench_function("pooled", |b| b.iter(pooled));
+    group.finish();
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+//! Contention of DHT value storage under concurrent Store/FindValue load:
+//! single `DashMap` versus storage sharded by key prefix
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use dashmap::DashMap;
+use rand::Rng;
+use std::{sync::Arc, thread};
+
+#[allow(dead_code)]
+#[path = "../src/storage.rs"]
+mod storage;
+
+use storage::ShardedMap;
+
+const KEYS: usize = 100_000;
+const OPS_PER_THREAD: usize = 10_000;
+
+trait Storage: Send + Sync + 'static {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>>;
+    fn put(&self, key: [u8; 32], value: Vec<u8>);
+}
+
+impl Storage for DashMap<[u8; 32], Vec<u8>> {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        DashMap::get(self, key).map(|value| value.value().clone())
+    }
+    fn put(&self, key: [u8; 32], value: Vec<u8>) {
+        self.insert(key, value);
+    }
+}
+
+impl Storage for ShardedMap<[u8; 32], Vec<u8>> {
+    fn get(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        ShardedMap::get(self, key)
+    }
+    fn put(&self, key: [u8; 32], value: Vec<u8>) {
+        self.insert(key, value);
+    }
+}
+
+fn keys() -> Arc<Vec<[u8; 32]>> {
+    let mut rng = rand::thread_rng();
+    Arc::new((0..KEYS).map(|_| rng.gen()).collect())
+}
+
+fn fill<S: Storage>(storage: &S, keys: &[[u8; 32]]) {
+    for key in keys {
+        storage.put(*key, vec![0u8; 256]);
+    }
+}
+
+/// Each thread does 1 store per 4 lookups
+fn load<S: Storage>(storage: Arc<S>, keys: Arc<Vec<[u8; 32]>>, threads: usize) {
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let storage = storage.clone();
+            let keys = keys.clone();
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                for i in 0..OPS_PER_THREAD {
+                    let key = keys[rng.gen_range(0, keys.len())];
+                    if (i + t) % 5 == 0 {
+                        storage.put(key, vec![1u8; 256]);
+                    } else {
+                        criterion::black_box(storage.get(&key));
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+fn storage_contention(c: &mut Criterion) {
+    let keys = keys();
+    let mut group = c.benchmark_group("storage_contention");
+    for threads in [1usize, 4, 16].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("dashmap", threads),
+            threads,
+            |b, &threads| {
+                b.iter_batched(
+                    || {
+                        let storage = Arc::new(DashMap::new());
+                        fill(storage.as_ref(), &keys);
+                        storage
+                    },
+                    |storage| load(storage, keys.clone(), threads),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("sharded", threads),
+            threads,
+            |b, &threads| {
+                b.iter_batched(
+                    || {
+                        let storage = Arc::new(ShardedMap::new());
+                        fill(storage.as_ref(), &keys);
+                        storage
+                    },
+                    |storage| load(storage, keys.clone(), threads),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, storage_contention);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use adnl::common::KeyOption;
+use adnl::node::{AdnlNode, AdnlNodeConfig};
+use dht::DhtNode;
+use tokio::runtime::Runtime;
+
+const KEY_TAG: usize = 1;
+
+/// DHT node on top of ADNL node which is never started, so no network is used
+pub fn node(runtime: &Runtime) -> Arc<DhtNode> {
+    runtime.block_on(async {
+        let (_, config) = AdnlNodeConfig::with_ip_address_and_key_type(
+            "127.0.0.1:4191",
+            KeyOption::KEY_ED25519,
+            vec![KEY_TAG],
+        )
+        .unwrap();
+        let adnl = AdnlNode::with_config(config).await.unwrap();
+        DhtNode::with_adnl_node(adnl, KEY_TAG).unwrap()
+    })
+}
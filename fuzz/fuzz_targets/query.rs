@@ -0,0 +1,21 @@
+#![no_main]
+use std::sync::Arc;
+
+use dht::DhtNode;
+use libfuzzer_sys::fuzz_target;
+use once_cell::sync::Lazy;
+use tokio::runtime::Runtime;
+
+mod common;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().unwrap());
+static NODE: Lazy<Arc<DhtNode>> = Lazy::new(|| common::node(&RUNTIME));
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 32 {
+        return;
+    }
+    let mut peer = [0u8; 32];
+    peer.copy_from_slice(&data[..32]);
+    let _ = RUNTIME.block_on(dht::fuzzing::try_consume_query(&NODE, &data[32..], peer));
+});
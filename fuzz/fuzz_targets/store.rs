@@ -0,0 +1,16 @@
+#![no_main]
+use std::sync::Arc;
+
+use dht::DhtNode;
+use libfuzzer_sys::fuzz_target;
+use once_cell::sync::Lazy;
+use tokio::runtime::Runtime;
+
+mod common;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().unwrap());
+static NODE: Lazy<Arc<DhtNode>> = Lazy::new(|| common::node(&RUNTIME));
+
+fuzz_target!(|data: &[u8]| {
+    let _ = dht::fuzzing::process_store(&NODE, data);
+});
@@ -0,0 +1,148 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use adnl::common::{KeyId, Wait};
+
+use crate::{DhtNode, TARGET};
+
+/// Handle of background pre-resolution of bucket peers' addresses
+pub struct AddressWarmup {
+    stopped: Arc<AtomicBool>,
+}
+
+impl AddressWarmup {
+    /// Stop pre-resolution
+    pub fn cancel(&self) {
+        self.stopped.store(true, Ordering::Relaxed)
+    }
+}
+
+impl DhtNode {
+    /// Keep address data of routing table fresh like `warm_up_addresses` does, and
+    /// forget known peers whose signed address lists were not obtained `max_misses`
+    /// times in row
+    pub fn refresh_addresses(
+        dht: &Arc<Self>,
+        interval: Duration,
+        batch: usize,
+        max_misses: u32,
+    ) -> AddressWarmup {
+        Self::spawn_address_refresh(dht, interval, batch, Some(max_misses.max(1)))
+    }
+
+    /// Keep ADNL address entries of bucket peers warm: each `interval` re-request
+    /// signed address lists of next `batch` bucket peers, rotating over all of them
+    pub fn warm_up_addresses(dht: &Arc<Self>, interval: Duration, batch: usize) -> AddressWarmup {
+        Self::spawn_address_refresh(dht, interval, batch, None)
+    }
+
+    fn spawn_address_refresh(
+        dht: &Arc<Self>,
+        interval: Duration,
+        batch: usize,
+        max_misses: Option<u32>,
+    ) -> AddressWarmup {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let warmup = AddressWarmup {
+            stopped: stopped.clone(),
+        };
+        let weak_dht = Arc::downgrade(dht);
+        let tasks = dht.tasks.clone();
+        let batch = batch.max(1);
+        dht.spawn(async move {
+            let mut cursor = 0;
+            let mut misses: HashMap<Arc<KeyId>, u32> = HashMap::new();
+            loop {
+                let running = tasks.sleep(interval).await;
+                if !running || stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                let mut peers: Vec<Arc<KeyId>> = Vec::new();
+                dht.buckets.for_each(|peer, _| peers.push(peer.clone()));
+                if peers.is_empty() {
+                    continue;
+                }
+                let bucket_peers: HashSet<&Arc<KeyId>> = peers.iter().collect();
+                misses.retain(|peer, _| bucket_peers.contains(peer));
+                if cursor >= peers.len() {
+                    cursor = 0
+                }
+                let (wait, mut queue_reader) = Wait::new();
+                for peer in peers
+                    .iter()
+                    .cycle()
+                    .skip(cursor)
+                    .take(batch.min(peers.len()))
+                {
+                    let dht_cloned = dht.clone();
+                    let peer = peer.clone();
+                    let wait = wait.clone();
+                    wait.request();
+                    dht.spawn(async move {
+                        let refreshed = match dht_cloned.get_signed_address_list(&peer).await {
+                            Ok(node) => node.is_some(),
+                            Err(e) => {
+                                log::debug!(
+                                    target: TARGET,
+                                    "Cannot refresh address of {}: {}",
+                                    peer,
+                                    e
+                                );
+                                false
+                            }
+                        };
+                        wait.respond(Some((peer, refreshed)))
+                    });
+                }
+                cursor += batch;
+                let mut refreshed = 0;
+                let mut pruned = HashSet::new();
+                while let Some(answer) = wait.wait(&mut queue_reader, false).await {
+                    let (peer, answered) = if let Some(answer) = answer {
+                        answer
+                    } else {
+                        continue;
+                    };
+                    if answered {
+                        refreshed += 1;
+                        misses.remove(&peer);
+                        continue;
+                    }
+                    if let Some(max_misses) = max_misses {
+                        let count = misses.entry(peer.clone()).or_insert(0);
+                        *count += 1;
+                        if (*count >= max_misses) && !dht.is_priority_peer(&peer) {
+                            misses.remove(&peer);
+                            log_event!(info, "prune", peer = peer, reason = "address");
+                            pruned.insert(peer);
+                        }
+                    }
+                }
+                if !pruned.is_empty() {
+                    // Pruned peers are forgotten entirely, so lookups do not re-add them
+                    if let Err(e) = dht.remove_known_peers(&pruned) {
+                        log::warn!(target: TARGET, "Cannot remove pruned DHT peers: {}", e)
+                    }
+                }
+                log::debug!(
+                    target: TARGET,
+                    "Refreshed addresses of {} of {} bucket peers",
+                    refreshed,
+                    peers.len()
+                );
+            }
+        });
+        warmup
+    }
+}
@@ -0,0 +1,109 @@
+//! Internals of hot handlers exposed to benchmarks
+
+use adnl::common::{hash, now, serialize, KeyId};
+use overlay::OverlayShortId;
+use rand::Rng;
+use ton_api::ton::adnl::addresslist::AddressList;
+use ton_api::ton::dht::key::Key as DhtKey;
+use ton_api::ton::dht::keydescription::KeyDescription as DhtKeyDescription;
+use ton_api::ton::dht::node::Node;
+use ton_api::ton::dht::value::Value as DhtValue;
+use ton_api::ton::dht::UpdateRule;
+use ton_api::ton::overlay::nodes::Nodes as OverlayNodes;
+use ton_api::ton::pub_::publickey::{Ed25519, Overlay};
+use ton_api::ton::{self, rpc};
+use ton_api::IntoBoxed;
+use ton_types::Result;
+
+use crate::{storage::StoredValue, DhtKeyId, DhtNode};
+
+/// Fill routing table with `count` random peers, signatures and ADNL are bypassed
+pub fn fill_buckets(dht: &DhtNode, count: usize) -> Result<()> {
+    let mut rng = rand::thread_rng();
+    for _ in 0..count {
+        let key: [u8; 32] = rng.gen();
+        let node = Node {
+            id: Ed25519 {
+                key: ton::int256(key),
+            }
+            .into_boxed(),
+            addr_list: AddressList {
+                addrs: Vec::new().into(),
+                version: 0,
+                reinit_date: 0,
+                priority: 0,
+                expire_at: 0,
+            },
+            version: now(),
+            signature: ton::bytes::default(),
+        };
+        dht.insert_peer(&KeyId::from_data(rng.gen()), &node)?;
+    }
+    Ok(())
+}
+
+/// Fill storage with `count` random values of `size` bytes, verification is bypassed
+pub fn fill_storage(dht: &DhtNode, count: usize, size: usize) -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let ttl = now() + DhtNode::TIMEOUT_VALUE;
+    for _ in 0..count {
+        let key = DhtKey {
+            id: ton::int256(rng.gen()),
+            idx: 0,
+            name: ton::bytes(b"address".to_vec()),
+        };
+        let value = DhtValue {
+            key: DhtKeyDescription {
+                id: dht.node_key.as_tl_public_key()?,
+                key: key.clone(),
+                signature: ton::bytes::default(),
+                update_rule: UpdateRule::Dht_UpdateRule_Signature,
+            },
+            ttl,
+            signature: ton::bytes::default(),
+            value: ton::bytes((0..size).map(|_| rng.gen()).collect()),
+        };
+        dht.storage.insert(hash(key)?, StoredValue::new(value));
+    }
+    Ok(())
+}
+
+/// Overlay nodes value with own node signed for overlay with given ID
+pub fn overlay_nodes_value(dht: &DhtNode, overlay_id: [u8; 32]) -> Result<(DhtKeyId, DhtValue)> {
+    let overlay_id = Overlay {
+        name: ton::bytes(overlay_id.to_vec()),
+    };
+    let overlay_short_id = OverlayShortId::from_data(hash(overlay_id.clone())?);
+    let node = DhtNode::sign_overlay_node(&overlay_short_id, &dht.node_key)?;
+    let nodes = OverlayNodes {
+        nodes: vec![node].into(),
+    }
+    .into_boxed();
+    let key = dht.dht_key_from_key_id(&overlay_short_id, "nodes");
+    let value = DhtValue {
+        key: DhtKeyDescription {
+            id: overlay_id.into_boxed(),
+            key: key.clone(),
+            signature: ton::bytes::default(),
+            update_rule: UpdateRule::Dht_UpdateRule_OverlayNodes,
+        },
+        ttl: now() + DhtNode::TIMEOUT_VALUE,
+        signature: ton::bytes::default(),
+        value: ton::bytes(serialize(&nodes)?),
+    };
+    Ok((hash(key)?, value))
+}
+
+/// Answer FindNode query, returns number of nodes in answer
+pub fn process_find_node(dht: &DhtNode, key: [u8; 32], k: i32) -> Result<usize> {
+    let query = rpc::dht::FindNode {
+        key: ton::int256(key),
+        k,
+    };
+    Ok(dht.process_find_node(&query)?.nodes.len())
+}
+
+/// Store overlay nodes value
+pub fn process_store_overlay_nodes(dht: &DhtNode, key: DhtKeyId, value: DhtValue) -> Result<bool> {
+    dht.process_store_overlay_nodes(key, value)
+}
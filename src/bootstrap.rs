@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use adnl::common::{KeyId, Wait};
+use ton_types::{fail, Result};
+
+use crate::{DhtError, DhtNode, TARGET};
+
+/// Diagnostics of bootstrap which did not complete before deadline
+#[derive(Clone, Debug, Default)]
+pub struct BootstrapReport {
+    /// Number of answered peers in routing table
+    pub ready_peers: usize,
+    /// Required number of answered peers
+    pub min_peers: usize,
+    /// Peers which failed last query, with reasons
+    pub failed: Vec<(Arc<KeyId>, String)>,
+}
+
+impl fmt::Display for BootstrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} peers ready, {} failed",
+            self.ready_peers,
+            self.min_peers,
+            self.failed.len()
+        )?;
+        for (peer, reason) in self.failed.iter() {
+            write!(f, "; {}: {}", peer, reason)?
+        }
+        Ok(())
+    }
+}
+
+impl DhtNode {
+    const BOOTSTRAP_PARALLELISM: usize = 16;
+    const BOOTSTRAP_ROUND_PAUSE: Duration = Duration::from_secs(1);
+
+    /// Query known peers for nodes in rounds until routing table has at least
+    /// `min_peers` peers which answered us, with bounded number of queries in flight.
+    /// Returns number of such peers, or fails with `DhtError::NotReady` when
+    /// deadline passes
+    pub async fn wait_ready(dht: &Arc<Self>, min_peers: usize, deadline: Instant) -> Result<usize> {
+        let mut failed = HashMap::new();
+        loop {
+            let ready_peers = dht.ready_peers();
+            if ready_peers >= min_peers {
+                return Ok(ready_peers);
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            let known_peers = dht.known_peers_snapshot();
+            if known_peers.is_empty() {
+                fail!(DhtError::NoPeers)
+            }
+            let mut peers = known_peers.into_iter().filter(|peer| !dht.is_banned(peer));
+            let (wait, mut queue_reader) = Wait::new();
+            for peer in (&mut peers).take(Self::BOOTSTRAP_PARALLELISM) {
+                Self::query_bootstrap_peer(dht, &wait, peer)
+            }
+            loop {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                let answer = match dht
+                    .tasks
+                    .timeout(timeout, wait.wait(&mut queue_reader, false))
+                    .await
+                {
+                    Some(answer) => answer,
+                    None => break,
+                };
+                if let Some(Some(_)) = &answer {
+                    // Next peer is queried in place of answered one
+                    if let Some(peer) = peers.next() {
+                        Self::query_bootstrap_peer(dht, &wait, peer)
+                    }
+                }
+                match answer {
+                    Some(Some((peer, Ok(())))) => {
+                        failed.remove(&peer);
+                        let ready_peers = dht.ready_peers();
+                        if ready_peers >= min_peers {
+                            return Ok(ready_peers);
+                        }
+                    }
+                    Some(Some((peer, Err(reason)))) => {
+                        log::debug!(target: TARGET, "Bootstrap peer {} failed: {}", peer, reason);
+                        failed.insert(peer, reason);
+                    }
+                    Some(None) => (),
+                    None => break,
+                }
+            }
+            let pause = deadline
+                .saturating_duration_since(Instant::now())
+                .min(Self::BOOTSTRAP_ROUND_PAUSE);
+            if !dht.tasks.sleep(pause).await {
+                fail!("DHT node is stopped")
+            }
+        }
+        let report = BootstrapReport {
+            ready_peers: dht.ready_peers(),
+            min_peers,
+            failed: failed.into_iter().collect(),
+        };
+        fail!(DhtError::NotReady(report))
+    }
+
+    fn query_bootstrap_peer(
+        dht: &Arc<Self>,
+        wait: &Arc<Wait<(Arc<KeyId>, std::result::Result<(), String>)>>,
+        peer: Arc<KeyId>,
+    ) {
+        let dht_cloned = dht.clone();
+        let wait = wait.clone();
+        wait.request();
+        let k = dht.options.answer_k_default as i32;
+        dht.spawn(async move {
+            let result = match dht_cloned.find_dht_nodes(&peer, k).await {
+                Ok(_) => {
+                    let answered = dht_cloned
+                        .peer_stats(&peer)
+                        .map(|stats| stats.failed_in_row == 0)
+                        .unwrap_or(false);
+                    if answered {
+                        Ok(())
+                    } else {
+                        Err("no answer".to_string())
+                    }
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            wait.respond(Some((peer, result)))
+        });
+    }
+
+    fn ready_peers(&self) -> usize {
+        let mut ret = 0;
+        self.buckets.for_each(|peer, _| {
+            let answered = self
+                .peer_stats
+                .get(peer)
+                .map(|stats| stats.value().answered > 0)
+                .unwrap_or(false);
+            if answered {
+                ret += 1
+            }
+        });
+        ret
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+use adnl::common::serialize_inplace;
+use ton_api::BoxedSerialize;
+use ton_types::Result;
+
+/// Pool of reusable buffers for TL serialization on hot paths: signature checks
+/// of received nodes and values, key hashing of received values, answer size
+/// fitting and traffic accounting. Outgoing queries and answers are serialized
+/// by ADNL itself and do not use the pool
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    const MAX_BUFFERS: usize = 64;
+    const MAX_BUFFER_SIZE: usize = 64 * 1024;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Take empty buffer from pool, it is returned back when dropped
+    pub(crate) fn get(&self) -> PooledBuffer<'_> {
+        let buf = self.lock().pop().unwrap_or_default();
+        PooledBuffer { buf, pool: self }
+    }
+
+    /// Serialize boxed TL object into pooled buffer
+    pub(crate) fn serialize<T: BoxedSerialize>(&self, object: &T) -> Result<PooledBuffer<'_>> {
+        let mut buf = self.get();
+        serialize_inplace(&mut buf, object)?;
+        Ok(buf)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        match self.buffers.lock() {
+            Ok(buffers) => buffers,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() > Self::MAX_BUFFER_SIZE {
+            // Do not keep memory of rare huge objects
+            return;
+        }
+        buf.clear();
+        let mut buffers = self.lock();
+        if buffers.len() < Self::MAX_BUFFERS {
+            buffers.push(buf)
+        }
+    }
+}
+
+/// Buffer taken from pool
+pub(crate) struct PooledBuffer<'a> {
+    buf: Vec<u8>,
+    pool: &'a BufferPool,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf))
+    }
+}
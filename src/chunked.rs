@@ -0,0 +1,155 @@
+use std::{convert::TryInto, sync::Arc};
+
+use adnl::common::{hash, serialize, KeyId, KeyOption};
+use sha2::{Digest, Sha256};
+use ton_api::ton::adnl::message::message::Custom as ChunkData;
+use ton_api::ton::adnl::Message as ChunkDataBoxed;
+use ton_api::{ton, IntoBoxed};
+use ton_types::{fail, Result};
+
+use crate::{DhtError, DhtNode, StoreReport, TARGET};
+
+/// Max size of payload part stored under one key, so that value with key description
+/// and signatures fits single datagram
+const CHUNK_SIZE: usize = 768;
+/// Max number of chunks in one record
+const MAX_CHUNKS: usize = 256;
+/// Manifest is number of chunks, payload size and payload SHA-256
+const MANIFEST_SIZE: usize = 40;
+
+impl DhtNode {
+    /// Find payload stored with `store_chunked` by given owner under given name
+    pub async fn find_chunked(
+        dht: &Arc<Self>,
+        id: &Arc<KeyId>,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let manifest = match Self::find_chunk(dht, id, name, 0).await? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        if manifest.len() != MANIFEST_SIZE {
+            fail!(DhtError::Malformed(format!(
+                "Wrong manifest of chunked value {}",
+                name
+            )))
+        }
+        let count = u32::from_le_bytes(manifest[0..4].try_into()?) as usize;
+        let size = u32::from_le_bytes(manifest[4..8].try_into()?) as usize;
+        if count > MAX_CHUNKS || size > count * CHUNK_SIZE {
+            fail!(DhtError::Malformed(format!(
+                "Too big chunked value {}: {} chunks, {} bytes",
+                name, count, size
+            )))
+        }
+        let mut payload = Vec::with_capacity(size);
+        for idx in 1..=count {
+            match Self::find_chunk(dht, id, name, idx as i32).await? {
+                Some(chunk) => payload.extend_from_slice(&chunk),
+                None => {
+                    log::debug!(
+                        target: TARGET,
+                        "Chunk {} of {} of value {} not found",
+                        idx,
+                        count,
+                        name
+                    );
+                    return Ok(None);
+                }
+            }
+        }
+        if payload.len() != size || Sha256::digest(&payload).as_slice() != &manifest[8..] {
+            fail!(DhtError::Verification(format!(
+                "Chunked value {} does not match its manifest",
+                name
+            )))
+        }
+        Ok(Some(payload))
+    }
+
+    /// Store payload of any size up to 192 KB: it is split into chunks stored under
+    /// given name with indices 1..n, then manifest is stored with index 0.
+    /// Returns reports in the order chunks were stored, manifest is the last
+    pub async fn store_chunked(
+        dht: &Arc<Self>,
+        key: &Arc<KeyOption>,
+        name: &str,
+        payload: &[u8],
+    ) -> Result<Vec<StoreReport>> {
+        let count = (payload.len() + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        if count > MAX_CHUNKS {
+            fail!("Too big payload to store in DHT: {} bytes", payload.len())
+        }
+        let mut reports = Vec::with_capacity(count + 1);
+        for (i, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+            reports.push(Self::store_chunk(dht, key, name, i as i32 + 1, chunk).await?)
+        }
+        let mut manifest = Vec::with_capacity(MANIFEST_SIZE);
+        manifest.extend_from_slice(&(count as u32).to_le_bytes());
+        manifest.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        manifest.extend_from_slice(Sha256::digest(payload).as_slice());
+        reports.push(Self::store_chunk(dht, key, name, 0, &manifest).await?);
+        Ok(reports)
+    }
+
+    async fn find_chunk(
+        dht: &Arc<Self>,
+        id: &Arc<KeyId>,
+        name: &str,
+        idx: i32,
+    ) -> Result<Option<Vec<u8>>> {
+        let key = dht.dht_key_builder(id, name).idx(idx).build();
+        let (mut found, _) = Self::find_value(
+            dht,
+            key,
+            |object| object.is::<ChunkDataBoxed>(),
+            false,
+            &mut None,
+        )
+        .await?;
+        match found.pop() {
+            Some(found) => match found.object.downcast::<ChunkDataBoxed>() {
+                Ok(ChunkDataBoxed::Adnl_Message_Custom(chunk)) => Ok(Some(chunk.data.0)),
+                _ => fail!("INTERNAL ERROR: chunk type mismatch in search"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn store_chunk(
+        dht: &Arc<Self>,
+        key: &Arc<KeyOption>,
+        name: &str,
+        idx: i32,
+        data: &[u8],
+    ) -> Result<StoreReport> {
+        let chunk = ChunkData {
+            data: ton::bytes(data.to_vec()),
+        }
+        .into_boxed();
+        let value =
+            dht.sign_indexed_value(name, idx, &serialize(&chunk)?, key, Self::TIMEOUT_VALUE)?;
+        let dht_key = dht.dht_key_builder(key.id(), name).idx(idx).build();
+        dht.process_store_signed_value(hash(dht_key.clone())?, value.clone())?;
+        Self::store_value(
+            dht,
+            dht_key,
+            value,
+            |object| object.is::<ChunkDataBoxed>(),
+            false,
+            |mut objects| {
+                while let Some(found) = objects.pop() {
+                    if let Ok(ChunkDataBoxed::Adnl_Message_Custom(chunk)) =
+                        found.object.downcast::<ChunkDataBoxed>()
+                    {
+                        if chunk.data.0 == data {
+                            return Ok(true);
+                        }
+                    }
+                }
+                Ok(false)
+            },
+        )
+        .await
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use adnl::common::{KeyId, KeyOption};
+use adnl::node::{AdnlNode, AdnlNodeConfig, IpAddress};
+use overlay::OverlayShortId;
+use ton_api::ton::dht::node::Node;
+use ton_types::{fail, Result};
+
+use crate::{DhtHandle, DhtNode, DhtNodeOptions, DhtRole, ResolvedOverlayNode, StoreReport};
+
+/// DHT client for tools: owns ADNL node with throwaway key and hides its plumbing
+pub struct DhtClient {
+    adnl: Arc<AdnlNode>,
+    handle: DhtHandle,
+}
+
+impl DhtClient {
+    const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(30);
+    const KEY_TAG: usize = 1;
+    const MIN_READY_PEERS: usize = 3;
+    const OVERLAY_NODES: usize = 16;
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Build ADNL node with throwaway key bound to `bind_addr`, join DHT through static
+    /// nodes from global config and wait till some of them answer
+    pub async fn connect(bind_addr: &str, static_nodes: &[Node]) -> Result<Self> {
+        if static_nodes.is_empty() {
+            fail!("No static DHT nodes to bootstrap from")
+        }
+        let (_, config) = AdnlNodeConfig::with_ip_address_and_key_type(
+            bind_addr,
+            KeyOption::KEY_ED25519,
+            vec![Self::KEY_TAG],
+        )?;
+        let adnl = AdnlNode::with_config(config).await?;
+        let options = DhtNodeOptions {
+            role: DhtRole::ClientOnly,
+            ..Default::default()
+        };
+        let handle = DhtNode::install(&adnl, Self::KEY_TAG, options).await?;
+        for node in static_nodes {
+            handle.dht().add_peer(node)?;
+        }
+        DhtNode::wait_ready(
+            handle.dht(),
+            static_nodes.len().min(Self::MIN_READY_PEERS),
+            Instant::now() + Self::BOOTSTRAP_TIMEOUT,
+        )
+        .await?;
+        Ok(Self { adnl, handle })
+    }
+
+    /// Underlying DHT node for operations not covered by the client
+    pub fn dht(&self) -> &Arc<DhtNode> {
+        self.handle.dht()
+    }
+
+    /// Find payload published with `publish` by owner with given key ID under given name
+    pub async fn fetch(&self, owner: &Arc<KeyId>, name: &str) -> Result<Option<Vec<u8>>> {
+        DhtNode::find_chunked(self.dht(), owner, name).await
+    }
+
+    /// Store payload under given name, signed by given key. The payload is split into
+    /// chunks, so it is read back by `fetch` or `DhtNode::find_chunked`
+    pub async fn publish(
+        &self,
+        key: &Arc<KeyOption>,
+        name: &str,
+        payload: &[u8],
+    ) -> Result<Vec<StoreReport>> {
+        DhtNode::store_chunked(self.dht(), key, name, payload).await
+    }
+
+    /// Find address of node with given ADNL key ID
+    pub async fn resolve(&self, key_id: &Arc<KeyId>) -> Result<(IpAddress, KeyOption)> {
+        DhtNode::find_address(self.dht(), key_id).await
+    }
+
+    /// Find nodes of overlay with given ID along with their addresses
+    pub async fn resolve_overlay(
+        &self,
+        overlay_id: &Arc<OverlayShortId>,
+    ) -> Result<Vec<ResolvedOverlayNode>> {
+        DhtNode::find_overlay_nodes(self.dht(), overlay_id, &mut None, Self::OVERLAY_NODES).await
+    }
+
+    /// Shut DHT and ADNL nodes down
+    pub async fn shutdown(self) -> Result<()> {
+        let ret = self.handle.shutdown(Self::SHUTDOWN_TIMEOUT).await;
+        self.adnl.stop().await;
+        ret
+    }
+}
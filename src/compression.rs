@@ -0,0 +1,80 @@
+use ton_types::{fail, Result};
+
+use crate::DhtError;
+
+/// Marker prepended to compressed bytes, it is never a valid TL constructor of stored objects
+const MARKER: [u8; 4] = [0xff, b'l', b'z', b'4'];
+/// Smaller data is not worth compressing
+const MIN_SIZE: usize = 256;
+
+/// Compress data with framing marker, returns None when compression doesn't pay off
+pub(crate) fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < MIN_SIZE {
+        return None;
+    }
+    let compressed = lz4_flex::compress_prepend_size(data);
+    if compressed.len() + MARKER.len() >= data.len() {
+        return None;
+    }
+    let mut ret = Vec::with_capacity(compressed.len() + MARKER.len());
+    ret.extend_from_slice(&MARKER);
+    ret.extend_from_slice(&compressed);
+    Some(ret)
+}
+
+/// Decompress data framed by `compress`. Decompressed size is checked against
+/// `max_size` before anything is allocated
+pub(crate) fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    if !data.starts_with(&MARKER) || (data.len() < MARKER.len() + 4) {
+        fail!(DhtError::Malformed("Value is not compressed".to_string()))
+    }
+    let size = u32::from_le_bytes(*arrayref::array_ref!(data, MARKER.len(), 4)) as usize;
+    if size > max_size {
+        fail!(DhtError::Malformed(format!(
+            "Too big compressed value: {} bytes, max {}",
+            size, max_size
+        )))
+    }
+    match lz4_flex::decompress_size_prepended(&data[MARKER.len()..]) {
+        Ok(ret) => Ok(ret),
+        Err(e) => fail!(DhtError::Malformed(format!("Bad compressed value: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = b"overlay node ".repeat(64);
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn small_data_is_not_compressed() {
+        assert!(compress(&[0u8; MIN_SIZE - 1]).is_none());
+    }
+
+    #[test]
+    fn huge_size_prefix_is_refused() {
+        let mut data = MARKER.to_vec();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0u8; 16]);
+        assert!(decompress(&data, 64 * 1024).is_err());
+    }
+
+    #[test]
+    fn size_over_limit_is_refused() {
+        let data = b"overlay node ".repeat(64);
+        let compressed = compress(&data).unwrap();
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+    }
+
+    #[test]
+    fn unmarked_data_is_refused() {
+        assert!(decompress(b"plain value bytes", 64 * 1024).is_err());
+    }
+}
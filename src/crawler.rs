@@ -0,0 +1,168 @@
+//! Crawler mapping DHT network: starting from known peers it asks every discovered
+//! node for peers close to random targets until no new nodes appear. Crawled nodes
+//! are registered in ADNL to be queried, but are kept apart from routing table
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use adnl::common::{KeyId, KeyOption, Query, Wait};
+use adnl::node::parse_address_list;
+use rand::Rng;
+use ton_api::ton::adnl::addresslist::AddressList;
+use ton_api::ton::dht::node::Node;
+use ton_api::ton::dht::Nodes as NodesBoxed;
+use ton_api::ton::{self, rpc, TLObject};
+use ton_types::Result;
+
+use crate::{DhtNode, TARGET};
+
+/// Node found by crawler
+#[derive(Clone, Debug)]
+pub struct CrawledNode {
+    /// Node ADNL key ID
+    pub key_id: Arc<KeyId>,
+    /// Latest seen address list
+    pub addr_list: AddressList,
+    /// Latest seen node version
+    pub version: i32,
+    /// FindNode round trip time, None if node has not answered
+    pub rtt: Option<Duration>,
+}
+
+/// Crawler options
+#[derive(Clone, Debug)]
+pub struct CrawlerOptions {
+    /// Number of nodes asked for peers in each FindNode query
+    pub k: i32,
+    /// Max number of crawling rounds
+    pub max_rounds: u32,
+    /// Number of queries in flight
+    pub parallelism: usize,
+}
+
+impl Default for CrawlerOptions {
+    fn default() -> Self {
+        Self {
+            k: 10,
+            max_rounds: 32,
+            parallelism: 16,
+        }
+    }
+}
+
+/// Crawl DHT network, returning deduplicated list of found nodes
+pub async fn crawl(dht: &Arc<DhtNode>, options: &CrawlerOptions) -> Result<Vec<CrawledNode>> {
+    let mut crawled = HashMap::new();
+    let mut frontier = Vec::new();
+    for node in dht.get_known_nodes(DhtNode::MAX_PEERS as usize)? {
+        discover(dht, &mut crawled, &mut frontier, node)?;
+    }
+    let mut rounds = 0;
+    while !frontier.is_empty() && (rounds < options.max_rounds) {
+        rounds += 1;
+        log::debug!(
+            target: TARGET,
+            "Crawling round {}: {} nodes to ask, {} nodes found",
+            rounds,
+            frontier.len(),
+            crawled.len()
+        );
+        let mut pending = std::mem::replace(&mut frontier, Vec::new());
+        let mut found = Vec::new();
+        let (wait, mut queue_reader) = Wait::new();
+        loop {
+            while let Some(peer) = pending.pop() {
+                let dht_cloned = dht.clone();
+                let query = TLObject::new(rpc::dht::FindNode {
+                    key: ton::int256(rand::thread_rng().gen()),
+                    k: options.k,
+                });
+                let wait = wait.clone();
+                let reqs = wait.request();
+                dht.spawn(async move {
+                    let start = Instant::now();
+                    let ret = match dht_cloned.query(&peer, &query).await {
+                        Ok(Some(answer)) => match Query::parse::<TLObject, NodesBoxed>(answer, &query) {
+                            Ok(answer) => Some((peer, start.elapsed(), answer.only().nodes.0)),
+                            Err(e) => {
+                                log::debug!(target: TARGET, "Bad FindNode answer from {}: {}", peer, e);
+                                None
+                            }
+                        },
+                        Ok(None) => None,
+                        Err(e) => {
+                            log::debug!(target: TARGET, "FindNode to {} error: {}", peer, e);
+                            None
+                        }
+                    };
+                    wait.respond(ret)
+                });
+                if reqs >= options.parallelism {
+                    break;
+                }
+            }
+            match wait.wait(&mut queue_reader, false).await {
+                Some(Some((peer, rtt, mut nodes))) => {
+                    if let Some(crawled) = crawled.get_mut(&peer) {
+                        crawled.rtt = Some(rtt)
+                    }
+                    found.append(&mut nodes)
+                }
+                Some(None) => (),
+                None => break,
+            }
+        }
+        for node in found {
+            discover(dht, &mut crawled, &mut frontier, node)?;
+        }
+    }
+    log::info!(
+        target: TARGET,
+        "Crawling finished in {} rounds: {} nodes found",
+        rounds,
+        crawled.len()
+    );
+    Ok(crawled.into_iter().map(|(_, node)| node).collect())
+}
+
+fn discover(
+    dht: &DhtNode,
+    crawled: &mut HashMap<Arc<KeyId>, CrawledNode>,
+    frontier: &mut Vec<Arc<KeyId>>,
+    node: Node,
+) -> Result<()> {
+    let key = Arc::new(KeyOption::from_tl_public_key(&node.id)?);
+    let key_id = key.id().clone();
+    if let Some(known) = crawled.get_mut(&key_id) {
+        if known.version < node.version {
+            known.addr_list = node.addr_list;
+            known.version = node.version;
+        }
+        return Ok(());
+    }
+    if dht.check_peer(&key, &node).is_some() {
+        // Node is banned, not allowed or forged
+        return Ok(());
+    }
+    let registered = dht.adnl.add_peer(
+        dht.node_key.id(),
+        &parse_address_list(&node.addr_list)?,
+        &key,
+    )?;
+    if registered.is_some() {
+        frontier.push(key_id.clone())
+    }
+    crawled.insert(
+        key_id.clone(),
+        CrawledNode {
+            key_id,
+            addr_list: node.addr_list,
+            version: node.version,
+            rtt: None,
+        },
+    );
+    Ok(())
+}
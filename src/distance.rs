@@ -0,0 +1,117 @@
+//! XOR metric of DHT key space
+
+/// Bitwise XOR of two keys
+pub fn xor_metric(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let mut ret = [0u8; 32];
+    for (ret, (a, b)) in ret.iter_mut().zip(a.iter().zip(b.iter())) {
+        *ret = a ^ b
+    }
+    ret
+}
+
+/// Number of leading common bits of two keys, i.e. index of routing bucket
+/// of key `b` in routing table of key `a`. Equal keys have distance 255
+pub fn distance(a: &[u8; 32], b: &[u8; 32]) -> u8 {
+    let mut dist = 0u32;
+    for (a, b) in a.iter().zip(b.iter()) {
+        match a ^ b {
+            0 => dist += 8,
+            x => {
+                dist += x.leading_zeros();
+                break;
+            }
+        }
+    }
+    dist.min(u8::MAX as u32) as u8
+}
+
+/// Indices of bits two keys differ in, most significant first. These are indices
+/// of routing buckets of key `a` holding keys closer to key `b` than `a` itself
+pub fn differing_bits(a: &[u8; 32], b: &[u8; 32]) -> impl Iterator<Item = u8> {
+    let xor = xor_metric(a, b);
+    (0..256usize)
+        .filter(move |bit| (xor[bit / 8] & (0x80 >> (bit % 8))) != 0)
+        .map(|bit| bit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    const ROUNDS: usize = 1000;
+
+    /// Random key pair, sharing random number of leading bits to cover all buckets
+    fn random_pair(rng: &mut impl Rng) -> ([u8; 32], [u8; 32]) {
+        let a: [u8; 32] = rng.gen();
+        let mut b: [u8; 32] = rng.gen();
+        let common = rng.gen_range(0, 257);
+        for bit in 0..common.min(256) {
+            let mask = 0x80 >> (bit % 8);
+            b[bit / 8] = (b[bit / 8] & !mask) | (a[bit / 8] & mask)
+        }
+        (a, b)
+    }
+
+    fn leading_zeros(key: &[u8; 32]) -> u32 {
+        let mut ret = 0;
+        for byte in key.iter() {
+            ret += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn metric_is_symmetric_and_zero_on_itself() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..ROUNDS {
+            let (a, b) = random_pair(&mut rng);
+            assert_eq!(xor_metric(&a, &b), xor_metric(&b, &a));
+            assert_eq!(xor_metric(&a, &a), [0; 32]);
+            assert_eq!(distance(&a, &b), distance(&b, &a));
+            assert_eq!(distance(&a, &a), u8::MAX);
+        }
+    }
+
+    #[test]
+    fn metric_composes_by_xor() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..ROUNDS {
+            let (a, b) = random_pair(&mut rng);
+            let c: [u8; 32] = rng.gen();
+            let ab = xor_metric(&a, &b);
+            let bc = xor_metric(&b, &c);
+            assert_eq!(xor_metric(&ab, &bc), xor_metric(&a, &c));
+        }
+    }
+
+    #[test]
+    fn distance_is_number_of_common_leading_bits() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..ROUNDS {
+            let (a, b) = random_pair(&mut rng);
+            let zeros = leading_zeros(&xor_metric(&a, &b));
+            assert_eq!(distance(&a, &b) as u32, zeros.min(255));
+        }
+    }
+
+    #[test]
+    fn differing_bits_match_metric() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..ROUNDS {
+            let (a, b) = random_pair(&mut rng);
+            let xor = xor_metric(&a, &b);
+            let bits: Vec<u8> = differing_bits(&a, &b).collect();
+            let ones: u32 = xor.iter().map(|byte| byte.count_ones()).sum();
+            assert_eq!(bits.len() as u32, ones);
+            assert!(bits.windows(2).all(|pair| pair[0] < pair[1]));
+            match bits.first() {
+                Some(first) => assert_eq!(*first, distance(&a, &b)),
+                None => assert_eq!(a, b),
+            }
+        }
+    }
+}
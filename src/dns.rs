@@ -0,0 +1,221 @@
+use std::{
+    net::{SocketAddr, SocketAddrV4, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+
+use adnl::node::IpAddress;
+use ton_api::ton::dht::node::Node;
+use ton_types::{error, fail, Result};
+
+use crate::{build_dht_node_info_from_addrs, AddPeerResult, DhtError, DhtNode, TARGET};
+
+/// Resolver of host names in DHT node addresses
+#[async_trait::async_trait]
+pub trait HostResolver: Send + Sync {
+    /// Resolve host name into socket addresses with given port
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>>;
+}
+
+/// Resolver using system DNS configuration, lookups run on blocking thread pool
+pub struct SystemResolver;
+
+#[async_trait::async_trait]
+impl HostResolver for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        let host = host.to_string();
+        let resolved =
+            tokio::task::spawn_blocking(move || (host.as_str(), port).to_socket_addrs()).await?;
+        Ok(resolved?.collect())
+    }
+}
+
+const RESOLVE_ATTEMPTS: usize = 3;
+const RESOLVE_PAUSE: Duration = Duration::from_secs(1);
+
+/// Build static DHT node record from "host:port" address, where host is either IPv4
+/// literal or host name resolved with given resolver, retrying on failure. All IPv4
+/// addresses of the host make up node address list in ascending order, IPv6 ones are
+/// skipped as ADNL UDP addresses are IPv4 only. Signature must cover the resolved
+/// address list, so DNS records have to point to addresses the node record was
+/// signed with
+pub async fn build_dht_node_info_with_resolver(
+    addr: &str,
+    key: &str,
+    signature: &str,
+    resolver: &dyn HostResolver,
+) -> Result<Node> {
+    if let Ok(ip) = IpAddress::from_string(addr) {
+        return build_dht_node_info_from_addrs(vec![ip], key, signature);
+    }
+    let (host, port) = split_host_port(addr)?;
+    let mut attempt = 0;
+    let resolved = loop {
+        attempt += 1;
+        match resolver.resolve(host, port).await {
+            Ok(resolved) if !resolved.is_empty() => break resolved,
+            Ok(_) if attempt >= RESOLVE_ATTEMPTS => {
+                fail!(DhtError::NotFound(format!("No addresses for {}", host)))
+            }
+            Err(e) if attempt >= RESOLVE_ATTEMPTS => fail!(e),
+            Ok(_) => log::debug!(target: TARGET, "No addresses for {}, retry", host),
+            Err(e) => log::debug!(target: TARGET, "Cannot resolve {}: {}, retry", host, e),
+        }
+        tokio::time::sleep(RESOLVE_PAUSE).await
+    };
+    build_dht_node_info_from_addrs(ipv4_addresses(host, resolved)?, key, signature)
+}
+
+/// Blocking variant of `build_dht_node_info_with_resolver` with single system lookup
+pub(crate) fn build_dht_node_info_blocking(addr: &str, key: &str, signature: &str) -> Result<Node> {
+    if let Ok(ip) = IpAddress::from_string(addr) {
+        return build_dht_node_info_from_addrs(vec![ip], key, signature);
+    }
+    let (host, port) = split_host_port(addr)?;
+    let resolved = (host, port).to_socket_addrs()?.collect();
+    build_dht_node_info_from_addrs(ipv4_addresses(host, resolved)?, key, signature)
+}
+
+impl DhtNode {
+    /// Host names of static peers are resolved again with this period
+    const TIMEOUT_RESOLVE: u64 = 600; // Seconds
+
+    /// Add static DHT peer given by "host:port" address. The host is resolved again
+    /// periodically till the node is stopped, and the peer is added back whenever
+    /// it is no longer known, e.g. after eviction or when DNS was down at start
+    pub async fn add_peer_by_host(
+        dht: &Arc<Self>,
+        addr: &str,
+        key: &str,
+        signature: &str,
+        resolver: Arc<dyn HostResolver>,
+    ) -> Result<AddPeerResult> {
+        let peer = build_dht_node_info_with_resolver(addr, key, signature, &*resolver).await?;
+        let ret = dht.add_peer(&peer)?;
+        let weak_dht = Arc::downgrade(dht);
+        let (addr, key, signature) = (addr.to_string(), key.to_string(), signature.to_string());
+        let tasks = dht.tasks.clone();
+        dht.spawn(async move {
+            while tasks
+                .sleep(Duration::from_secs(Self::TIMEOUT_RESOLVE))
+                .await
+            {
+                let resolved =
+                    build_dht_node_info_with_resolver(&addr, &key, &signature, &*resolver).await;
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                match resolved.and_then(|peer| dht.add_peer(&peer)) {
+                    Ok(AddPeerResult::Added(_)) => {
+                        log::info!(target: TARGET, "Static DHT peer {} is added back", addr)
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        log::warn!(target: TARGET, "Cannot re-resolve DHT peer {}: {}", addr, e)
+                    }
+                }
+            }
+        });
+        Ok(ret)
+    }
+}
+
+/// Unique IPv4 addresses out of resolved ones, sorted so the address list is the
+/// same regardless of DNS answer order
+fn ipv4_addresses(host: &str, resolved: Vec<SocketAddr>) -> Result<Vec<IpAddress>> {
+    let mut addrs: Vec<SocketAddrV4> = resolved
+        .into_iter()
+        .filter_map(|addr| match addr {
+            SocketAddr::V4(addr) => Some(addr),
+            SocketAddr::V6(_) => None,
+        })
+        .collect();
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        fail!(DhtError::NotFound(format!(
+            "No IPv4 addresses for {}",
+            host
+        )))
+    }
+    addrs
+        .into_iter()
+        .map(|addr| IpAddress::from_string(&addr.to_string()))
+        .collect()
+}
+
+fn split_host_port(addr: &str) -> Result<(&str, u16)> {
+    let pos = addr
+        .rfind(':')
+        .ok_or_else(|| error!(DhtError::Malformed(format!("No port in address {}", addr))))?;
+    let port = addr[pos + 1..]
+        .parse()
+        .map_err(|_| error!(DhtError::Malformed(format!("Bad port in address {}", addr))))?;
+    Ok((&addr[..pos], port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    struct FakeResolver {
+        answers: Vec<Vec<SocketAddr>>,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl HostResolver for FakeResolver {
+        async fn resolve(&self, _host: &str, _port: u16) -> Result<Vec<SocketAddr>> {
+            let call = self.calls.fetch_add(1, Ordering::Relaxed);
+            let answer = &self.answers[call.min(self.answers.len() - 1)];
+            Ok(answer.clone())
+        }
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn addresses_are_sorted_and_unique() {
+        let resolver = FakeResolver {
+            answers: vec![vec![
+                addr("10.0.0.2:3000"),
+                addr("[::1]:3000"),
+                addr("10.0.0.1:3000"),
+                addr("10.0.0.2:3000"),
+            ]],
+            calls: AtomicUsize::new(0),
+        };
+        let a = build_dht_node_info_with_resolver("seed:3000", KEY, "", &resolver)
+            .await
+            .unwrap();
+        let resolver = FakeResolver {
+            answers: vec![vec![addr("10.0.0.1:3000"), addr("10.0.0.2:3000")]],
+            calls: AtomicUsize::new(0),
+        };
+        let b = build_dht_node_info_with_resolver("seed:3000", KEY, "", &resolver)
+            .await
+            .unwrap();
+        assert_eq!(a.addr_list.addrs.len(), 2);
+        assert_eq!(a.addr_list.addrs, b.addr_list.addrs);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn empty_answer_is_retried() {
+        let resolver = FakeResolver {
+            answers: vec![vec![], vec![addr("10.0.0.1:3000")]],
+            calls: AtomicUsize::new(0),
+        };
+        let node = build_dht_node_info_with_resolver("seed:3000", KEY, "", &resolver)
+            .await
+            .unwrap();
+        assert_eq!(node.addr_list.addrs.len(), 1);
+        assert_eq!(resolver.calls.load(Ordering::Relaxed), 2);
+    }
+}
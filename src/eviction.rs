@@ -0,0 +1,83 @@
+use std::{cmp::Ordering, collections::HashSet, sync::Arc};
+
+use adnl::common::KeyId;
+use ton_types::Result;
+
+use crate::{DhtNode, DhtNodeOptions, PeerStats, TARGET};
+
+/// Strategy of evicting known peers when their number exceeds `max_known_peers`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerEviction {
+    /// Earliest known peers are evicted first
+    Fifo,
+    /// Peers which did not answer for longest time are evicted first
+    LeastRecentlyUseful,
+    /// Peers with lowest share of answered queries are evicted first
+    ScoreWeighted,
+}
+
+impl DhtNode {
+    /// Known peers are evicted down to this percentage of capacity at once, so
+    /// eviction does not run on every new peer
+    const EVICTION_WATERMARK_PERCENT: usize = 90;
+
+    /// Limit of ADNL address cache of known peers. It is doubled capacity, so the cache
+    /// never drops peers by itself: all strategies are applied by `evict_known_peers`,
+    /// which keeps routing table, peer statistics and IP index in sync
+    pub(crate) fn known_peers_limit(options: &DhtNodeOptions) -> u32 {
+        let limit = options.max_known_peers.max(1).saturating_mul(2);
+        limit.min(u32::MAX as usize) as u32
+    }
+
+    /// Evict known peers according to strategy in options if their number exceeds
+    /// capacity, except given just added peer and priority peers. Returns number
+    /// of evicted peers
+    pub(crate) fn evict_known_peers(&self, added: &Arc<KeyId>) -> Result<usize> {
+        let capacity = self.options.max_known_peers.max(1);
+        if self.known_peers().count() as usize <= capacity {
+            return Ok(0);
+        }
+        let snapshot = self.known_peers_snapshot();
+        let count = snapshot.len();
+        let mut candidates = Vec::new();
+        for peer in snapshot {
+            if (&peer != added) && !self.is_priority_peer(&peer) {
+                let stats = self
+                    .peer_stats
+                    .get(&peer)
+                    .map(|stats| stats.value().clone())
+                    .unwrap_or_default();
+                candidates.push((peer, stats));
+            }
+        }
+        match self.options.peer_eviction {
+            // Snapshot lists peers in order they became known
+            PeerEviction::Fifo => (),
+            PeerEviction::LeastRecentlyUseful => {
+                candidates.sort_by_key(|(_, stats)| (stats.last_answered_at, stats.last_seen_at))
+            }
+            PeerEviction::ScoreWeighted => candidates.sort_by(|(_, a), (_, b)| {
+                eviction_score(a)
+                    .partial_cmp(&eviction_score(b))
+                    .unwrap_or(Ordering::Equal)
+            }),
+        }
+        let target = capacity * Self::EVICTION_WATERMARK_PERCENT / 100;
+        let dead: HashSet<Arc<KeyId>> = candidates
+            .into_iter()
+            .take(count.saturating_sub(target))
+            .map(|(peer, _)| peer)
+            .collect();
+        if dead.is_empty() {
+            return Ok(0);
+        }
+        self.remove_known_peers(&dead)?;
+        log::debug!(target: TARGET, "Evicted {} known DHT peers", dead.len());
+        Ok(dead.len())
+    }
+}
+
+/// Smoothed share of answered queries, unknown peers score 0.5
+fn eviction_score(stats: &PeerStats) -> f64 {
+    (stats.answered as f64 + 1.0) / ((stats.answered + stats.failed) as f64 + 2.0)
+}
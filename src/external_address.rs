@@ -0,0 +1,138 @@
+use std::{collections::HashMap, sync::Arc};
+
+use adnl::common::{KeyId, KeyOption, Query, Wait};
+use adnl::node::{parse_address_list, IpAddress};
+use rand::seq::SliceRandom;
+use ton_api::ton::dht::Nodes as NodesBoxed;
+use ton_api::ton::{self, rpc, TLObject};
+use ton_types::{fail, Result};
+
+use crate::{DhtError, DhtNode, TARGET};
+
+impl DhtNode {
+    const MIN_ADDRESS_VOTES: usize = 2;
+
+    /// Ask up to `peers` random known peers which address they hold for this node,
+    /// returns address reported by majority of peers which know the node, at least
+    /// two of them. DHT queries carry no source address observed by peer, so peers
+    /// report address from signed node record they received, i.e. the address the
+    /// network reaches the node by. Queries are sent without own node prefix, so
+    /// peers are not told the address being checked
+    pub async fn discover_external_address(
+        dht: &Arc<Self>,
+        peers: usize,
+    ) -> Result<Option<IpAddress>> {
+        if peers == 0 {
+            fail!("It is useless to discover address with zero peers")
+        }
+        let mut candidates: Vec<_> = dht
+            .known_peers_snapshot()
+            .into_iter()
+            .filter(|peer| !dht.is_banned(peer))
+            .collect();
+        if candidates.is_empty() {
+            fail!(DhtError::NoPeers)
+        }
+        candidates.shuffle(&mut rand::thread_rng());
+        let (wait, mut queue_reader) = Wait::new();
+        for peer in candidates.into_iter().take(peers) {
+            let dht_cloned = dht.clone();
+            let wait = wait.clone();
+            wait.request();
+            dht.spawn(async move {
+                let ip = match dht_cloned.query_own_address(&peer).await {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        log::debug!(target: TARGET, "Cannot get own address from {}: {}", peer, e);
+                        None
+                    }
+                };
+                wait.respond(Some(ip))
+            });
+        }
+        let mut reported = Vec::new();
+        loop {
+            match wait.wait(&mut queue_reader, false).await {
+                Some(Some(Some(ip))) => reported.push(ip),
+                Some(_) => (),
+                None => break,
+            }
+        }
+        Ok(Self::agreed_address(reported))
+    }
+
+    /// Address reported by majority of peers, at least `MIN_ADDRESS_VOTES` of them
+    fn agreed_address(reported: Vec<IpAddress>) -> Option<IpAddress> {
+        let answered = reported.len();
+        // Addresses are counted by their text form
+        let mut votes: HashMap<String, (IpAddress, usize)> = HashMap::new();
+        for ip in reported {
+            votes.entry(ip.to_string()).or_insert((ip, 0)).1 += 1
+        }
+        let ret = votes
+            .into_iter()
+            .map(|(_, vote)| vote)
+            .max_by_key(|(_, count)| *count)
+            .filter(|(_, count)| (*count >= Self::MIN_ADDRESS_VOTES) && (*count * 2 > answered));
+        log::debug!(
+            target: TARGET,
+            "External address agreed by {} of {} peers",
+            ret.as_ref().map(|(_, count)| *count).unwrap_or(0),
+            answered
+        );
+        ret.map(|(ip, _)| ip)
+    }
+
+    /// Address of this node in its record held by peer, None if peer does not know it
+    async fn query_own_address(&self, peer: &Arc<KeyId>) -> Result<Option<IpAddress>> {
+        let query = TLObject::new(rpc::dht::FindNode {
+            key: ton::int256(*self.node_key.id().data()),
+            k: self.options.answer_k_default as i32,
+        });
+        let answer: NodesBoxed = if let Some(answer) = self.query(peer, &query).await? {
+            Query::parse(answer, &query)?
+        } else {
+            return Ok(None);
+        };
+        for node in answer.only().nodes.0 {
+            if KeyOption::from_tl_public_key(&node.id)?.id() != self.node_key.id() {
+                continue;
+            }
+            self.verify_other_node(&node)?;
+            return Ok(Some(parse_address_list(&node.addr_list)?));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(addrs: &[&str]) -> Vec<IpAddress> {
+        addrs
+            .iter()
+            .map(|addr| IpAddress::from_string(addr).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn majority_address_is_agreed() {
+        let reported = ips(&["1.2.3.4:3000", "5.6.7.8:3000", "1.2.3.4:3000"]);
+        let agreed = DhtNode::agreed_address(reported).unwrap();
+        assert_eq!(agreed.to_string(), ips(&["1.2.3.4:3000"])[0].to_string());
+    }
+
+    #[test]
+    fn split_or_single_report_is_not_agreed() {
+        assert!(DhtNode::agreed_address(ips(&["1.2.3.4:3000"])).is_none());
+        let reported = ips(&[
+            "1.2.3.4:3000",
+            "1.2.3.4:3000",
+            "5.6.7.8:3000",
+            "9.9.9.9:3000",
+        ]);
+        assert!(DhtNode::agreed_address(reported).is_none());
+        assert!(DhtNode::agreed_address(Vec::new()).is_none());
+    }
+}
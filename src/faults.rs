@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use adnl::common::now;
+use rand::Rng;
+
+use crate::{DhtKeyId, DhtNode, TARGET};
+
+/// Faults injected into DHT node for chaos testing
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjection {
+    /// Probability of dropping outgoing query as if it timed out
+    pub drop_query: f64,
+    /// Delay of answers to incoming queries
+    pub answer_delay: Option<Duration>,
+    /// Probability of corrupting TTL of accepted stored value
+    pub corrupt_ttl: f64,
+}
+
+impl DhtNode {
+    /// Replace faults injected into the node
+    pub fn inject_faults(&self, faults: FaultInjection) {
+        match self.faults.write() {
+            Ok(mut old) => *old = faults,
+            Err(e) => *e.into_inner() = faults,
+        }
+    }
+
+    pub(crate) fn fault_answer_delay(&self) -> Option<Duration> {
+        self.faults().answer_delay
+    }
+
+    pub(crate) fn fault_corrupt_ttl(&self, key: &DhtKeyId) {
+        if !Self::fault_happens(self.faults().corrupt_ttl) {
+            return;
+        }
+        let ttl = now() + rand::thread_rng().gen_range(-Self::TIMEOUT_VALUE, Self::TIMEOUT_VALUE);
+        if let Some(value) = self.storage.write(key).get_mut(key) {
+            log::debug!(
+                target: TARGET,
+                "Injected fault: TTL of DHT value with key {} changed from {} to {}",
+                base64::encode(key),
+                value.ttl,
+                ttl
+            );
+            value.ttl = ttl
+        }
+    }
+
+    pub(crate) fn fault_drop_query(&self) -> bool {
+        Self::fault_happens(self.faults().drop_query)
+    }
+
+    fn fault_happens(probability: f64) -> bool {
+        (probability > 0.0) && rand::thread_rng().gen_bool(probability.min(1.0))
+    }
+
+    fn faults(&self) -> FaultInjection {
+        match self.faults.read() {
+            Ok(faults) => faults.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use ton_types::{fail, Result};
+
+use crate::{DhtError, TARGET};
+
+/// Size of header of on-disk file: magic followed by format version (u32, little endian)
+pub(crate) const HEADER_SIZE: usize = 8;
+
+/// Kind of on-disk file written by DHT node
+#[derive(Clone, Copy)]
+pub(crate) enum FileFormat {
+    PeerDb,
+    PeerSnapshot,
+    StoreLog,
+}
+
+impl FileFormat {
+    /// Files without header were written before versioning and are of version 0,
+    /// their records have the same layout as in version 1. Such files are migrated
+    /// only if their records are recognized, anything else is refused
+    const VERSION: u32 = 1;
+
+    /// Header of file in current format version
+    pub(crate) fn header(self) -> [u8; HEADER_SIZE] {
+        let mut ret = [0u8; HEADER_SIZE];
+        ret[..4].copy_from_slice(self.magic());
+        ret[4..].copy_from_slice(&Self::VERSION.to_le_bytes());
+        ret
+    }
+
+    /// Check header of file data, returns offset of records and whether the file
+    /// needs migration to current version. Data without header is taken for records
+    /// of version 0 only if `is_legacy` recognizes them. Unrecognized files and files
+    /// of newer versions are refused
+    pub(crate) fn read_header(
+        self,
+        data: &[u8],
+        path: &Path,
+        is_legacy: impl FnOnce(&[u8]) -> bool,
+    ) -> Result<(usize, bool)> {
+        if data.is_empty() {
+            return Ok((0, false));
+        }
+        if (data.len() < HEADER_SIZE) || (&data[..4] != self.magic()) {
+            if is_legacy(data) {
+                return Ok((0, true));
+            }
+            fail!(DhtError::UnsupportedFormat(format!(
+                "{} {} has no format header and is not recognized as version 0",
+                self.name(),
+                path.display()
+            )))
+        }
+        let version = u32::from_le_bytes(*arrayref::array_ref!(data, 4, 4));
+        if version > Self::VERSION {
+            fail!(DhtError::UnsupportedFormat(format!(
+                "{} {} has version {}, max supported is {}",
+                self.name(),
+                path.display(),
+                version,
+                Self::VERSION
+            )))
+        }
+        Ok((HEADER_SIZE, version < Self::VERSION))
+    }
+
+    /// Atomically rewrite file with records in current format version. Temporary
+    /// file name is the full file name with ".tmp" appended, so files differing
+    /// only in extension do not share it
+    pub(crate) fn write(self, path: &Path, records: &[u8]) -> Result<()> {
+        let mut tmp_path = OsString::from(path);
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&self.header())?;
+        tmp.write_all(records)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Convert file of old version with given records to current version
+    pub(crate) fn migrate(self, path: &Path, records: &[u8]) -> Result<()> {
+        log::info!(
+            target: TARGET,
+            "Migrate {} {} to format version {}",
+            self.name(),
+            path.display(),
+            Self::VERSION
+        );
+        self.write(path, records)
+    }
+
+    /// Check that data is sequence of records prefixed with their length (u32, little
+    /// endian), each accepted by `is_record`. Incomplete last record is tolerated
+    /// if `torn_tail` is set, as logs may be torn by crash
+    pub(crate) fn is_record_log(
+        data: &[u8],
+        torn_tail: bool,
+        is_record: impl Fn(&[u8]) -> bool,
+    ) -> bool {
+        let mut count = 0;
+        let mut pos = 0;
+        while pos + 4 <= data.len() {
+            let len = u32::from_le_bytes(*arrayref::array_ref!(data, pos, 4)) as usize;
+            if pos + 4 + len > data.len() {
+                break;
+            }
+            if !is_record(&data[pos + 4..pos + 4 + len]) {
+                return false;
+            }
+            count += 1;
+            pos += 4 + len;
+        }
+        (count > 0) && (torn_tail || (pos == data.len()))
+    }
+
+    fn magic(self) -> &'static [u8; 4] {
+        match self {
+            Self::PeerDb => b"DHTD",
+            Self::PeerSnapshot => b"DHTP",
+            Self::StoreLog => b"DHTW",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::PeerDb => "DHT peer database",
+            Self::PeerSnapshot => "DHT peers snapshot",
+            Self::StoreLog => "DHT store log",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_log(records: &[&[u8]]) -> Vec<u8> {
+        let mut ret = Vec::new();
+        for record in records {
+            ret.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            ret.extend_from_slice(record);
+        }
+        ret
+    }
+
+    #[test]
+    fn recognized_legacy_file_is_migrated() {
+        let path = Path::new("legacy");
+        let data = record_log(&[b"rec1", b"rec2"]);
+        let is_legacy = |data: &[u8]| {
+            FileFormat::is_record_log(data, false, |record| record.starts_with(b"rec"))
+        };
+        assert_eq!(
+            FileFormat::StoreLog
+                .read_header(&data, path, is_legacy)
+                .unwrap(),
+            (0, true)
+        );
+        assert_eq!(
+            FileFormat::StoreLog
+                .read_header(&[], path, |_| false)
+                .unwrap(),
+            (0, false)
+        );
+    }
+
+    #[test]
+    fn foreign_file_is_refused() {
+        let path = Path::new("foreign");
+        let data = b"#!/bin/sh\necho hello\n".to_vec();
+        let is_legacy = |data: &[u8]| FileFormat::is_record_log(data, true, |_| true);
+        assert!(FileFormat::StoreLog
+            .read_header(&data, path, is_legacy)
+            .is_err());
+        let data = record_log(&[b"rec1", b"other"]);
+        let is_legacy = |data: &[u8]| {
+            FileFormat::is_record_log(data, false, |record| record.starts_with(b"rec"))
+        };
+        assert!(FileFormat::StoreLog
+            .read_header(&data, path, is_legacy)
+            .is_err());
+    }
+
+    #[test]
+    fn torn_tail_is_tolerated_for_logs_only() {
+        let mut data = record_log(&[b"rec1"]);
+        data.extend_from_slice(&100u32.to_le_bytes());
+        assert!(FileFormat::is_record_log(&data, true, |_| true));
+        assert!(!FileFormat::is_record_log(&data, false, |_| true));
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let mut data = FileFormat::PeerDb.header().to_vec();
+        data[4..].copy_from_slice(&(FileFormat::VERSION + 1).to_le_bytes());
+        assert!(FileFormat::PeerDb
+            .read_header(&data, Path::new("newer"), |_| true)
+            .is_err());
+        let data = FileFormat::PeerDb.header();
+        assert_eq!(
+            FileFormat::PeerDb
+                .read_header(&data, Path::new("current"), |_| true)
+                .unwrap(),
+            (HEADER_SIZE, false)
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Entry points feeding raw TL bytes into handlers of untrusted input,
+//! so they can be fuzzed without live network
+
+use adnl::common::{deserialize, AdnlPeers, KeyId, Subscriber};
+use adnl::node::parse_address_list as parse_adnl_address_list;
+use ton_api::ton::adnl::AddressList as AddressListBoxed;
+use ton_api::ton::rpc;
+use ton_types::{error, Result};
+
+use crate::{DhtError, DhtNode};
+
+/// Parse address list as received from peers
+pub fn parse_address_list(data: &[u8]) -> Result<()> {
+    let addr_list = deserialize(data)?
+        .downcast::<AddressListBoxed>()
+        .map_err(|object| {
+            error!(DhtError::Malformed(format!(
+                "Not an address list: {:?}",
+                object
+            )))
+        })?
+        .only();
+    parse_adnl_address_list(&addr_list)?;
+    Ok(())
+}
+
+/// Process Store query as received from peers
+pub fn process_store(dht: &DhtNode, data: &[u8]) -> Result<()> {
+    let query = deserialize(data)?
+        .downcast::<rpc::dht::Store>()
+        .map_err(|object| {
+            error!(DhtError::Malformed(format!(
+                "Not a store query: {:?}",
+                object
+            )))
+        })?;
+    dht.process_store(query, &KeyId::from_data([0u8; 32]))?;
+    Ok(())
+}
+
+/// Consume arbitrary query as received from peer with given key ID
+pub async fn try_consume_query(dht: &DhtNode, data: &[u8], peer: [u8; 32]) -> Result<()> {
+    let object = deserialize(data)?;
+    let peers = AdnlPeers::with_keys(dht.node_key.id().clone(), KeyId::from_data(peer));
+    dht.try_consume_query(object, &peers).await?;
+    Ok(())
+}
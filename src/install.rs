@@ -0,0 +1,121 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use adnl::common::Subscriber;
+use adnl::node::AdnlNode;
+use ton_types::{fail, Result};
+
+use crate::{AddressWarmup, DhtNode, DhtNodeOptions, PriorityPing, TARGET};
+
+/// DHT node installed into ADNL along with its maintenance tasks
+pub struct DhtHandle {
+    address_refresh: AddressWarmup,
+    dht: Arc<DhtNode>,
+    priority_ping: PriorityPing,
+    stopped: Arc<AtomicBool>,
+}
+
+impl DhtHandle {
+    /// Installed DHT node
+    pub fn dht(&self) -> &Arc<DhtNode> {
+        &self.dht
+    }
+
+    /// Stop maintenance tasks and shut the node down
+    pub async fn shutdown(self, timeout: Duration) -> Result<()> {
+        self.address_refresh.cancel();
+        self.priority_ping.cancel();
+        self.stopped.store(true, Ordering::Relaxed);
+        self.dht.shutdown(timeout).await
+    }
+}
+
+impl DhtNode {
+    const ADDRESS_REFRESH_BATCH: usize = 16;
+    const ADDRESS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+    const ADDRESS_REFRESH_MISSES: u32 = 5;
+    const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(600);
+    const PRIORITY_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Construct DHT node, start not yet started ADNL node with the DHT node as its
+    /// subscriber and start maintenance: address refresh, priority peers ping, peers GC
+    /// and peer database saving. Embedders having other ADNL subscribers construct
+    /// the node with `with_options` and start ADNL themselves
+    pub async fn install(
+        adnl: &Arc<AdnlNode>,
+        key_tag: usize,
+        options: DhtNodeOptions,
+    ) -> Result<DhtHandle> {
+        let mut handles = Self::install_identities(adnl, vec![(key_tag, options)]).await?;
+        match handles.pop() {
+            Some(handle) => Ok(handle),
+            None => fail!("INTERNAL ERROR: DHT node is not installed"),
+        }
+    }
+
+    /// Like `install`, but with several DHT identities over one ADNL node, each with
+    /// its own key tag and options. Each node serves queries to its own key only.
+    /// File paths in options of different identities must differ
+    pub async fn install_identities(
+        adnl: &Arc<AdnlNode>,
+        identities: Vec<(usize, DhtNodeOptions)>,
+    ) -> Result<Vec<DhtHandle>> {
+        let mut nodes = Vec::with_capacity(identities.len());
+        for (key_tag, options) in identities {
+            nodes.push(Self::with_options(adnl.clone(), key_tag, options)?)
+        }
+        let subscribers = nodes
+            .iter()
+            .map(|dht| dht.clone() as Arc<dyn Subscriber>)
+            .collect();
+        AdnlNode::start(adnl, subscribers).await?;
+        Ok(nodes.into_iter().map(Self::start_maintenance).collect())
+    }
+
+    fn start_maintenance(dht: Arc<Self>) -> DhtHandle {
+        let address_refresh = Self::refresh_addresses(
+            &dht,
+            Self::ADDRESS_REFRESH_INTERVAL,
+            Self::ADDRESS_REFRESH_BATCH,
+            Self::ADDRESS_REFRESH_MISSES,
+        );
+        let priority_ping = Self::ping_priority_peers(&dht, Self::PRIORITY_PING_INTERVAL);
+        let stopped = Arc::new(AtomicBool::new(false));
+        Self::spawn_maintenance(&dht, stopped.clone());
+        DhtHandle {
+            address_refresh,
+            dht,
+            priority_ping,
+            stopped,
+        }
+    }
+
+    fn spawn_maintenance(dht: &Arc<Self>, stopped: Arc<AtomicBool>) {
+        let weak_dht = Arc::downgrade(dht);
+        let tasks = dht.tasks.clone();
+        dht.spawn(async move {
+            loop {
+                let running = tasks.sleep(Self::MAINTENANCE_INTERVAL).await;
+                if !running || stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                if let Err(e) = dht.gc_peers() {
+                    log::warn!(target: TARGET, "Cannot remove dead DHT peers: {}", e)
+                }
+                if let Err(e) = dht.save_peer_db() {
+                    log::warn!(target: TARGET, "Cannot save DHT peer database: {}", e)
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashSet, net::Ipv4Addr, sync::Arc};
+
+use adnl::common::{deserialize, now, KeyId, KeyOption};
+use dashmap::DashMap;
+use ton_api::ton::dht::value::Value as DhtValue;
+use ton_api::ton::{self, adnl::addresslist::AddressList, adnl::AddressList as AddressListBoxed};
+
+use crate::{DhtNode, TARGET};
+
+/// Index from observed IP addresses to DHT key IDs advertising them
+pub(crate) struct IpIndex {
+    banned: DashMap<Ipv4Addr, i32>,
+    keys: DashMap<Ipv4Addr, HashSet<Arc<KeyId>>>,
+}
+
+impl IpIndex {
+    const MAX_IPS: usize = 65536;
+    const MAX_KEYS_PER_IP: usize = 256;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            banned: DashMap::new(),
+            keys: DashMap::new(),
+        }
+    }
+
+    /// Check if any address of list is banned
+    pub(crate) fn is_banned(&self, addr_list: &AddressList) -> bool {
+        !self.banned.is_empty() && ips(addr_list).any(|ip| self.banned.contains_key(&ip))
+    }
+
+    pub(crate) fn insert(&self, key_id: &Arc<KeyId>, addr_list: &AddressList) {
+        for ip in ips(addr_list) {
+            if let Some(mut keys) = self.keys.get_mut(&ip) {
+                if keys.len() < Self::MAX_KEYS_PER_IP {
+                    keys.insert(key_id.clone());
+                }
+                continue;
+            }
+            if self.keys.len() < Self::MAX_IPS {
+                self.keys
+                    .entry(ip)
+                    .or_insert_with(HashSet::new)
+                    .insert(key_id.clone());
+            }
+        }
+    }
+
+    /// Forget given keys under all addresses
+    pub(crate) fn remove(&self, key_ids: &HashSet<Arc<KeyId>>) {
+        self.keys.retain(|_, keys| {
+            keys.retain(|key_id| !key_ids.contains(key_id));
+            !keys.is_empty()
+        })
+    }
+}
+
+impl DhtNode {
+    /// Ban IP address: all DHT keys seen advertising it are banned, and peers
+    /// advertising it are not added anymore. Returns number of banned keys
+    pub fn ban_ip(&self, ip: &Ipv4Addr) -> usize {
+        log::info!(target: TARGET, "Ban DHT peers with IP {}", ip);
+        self.ip_index.banned.insert(*ip, now());
+        let keys = self.keys_by_ip(ip);
+        for key_id in keys.iter() {
+            self.ban_peer(key_id)
+        }
+        keys.len()
+    }
+
+    /// DHT key IDs seen advertising given IP address in peer records or stored addresses
+    pub fn keys_by_ip(&self, ip: &Ipv4Addr) -> Vec<Arc<KeyId>> {
+        self.ip_index
+            .keys
+            .get(ip)
+            .map(|keys| keys.value().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Unban IP address, keys banned along with it stay banned
+    pub fn unban_ip(&self, ip: &Ipv4Addr) {
+        self.ip_index.banned.remove(ip);
+    }
+
+    /// Owner key ID and address list of stored address value, if it is one
+    pub(crate) fn advertised_address(value: &DhtValue) -> Option<(Arc<KeyId>, AddressList)> {
+        if &value.key.key.name.0[..] != b"address" {
+            return None;
+        }
+        let owner = KeyOption::from_tl_public_key(&value.key.id).ok()?;
+        let addr_list = deserialize(&value.value.0)
+            .ok()?
+            .downcast::<AddressListBoxed>()
+            .ok()?
+            .only();
+        Some((owner.id().clone(), addr_list))
+    }
+}
+
+fn ips(addr_list: &AddressList) -> impl Iterator<Item = Ipv4Addr> + '_ {
+    addr_list.addrs.iter().filter_map(|addr| match addr {
+        ton::adnl::Address::Adnl_Address_Udp(udp) => Some(Ipv4Addr::from(udp.ip as u32)),
+        _ => None,
+    })
+}
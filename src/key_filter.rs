@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bloom filter over stored key IDs, lets lookups of missing keys skip storage.
+/// Key IDs are hashes already, so filter positions are taken from key bytes directly
+pub(crate) struct KeyFilter {
+    bits: Vec<AtomicU64>,
+    mask: usize,
+}
+
+impl KeyFilter {
+    const HASHES: usize = 4;
+
+    /// Filter of given number of bits, rounded up to power of two
+    pub(crate) fn new(bits: usize) -> Self {
+        let bits = bits.max(64).next_power_of_two();
+        Self {
+            bits: (0..bits / 64).map(|_| AtomicU64::new(0)).collect(),
+            mask: bits - 1,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: &[u8; 32]) {
+        for pos in self.positions(key) {
+            self.bits[pos / 64].fetch_or(1 << (pos % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// False means the key was never inserted, true may be false positive
+    pub(crate) fn may_contain(&self, key: &[u8; 32]) -> bool {
+        self.positions(key)
+            .all(|pos| self.bits[pos / 64].load(Ordering::Relaxed) & (1 << (pos % 64)) != 0)
+    }
+
+    fn positions<'a>(&'a self, key: &'a [u8; 32]) -> impl Iterator<Item = usize> + 'a {
+        key.chunks(32 / Self::HASHES).map(move |chunk| {
+            let mut pos = [0u8; 8];
+            pos.copy_from_slice(chunk);
+            u64::from_le_bytes(pos) as usize & self.mask
+        })
+    }
+}
@@ -0,0 +1,115 @@
+//! Generation of node keys targeting parts of DHT key space, so several nodes
+//! of one operator cover key space evenly
+
+use adnl::common::{KeyOption, KeyOptionJson};
+use ton_types::{fail, Result};
+
+use crate::distance;
+
+/// Part of key space for key ID to fall in
+#[derive(Clone, Debug)]
+pub enum KeyTarget {
+    /// Leading `bits` bits of key ID are equal to ones of `value`, most significant first
+    Prefix { value: u32, bits: u8 },
+    /// Number of leading bits key ID has in common with `base` is within `min..=max`
+    Distance { base: [u8; 32], min: u8, max: u8 },
+}
+
+impl KeyTarget {
+    const MAX_PREFIX_BITS: u8 = 32;
+
+    /// Check if key ID falls in target
+    pub fn matches(&self, id: &[u8; 32]) -> bool {
+        match self {
+            KeyTarget::Prefix { value, bits } => {
+                let bits = (*bits).min(Self::MAX_PREFIX_BITS);
+                (bits == 0) || (prefix(id, bits) == value >> (32 - bits as u32))
+            }
+            KeyTarget::Distance { base, min, max } => {
+                let dist = distance::distance(base, id);
+                (*min <= dist) && (dist <= *max)
+            }
+        }
+    }
+}
+
+/// Nodes of a set per key space part selected by leading key ID bits
+#[derive(Clone, Debug)]
+pub struct KeyCoverage {
+    /// Number of leading key ID bits selecting key space part
+    pub prefix_bits: u8,
+    /// Number of nodes per key space part, indexed by prefix
+    pub nodes: Vec<usize>,
+}
+
+impl KeyCoverage {
+    const MAX_PREFIX_BITS: u8 = 16;
+
+    /// Prefixes of key space parts without nodes
+    pub fn uncovered(&self) -> Vec<u32> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, nodes)| **nodes == 0)
+            .map(|(prefix, _)| prefix as u32)
+            .collect()
+    }
+
+    /// Target for next node key: first key space part with the fewest nodes
+    pub fn next_target(&self) -> KeyTarget {
+        let prefix = self
+            .nodes
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, nodes)| **nodes)
+            .map(|(prefix, _)| prefix as u32)
+            .unwrap_or(0);
+        let value = if self.prefix_bits == 0 {
+            0
+        } else {
+            prefix << (32 - self.prefix_bits as u32)
+        };
+        KeyTarget::Prefix {
+            value,
+            bits: self.prefix_bits,
+        }
+    }
+}
+
+/// Analyze how node set with given key IDs covers key space split into
+/// 2^`prefix_bits` parts, at most 2^16
+pub fn coverage(ids: &[[u8; 32]], prefix_bits: u8) -> KeyCoverage {
+    let prefix_bits = prefix_bits.min(KeyCoverage::MAX_PREFIX_BITS);
+    let mut nodes = vec![0; 1 << prefix_bits];
+    for id in ids {
+        nodes[prefix(id, prefix_bits) as usize] += 1
+    }
+    KeyCoverage { prefix_bits, nodes }
+}
+
+/// Generate Ed25519 keys until key ID falls in target, at most `max_attempts` times.
+/// Prefix of n bits takes 2^n attempts on average
+pub fn generate_key(
+    target: &KeyTarget,
+    max_attempts: usize,
+) -> Result<Option<(KeyOptionJson, KeyOption)>> {
+    if max_attempts == 0 {
+        fail!("It is useless to generate key in zero attempts")
+    }
+    for _ in 0..max_attempts {
+        let (json, key) = KeyOption::with_type_id(KeyOption::KEY_ED25519)?;
+        if target.matches(key.id().data()) {
+            return Ok(Some((json, key)));
+        }
+    }
+    Ok(None)
+}
+
+/// Leading `bits` bits of key ID, at most 32
+fn prefix(id: &[u8; 32], bits: u8) -> u32 {
+    if bits == 0 {
+        return 0;
+    }
+    let value = u32::from_be_bytes(*arrayref::array_ref!(id, 0, 4));
+    value >> (32 - bits.min(32) as u32)
+}
@@ -1,10 +1,26 @@
-use std::{mem, ops::Deref, sync::Arc};
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    mem,
+    net::Ipv4Addr,
+    ops::Deref,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use adnl::common::*;
 use adnl::node::{parse_address_list, AddressCache, AddressCacheIterator, AdnlNode, IpAddress};
 use dashmap::DashMap;
 use overlay::{OverlayId, OverlayShortId, OverlayUtils};
 use rand::Rng;
+use sha2::{Digest, Sha256};
+use ton_api::ton::adnl::id::short::Short as AdnlShortId;
 use ton_api::ton::adnl::{addresslist::AddressList, AddressList as AddressListBoxed};
 use ton_api::ton::dht::key::Key as DhtKey;
 use ton_api::ton::dht::keydescription::KeyDescription as DhtKeyDescription;
@@ -17,6 +33,7 @@ use ton_api::ton::dht::Node as NodeBoxed;
 use ton_api::ton::dht::Nodes as NodesBoxed;
 use ton_api::ton::dht::Pong as DhtPongBoxed;
 use ton_api::ton::dht::{Stored, UpdateRule, ValueResult as DhtValueResult};
+use ton_api::ton::overlay::node::tosign::ToSign as OverlayNodeToSign;
 use ton_api::ton::overlay::node::Node as OverlayNode;
 use ton_api::ton::overlay::nodes::Nodes as OverlayNodes;
 use ton_api::ton::overlay::Nodes as OverlayNodesBoxed;
@@ -26,8 +43,163 @@ use ton_api::{ton, IntoBoxed};
 
 use ton_types::{error, fail, Result};
 
+/// Log DHT protocol event as `event=<name> <field>=<value> ...` line.
+/// Common fields are `peer` (ADNL key ID), `key` (DHT key ID), `query` (query kind)
+/// and `outcome` (event result), so log pipelines can aggregate them.
+/// Values with spaces, quotes, `=` or control characters are quoted and escaped
+macro_rules! log_event {
+    ($level:ident, $event:literal $(, $field:ident = $value:expr)* $(,)?) => {
+        log::$level!(
+            target: TARGET,
+            concat!("event=", $event $(, " ", stringify!($field), "={}")*)
+            $(, $crate::EventField(&$value))*
+        )
+    };
+}
+
+/// Value of `log_event!` field, written as is when it is a single plain token
+struct EventField<'a, T: fmt::Display>(&'a T);
+
+impl<T: fmt::Display> fmt::Display for EventField<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = self.0.to_string();
+        let plain = !value.is_empty()
+            && value
+                .chars()
+                .all(|c| !c.is_whitespace() && !c.is_control() && (c != '"') && (c != '='));
+        if plain {
+            f.write_str(&value)
+        } else {
+            write!(f, "{:?}", value)
+        }
+    }
+}
+
+mod address_warmup;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod bootstrap;
+mod buffer_pool;
+mod chunked;
+mod client;
+mod compression;
+pub mod crawler;
+pub mod distance;
+mod dns;
+mod eviction;
+mod external_address;
+#[cfg(feature = "testing")]
+mod faults;
+mod format;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod install;
+mod ip_index;
+mod key_filter;
+pub mod keygen;
+mod lookup_graph;
+mod namespace;
+mod overlay_cache;
+mod overlay_members;
+mod partition;
+mod peer_db;
+mod peer_snapshot;
+mod priority;
+mod query_pool;
+mod rate_limit;
+mod registration;
+mod rejection;
+mod spawner;
+mod storage;
+mod storage_probe;
+mod store_audit;
+#[cfg(test)]
+mod tests;
+#[cfg(test)]
+mod tl_vectors;
+mod traffic;
+mod wal;
+
+pub use address_warmup::AddressWarmup;
+pub use bootstrap::BootstrapReport;
+use buffer_pool::BufferPool;
+pub use client::DhtClient;
+pub use dns::{build_dht_node_info_with_resolver, HostResolver, SystemResolver};
+pub use eviction::PeerEviction;
+#[cfg(feature = "testing")]
+pub use faults::FaultInjection;
+pub use install::DhtHandle;
+use ip_index::IpIndex;
+use key_filter::KeyFilter;
+use lookup_graph::GraphRecorder;
+pub use lookup_graph::{GraphEdge, GraphEdgeKind, LookupGraph};
+pub use namespace::KeyNamespaceHandler;
+use namespace::KeyNamespaces;
+use overlay_cache::OverlayNodesCache;
+pub use overlay_members::OverlayMemberVerifier;
+pub use partition::{ConsistencyReport, PartitionMonitor, PathOutcome};
+use peer_db::PeerDb;
+pub use peer_db::PeerRecord;
+pub use priority::PriorityPing;
+use query_pool::QueryPools;
+pub use query_pool::{QueryClass, QueueDepth};
+pub use rate_limit::RateLimit;
+use rate_limit::RateLimiter;
+use registration::PeerRegistrations;
+pub use rejection::RejectReason;
+use rejection::Rejections;
+pub use spawner::{SpawnedTask, Spawner, TokioSpawner};
+use spawner::{TaskGroup, TaskTracker};
+use storage::{ShardedMap, StoredValue};
+pub use storage_probe::ProbeOutcome;
+use store_audit::StoreAuditLog;
+pub use store_audit::{StoreAudit, StoreAuditRecord};
+use traffic::TrafficStats;
+pub use traffic::{QueryTraffic, Traffic};
+use wal::StoreWal;
+
 pub const TARGET: &str = "dht";
 
+/// DHT error, can be obtained from `failure::Error` via `downcast_ref`
+#[derive(Debug, failure::Fail)]
+pub enum DhtError {
+    /// Protocol data cannot be parsed or has unexpected format
+    #[fail(display = "Malformed DHT data: {}", _0)]
+    Malformed(String),
+    /// No DHT peers are known to perform the operation
+    #[fail(display = "No DHT peers known")]
+    NoPeers,
+    /// Requested value was not found
+    #[fail(display = "DHT value not found: {}", _0)]
+    NotFound(String),
+    /// Node did not get enough peers before deadline
+    #[fail(display = "DHT node not ready: {}", _0)]
+    NotReady(BootstrapReport),
+    /// Request is not allowed by node policy
+    #[fail(display = "DHT request rejected: {}", _0)]
+    Rejected(String),
+    /// Node is too busy to process the request
+    #[fail(display = "DHT node overloaded: {}", _0)]
+    Overloaded(String),
+    /// Operation exceeds configured limits
+    #[fail(display = "DHT quota exceeded: {}", _0)]
+    QuotaExceeded(String),
+    /// Remote peer did not answer in time
+    #[fail(display = "DHT query timeout")]
+    Timeout,
+    /// Value update rule is not supported by node
+    #[fail(display = "Unsupported DHT update rule: {}", _0)]
+    UnsupportedRule(String),
+    /// On-disk file has unknown or too new format version
+    #[fail(display = "Unsupported DHT file format: {}", _0)]
+    UnsupportedFormat(String),
+    /// Signature, TTL or key check failed
+    #[fail(display = "DHT verification failed: {}", _0)]
+    Verification(String),
+}
+
 #[macro_export]
 macro_rules! sign {
     ($data:expr, $key:expr) => {{
@@ -53,12 +225,22 @@ macro_rules! verify {
     }};
 }
 
+/// Build static DHT node record from "ip:port" or "host:port" address. Host names
+/// are resolved by single blocking system lookup, so in async code use
+/// `build_dht_node_info_with_resolver` or `DhtNode::add_peer_by_host` instead
 pub fn build_dht_node_info(ip: &str, key: &str, signature: &str) -> Result<Node> {
+    dns::build_dht_node_info_blocking(ip, key, signature)
+}
+
+fn build_dht_node_info_from_addrs(ips: Vec<IpAddress>, key: &str, signature: &str) -> Result<Node> {
     let key = base64::decode(key)?;
     if key.len() != 32 {
-        fail!("Bad public key length")
+        fail!(DhtError::Malformed("Bad public key length".to_string()))
     }
-    let addrs = vec![IpAddress::from_string(ip)?.into_udp().into_boxed()];
+    let addrs: Vec<_> = ips
+        .into_iter()
+        .map(|ip| ip.into_udp().into_boxed())
+        .collect();
     let signature = base64::decode(signature)?;
     let node = Node {
         id: Ed25519 {
@@ -78,125 +260,1212 @@ pub fn build_dht_node_info(ip: &str, key: &str, signature: &str) -> Result<Node>
     Ok(node)
 }
 
-type DhtKeyId = [u8; 32];
+/// ID of DHT key (hash of the key)
+pub type DhtKeyId = [u8; 32];
+
+/// ID of DHT key
+pub fn dht_key_hash(key: &DhtKey) -> Result<DhtKeyId> {
+    hash(key.clone())
+}
+
+/// Builder of DHT key, allowing to compute exact ID the value is stored under
+#[derive(Clone, Debug)]
+pub struct DhtKeyBuilder {
+    id: [u8; 32],
+    idx: i32,
+    name: String,
+}
+
+impl DhtKeyBuilder {
+    /// Key with given owner ID and name, index is 0
+    pub fn new(id: &Arc<KeyId>, name: &str) -> Self {
+        Self {
+            id: *id.data(),
+            idx: 0,
+            name: name.to_string(),
+        }
+    }
+
+    /// Set key index, used by sharded records
+    pub fn idx(mut self, idx: i32) -> Self {
+        self.idx = idx;
+        self
+    }
+
+    /// Salt key name with private network ID, as nodes with `network_id` option do
+    pub fn network_id(mut self, network_id: &[u8]) -> Self {
+        self.name = format!("{}:{}", self.name, hex::encode(network_id));
+        self
+    }
+
+    /// Build TL key
+    pub fn build(&self) -> DhtKey {
+        DhtKey {
+            id: ton::int256(self.id),
+            idx: self.idx,
+            name: ton::bytes(self.name.as_bytes().to_vec()),
+        }
+    }
+
+    /// ID the value with this key is stored under
+    pub fn dht_key_hash(&self) -> Result<DhtKeyId> {
+        dht_key_hash(&self.build())
+    }
+}
+
+/// Outcome of storing a value in DHT
+#[derive(Clone, Debug, Default)]
+pub struct StoreReport {
+    /// Number of peers the store query was sent to
+    pub contacted: usize,
+    /// Peers which acknowledged the store with Stored
+    pub confirmed: Vec<Arc<KeyId>>,
+    /// Whether the stored value was found by subsequent search
+    pub verified: bool,
+}
+
+impl StoreReport {
+    /// Number of Stored acknowledgements
+    pub fn acks(&self) -> usize {
+        self.confirmed.len()
+    }
+}
+
+/// Outcome of adding DHT peer
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddPeerResult {
+    /// New peer added
+    Added(Arc<KeyId>),
+    /// Known peer updated with newer version
+    UpdatedVersion(Arc<KeyId>),
+    /// Peer with the same version is already known
+    AlreadyKnown(Arc<KeyId>),
+    /// Peer signature check failed
+    RejectedBadSignature,
+    /// Peer with newer version is already known
+    RejectedStaleAddress,
+    /// Peer is banned
+    RejectedBanned,
+    /// Peer addresses are not allowed by address policy
+    RejectedByPolicy,
+    /// Peer record exceeds strict validation limits
+    RejectedMalformed,
+    /// Peer is refused by ADNL
+    RejectedByAdnl,
+}
+
+impl AddPeerResult {
+    /// ID of the peer if it is in routing table
+    pub fn peer_id(&self) -> Option<&Arc<KeyId>> {
+        match self {
+            AddPeerResult::Added(id)
+            | AddPeerResult::UpdatedVersion(id)
+            | AddPeerResult::AlreadyKnown(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    fn outcome(&self) -> &'static str {
+        match self {
+            AddPeerResult::Added(_) => "added",
+            AddPeerResult::UpdatedVersion(_) => "updated",
+            AddPeerResult::AlreadyKnown(_) => "known",
+            AddPeerResult::RejectedBadSignature => "bad_signature",
+            AddPeerResult::RejectedStaleAddress => "stale_address",
+            AddPeerResult::RejectedBanned => "banned",
+            AddPeerResult::RejectedByPolicy => "address_policy",
+            AddPeerResult::RejectedMalformed => "malformed",
+            AddPeerResult::RejectedByAdnl => "refused",
+        }
+    }
+}
+
+/// Overlay node with resolved address
+pub struct ResolvedOverlayNode {
+    /// Node IP address
+    pub ip: IpAddress,
+    /// Node ADNL key
+    pub adnl_key: KeyOption,
+    /// Overlay node record
+    pub node: OverlayNode,
+}
+
+/// Result of joining overlay
+pub struct OverlayJoin {
+    /// Report of storing own overlay node
+    pub report: StoreReport,
+    /// Resolved overlay members registered in ADNL
+    pub peers: Vec<ResolvedOverlayNode>,
+}
+
+/// Handle of periodic overlay node announcement
+pub struct OverlayNodeAnnouncement {
+    stopped: Arc<AtomicBool>,
+}
+
+impl OverlayNodeAnnouncement {
+    /// Stop announcement
+    pub fn cancel(&self) {
+        self.stopped.store(true, Ordering::Relaxed)
+    }
+}
+
+/// DHT peer health
+#[derive(Clone, Debug)]
+pub struct PeerHealth {
+    /// Whether the peer answered ping
+    pub reachable: bool,
+    /// Measured round-trip time
+    pub rtt: Option<Duration>,
+}
+
+/// DHT peer statistics
+#[derive(Clone, Debug, Default)]
+pub struct PeerStats {
+    /// Last measured round-trip time
+    pub last_rtt: Option<Duration>,
+    /// Smoothed round-trip time
+    pub avg_rtt: Option<Duration>,
+    /// Smoothed mean deviation of round-trip time
+    pub rtt_var: Option<Duration>,
+    /// Number of answered queries
+    pub answered: u64,
+    /// Number of unanswered queries
+    pub failed: u64,
+    /// Number of unanswered queries since last answered one
+    pub failed_in_row: u32,
+    /// Time of last answer from the peer
+    pub last_answered_at: i32,
+    /// Time the peer was last seen: became known, answered or queried us
+    pub last_seen_at: i32,
+    /// Time until which the peer is skipped in lookups and stores after failures in row
+    pub backoff_until: i32,
+    /// Number of storage probes the peer answered with value it acknowledged to store,
+    /// halved together with `probes_dropped` as probes accumulate, so old ones fade
+    pub probes_retained: u64,
+    /// Number of storage probes the peer answered without value it acknowledged to store,
+    /// decays like `probes_retained`
+    pub probes_dropped: u64,
+}
+
+impl PeerStats {
+    const BACKOFF_FAILURES: u32 = 3;
+    const MAX_BACKOFF_SEC: i32 = 600;
+    const MIN_BACKOFF_SEC: i32 = 2;
+    const MAX_PROBES: u64 = 32;
+    const MAX_TIMEOUT: Duration = Duration::from_secs(10);
+    const MIN_PROBES: u64 = 4;
+    const MIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+    fn update(&mut self, answered: bool) {
+        if answered {
+            let now = now();
+            self.answered += 1;
+            self.failed_in_row = 0;
+            self.last_answered_at = now;
+            self.last_seen_at = now;
+            self.backoff_until = 0;
+        } else {
+            self.failed += 1;
+            self.failed_in_row += 1;
+            if self.failed_in_row >= Self::BACKOFF_FAILURES {
+                // Cool-down doubles with each further failure
+                let exp = (self.failed_in_row - Self::BACKOFF_FAILURES).min(16);
+                let backoff = Self::MIN_BACKOFF_SEC
+                    .checked_shl(exp)
+                    .unwrap_or(Self::MAX_BACKOFF_SEC)
+                    .min(Self::MAX_BACKOFF_SEC);
+                self.backoff_until = now() + backoff;
+            }
+        }
+    }
+
+    fn update_probes(&mut self, retained: bool) {
+        if self.probes_retained + self.probes_dropped >= Self::MAX_PROBES {
+            self.probes_retained /= 2;
+            self.probes_dropped /= 2;
+        }
+        if retained {
+            self.probes_retained += 1
+        } else {
+            self.probes_dropped += 1
+        }
+    }
+
+    fn update_rtt(&mut self, rtt: Duration) {
+        // EWMA of RTT and its deviation with 1/8 and 1/4 weights of the new sample, as in TCP
+        match (self.avg_rtt, self.rtt_var) {
+            (Some(avg), Some(var)) => {
+                let deviation = if avg > rtt { avg - rtt } else { rtt - avg };
+                self.rtt_var = Some((var * 3 + deviation) / 4);
+                self.avg_rtt = Some((avg * 7 + rtt) / 8);
+            }
+            _ => {
+                self.rtt_var = Some(rtt / 2);
+                self.avg_rtt = Some(rtt);
+            }
+        }
+        self.last_rtt = Some(rtt);
+    }
+
+    /// Adaptive query timeout: smoothed RTT plus four deviations, doubled on each
+    /// failure in row. None if RTT is not measured yet
+    pub fn timeout(&self) -> Option<Duration> {
+        let (avg, var) = match (self.avg_rtt, self.rtt_var) {
+            (Some(avg), Some(var)) => (avg, var),
+            _ => return None,
+        };
+        let mut timeout = (avg + var * 4).max(Self::MIN_TIMEOUT);
+        for _ in 0..self.failed_in_row {
+            if timeout >= Self::MAX_TIMEOUT {
+                break;
+            }
+            timeout *= 2;
+        }
+        Some(timeout.min(Self::MAX_TIMEOUT))
+    }
+
+    /// Whether the peer is skipped in lookups and stores due to failures in row
+    pub fn is_backed_off(&self) -> bool {
+        self.backoff_until > now()
+    }
+
+    /// Whether the peer acknowledges stores but dropped stored values in most of
+    /// enough storage probes
+    pub fn drops_values(&self) -> bool {
+        (self.probes_dropped >= Self::MIN_PROBES) && (self.probes_dropped > self.probes_retained)
+    }
+
+    /// Whether the peer answered last query
+    pub fn is_reachable(&self) -> bool {
+        (self.answered > 0) && (self.failed_in_row == 0)
+    }
+}
+
+/// Value found in DHT lookup
+pub struct FoundValue {
+    /// Value key description
+    pub key: DhtKeyDescription,
+    /// Value object
+    pub object: TLObject,
+    /// Peer the value was received from
+    pub peer: Arc<KeyId>,
+    /// Value expiration time
+    pub ttl: i32,
+    /// Version of value object if any (e.g. address list version)
+    pub version: Option<i32>,
+}
+
+/// DHT value lookup statistics
+#[derive(Clone, Debug, Default)]
+pub struct LookupStats {
+    /// Number of peers queried
+    pub contacted: usize,
+    /// Number of lookup rounds
+    pub hops: u32,
+}
+
+/// Response of peer queried during value lookup
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerResponse {
+    /// Peer returned requested value
+    Found,
+    /// Peer has no value and suggested other peers
+    NotFound,
+    /// Peer returned value which failed verification or has unexpected type
+    Invalid,
+    /// Peer did not answer in time
+    Timeout,
+    /// Query failed
+    Error,
+}
+
+/// Query sent during value lookup
+#[derive(Clone, Debug)]
+pub struct TracedQuery {
+    /// Queried peer
+    pub peer: Arc<KeyId>,
+    /// Lookup round the query was sent in
+    pub hop: u32,
+    /// Time since lookup start the query was sent at
+    pub sent_at: Duration,
+    /// Time since lookup start the response was received at
+    pub answered_at: Duration,
+    /// Peer response
+    pub response: PeerResponse,
+}
+
+/// Trace of value lookup
+#[derive(Clone, Debug, Default)]
+pub struct LookupTrace {
+    /// Queries in order of sending
+    pub queries: Vec<TracedQuery>,
+    /// Round in which value was first received from peer
+    pub found_at_hop: Option<u32>,
+    /// Total lookup duration
+    pub duration: Duration,
+}
+
+struct TraceSink {
+    queries: Mutex<Vec<TracedQuery>>,
+    start: Instant,
+}
+
+impl TraceSink {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            queries: Mutex::new(Vec::new()),
+            start: Instant::now(),
+        })
+    }
+
+    fn finish(&self) -> LookupTrace {
+        let mut queries = match self.queries.lock() {
+            Ok(queries) => mem::take(&mut *queries),
+            Err(e) => mem::take(&mut *e.into_inner()),
+        };
+        queries.sort_by_key(|query| query.sent_at);
+        let found_at_hop = queries
+            .iter()
+            .filter(|query| query.response == PeerResponse::Found)
+            .map(|query| query.hop)
+            .min();
+        LookupTrace {
+            queries,
+            found_at_hop,
+            duration: self.start.elapsed(),
+        }
+    }
+
+    fn record(&self, peer: Arc<KeyId>, hop: u32, sent_at: Duration, response: PeerResponse) {
+        let query = TracedQuery {
+            peer,
+            hop,
+            sent_at,
+            answered_at: self.start.elapsed(),
+            response,
+        };
+        match self.queries.lock() {
+            Ok(mut queries) => queries.push(query),
+            Err(e) => e.into_inner().push(query),
+        }
+    }
+}
+
+/// Popularity of DHT key among incoming FindValue queries
+#[derive(Clone, Debug, Default)]
+pub struct KeyPopularity {
+    /// DHT key ID
+    pub key: DhtKeyId,
+    /// Number of queries answered with locally stored value
+    pub hits: u64,
+    /// Number of queries for the key not stored locally
+    pub misses: u64,
+}
+
+impl KeyPopularity {
+    /// Total number of queries for the key
+    pub fn reads(&self) -> u64 {
+        self.hits + self.misses
+    }
+}
+
+/// Number and total size of locally stored values of one class
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StorageUsage {
+    /// Number of values
+    pub values: usize,
+    /// Total size of values in bytes
+    pub bytes: usize,
+}
+
+impl StorageUsage {
+    fn add(&mut self, value: &DhtValue) {
+        self.values += 1;
+        self.bytes += value.value.len();
+    }
+}
+
+/// DHT node status summary
+#[derive(Clone, Debug)]
+pub struct DhtStatus {
+    /// Number of known peers
+    pub known_peers: u32,
+    /// Number of peers per non-empty bucket (distance, count)
+    pub buckets: Vec<(u8, usize)>,
+    /// Number of locally stored values
+    pub stored_values: usize,
+    /// Total size of locally stored values in bytes
+    pub stored_bytes: usize,
+    /// Locally stored values with signature update rule, e.g. addresses
+    pub stored_signed: StorageUsage,
+    /// Locally stored overlay nodes lists
+    pub stored_overlay_nodes: StorageUsage,
+    /// Locally stored values with other update rules
+    pub stored_other: StorageUsage,
+    /// Time of last successful value lookup
+    pub last_lookup_at: Option<i32>,
+    /// Time of last acknowledged value store
+    pub last_store_at: Option<i32>,
+    /// Whether own address record is currently published
+    pub address_published: bool,
+    /// Number of incoming FindValue queries answered with locally stored value
+    pub find_value_hits: u64,
+    /// Number of incoming FindValue queries for values not stored locally
+    pub find_value_misses: u64,
+    /// Number of stored values purged because they failed re-verification
+    pub purged_values: u64,
+    /// Numbers of rejected incoming queries per reason
+    pub rejections: Vec<(RejectReason, u64)>,
+}
+
+/// Node record prefixed to outgoing queries, so queried peers learn about us
+#[derive(Clone, Debug)]
+pub enum QueryPrefix {
+    /// Own signed node, re-signed when address list changes or after given
+    /// number of seconds
+    LocalNode(i32),
+    /// No prefix, queries are anonymous
+    Disabled,
+    /// Node supplied by caller
+    Custom(Node),
+}
+
+/// Role of DHT node in the network
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DhtRole {
+    /// Serves all DHT queries and advertises itself to peers
+    Full,
+    /// Performs outgoing lookups only: does not advertise itself to peers
+    /// and rejects Store and FindValue queries
+    ClientOnly,
+    /// Seed node: answers routing queries but rejects Store queries
+    BootstrapOnly,
+}
+
+/// Policy of accepting peers by their advertised addresses
+#[derive(Clone)]
+pub enum AddressPolicy {
+    /// Accept any addresses, e.g. for local test networks
+    AllowPrivate,
+    /// Reject peers advertising loopback, private and other non-routable addresses
+    PublicOnly,
+    /// Accept peers whose every address matches predicate
+    Custom(Arc<dyn Fn(&Ipv4Addr) -> bool + Send + Sync>),
+}
+
+impl AddressPolicy {
+    fn allows(&self, addr_list: &AddressList) -> bool {
+        addr_list.addrs.iter().all(|addr| match addr {
+            ton::adnl::Address::Adnl_Address_Udp(udp) => {
+                let ip = Ipv4Addr::from(udp.ip as u32);
+                match self {
+                    AddressPolicy::AllowPrivate => true,
+                    AddressPolicy::PublicOnly => Self::is_public(&ip),
+                    AddressPolicy::Custom(predicate) => predicate(&ip),
+                }
+            }
+            _ => true,
+        })
+    }
+
+    fn is_public(ip: &Ipv4Addr) -> bool {
+        let octets = ip.octets();
+        // 100.64.0.0/10 is shared address space of carrier-grade NAT
+        let shared = (octets[0] == 100) && ((octets[1] & 0xC0) == 64);
+        !(ip.is_loopback()
+            || ip.is_private()
+            || ip.is_link_local()
+            || ip.is_unspecified()
+            || ip.is_broadcast()
+            || ip.is_documentation()
+            || ip.is_multicast()
+            || shared)
+    }
+}
+
+impl fmt::Debug for AddressPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressPolicy::AllowPrivate => write!(f, "AllowPrivate"),
+            AddressPolicy::PublicOnly => write!(f, "PublicOnly"),
+            AddressPolicy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Whitelist of keys allowed to store values on the node
+#[derive(Clone)]
+pub enum StoreWhitelist {
+    /// Keys with given IDs
+    Keys(HashSet<Arc<KeyId>>),
+    /// Keys matching predicate
+    Predicate(Arc<dyn Fn(&Arc<KeyId>) -> bool + Send + Sync>),
+}
+
+impl StoreWhitelist {
+    fn allows(&self, key_id: &Arc<KeyId>) -> bool {
+        match self {
+            StoreWhitelist::Keys(keys) => keys.contains(key_id),
+            StoreWhitelist::Predicate(predicate) => predicate(key_id),
+        }
+    }
+}
+
+impl fmt::Debug for StoreWhitelist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreWhitelist::Keys(keys) => write!(f, "Keys({})", keys.len()),
+            StoreWhitelist::Predicate(_) => write!(f, "Predicate"),
+        }
+    }
+}
+
+/// Class of short-living values, e.g. presence or heartbeat records. They are never
+/// persisted to store log and are evicted first when there are too many of them
+#[derive(Clone, Debug)]
+pub struct EphemeralValues {
+    /// Key names of ephemeral values
+    pub names: Vec<String>,
+    /// Max TTL of ephemeral value, seconds from now
+    pub max_ttl: i32,
+    /// Max number of stored ephemeral values, ones expiring first are evicted above it
+    pub max_values: usize,
+}
+
+impl Default for EphemeralValues {
+    fn default() -> Self {
+        Self {
+            names: Vec::new(),
+            max_ttl: 300,
+            max_values: 4096,
+        }
+    }
+}
+
+impl EphemeralValues {
+    fn contains(&self, name: &[u8]) -> bool {
+        self.names.iter().any(|ephemeral| {
+            let ephemeral = ephemeral.as_bytes();
+            // Key names of private network are salted with network ID after colon
+            name.starts_with(ephemeral)
+                && ((name.len() == ephemeral.len()) || (name[ephemeral.len()] == b':'))
+        })
+    }
+}
+
+/// Limits of strict validation of data received from untrusted peers
+#[derive(Clone, Debug)]
+pub struct ValidationLimits {
+    /// Max number of addresses in address list
+    pub max_addresses: usize,
+    /// Max time in seconds node versions and address list dates may be ahead of local clock
+    pub max_clock_skew: i32,
+    /// Max length of key name
+    pub max_key_name_len: usize,
+    /// Max time in seconds value TTL may be ahead of local clock
+    pub max_ttl: i32,
+    /// Max size of value
+    pub max_value_size: usize,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        Self {
+            max_addresses: 16,
+            max_clock_skew: 600,
+            max_key_name_len: 127,
+            max_ttl: 7 * 24 * 3600,
+            max_value_size: 64 * 1024,
+        }
+    }
+}
+
+impl ValidationLimits {
+    fn check_node(&self, node: &Node) -> Result<()> {
+        let addr_list = &node.addr_list;
+        if addr_list.addrs.len() > self.max_addresses {
+            fail!(DhtError::QuotaExceeded(format!(
+                "Too many addresses in node: {}",
+                addr_list.addrs.len()
+            )))
+        }
+        let max_date = now() + self.max_clock_skew;
+        if (node.version > max_date)
+            || (addr_list.version > max_date)
+            || (addr_list.reinit_date > max_date)
+        {
+            fail!(DhtError::Malformed(format!(
+                "Node version {} / address list version {} are from the future",
+                node.version, addr_list.version
+            )))
+        }
+        Ok(())
+    }
+
+    fn check_value(&self, value: &DhtValue) -> Result<()> {
+        if value.key.key.name.len() > self.max_key_name_len {
+            fail!(DhtError::QuotaExceeded(format!(
+                "Too long DHT key name: {} bytes",
+                value.key.key.name.len()
+            )))
+        }
+        if value.ttl > now() + self.max_ttl {
+            fail!(DhtError::QuotaExceeded(format!(
+                "Too long DHT value TTL: {}",
+                value.ttl
+            )))
+        }
+        if value.value.len() > self.max_value_size {
+            fail!(DhtError::QuotaExceeded(format!(
+                "Too big DHT value: {} bytes",
+                value.value.len()
+            )))
+        }
+        Ok(())
+    }
+}
+
+/// DHT node options
+#[derive(Clone, Debug)]
+pub struct DhtNodeOptions {
+    /// Policy of accepting peers by their addresses, applied to all learned peers
+    pub address_policy: AddressPolicy,
+    /// Put own signed node first into nodes of FindNode and not found FindValue
+    /// answers, so requesters may add us to their routing tables
+    pub advertise_local_node: bool,
+    /// Number of nodes in answers to FindNode/FindValue queries which do not request
+    /// positive number of nodes
+    pub answer_k_default: usize,
+    /// Max number of nodes in answers to FindNode/FindValue queries, bigger requested
+    /// numbers are clamped
+    pub answer_k_max: usize,
+    /// Cache remote values which are looked up repeatedly. Cached values answer
+    /// single value lookups till TTL or till newer version of the value is seen,
+    /// store verification and quorum reads always go to network
+    pub cache_popular_values: bool,
+    /// Keep stored overlay nodes lists compressed in memory, they are decompressed
+    /// when served
+    pub compress_overlay_nodes: bool,
+    /// If set, values with these key names are accepted with shorter TTL and kept
+    /// in memory only
+    pub ephemeral_values: Option<EphemeralValues>,
+    /// Include own signed node into FindNode answers when it is among k nodes
+    /// closest to the target, so requesters converge toward us faster
+    pub include_local_node: bool,
+    /// Serve single value lookups of values published by the node from local
+    /// storage, so they are read back without network round. Values stored on us
+    /// by other peers are always looked up in network
+    pub local_first_reads: bool,
+    /// Start value lookups from peers which recently had values with keys of the
+    /// same prefix
+    pub lookup_warm_start: bool,
+    /// Number of FindValue queries processed in parallel
+    pub lookup_workers: usize,
+    /// If set, answers to FindNode/FindValue queries are limited to this number of
+    /// serialized bytes: excess nodes are omitted, stored overlay nodes lists are
+    /// trimmed to newest nodes
+    pub max_answer_size: Option<usize>,
+    /// Max number of known peers, excess ones are evicted according to `peer_eviction`
+    pub max_known_peers: usize,
+    /// Max number of rounds in single value lookup
+    pub max_lookup_hops: u32,
+    /// Max number of peers queried in single value lookup
+    pub max_lookup_peers: usize,
+    /// Max number of nodes stored in overlay nodes list, nodes with oldest versions
+    /// are evicted first
+    pub max_overlay_nodes: usize,
+    /// If set, incoming overlay nodes values with longer TTL are rejected, seconds from now
+    pub max_overlay_nodes_ttl: Option<i32>,
+    /// Peers not seen for this number of seconds are removed by peers GC
+    pub max_peer_age: Option<i32>,
+    /// Peers which failed this number of queries in row are removed by peers GC
+    pub max_peer_failures: Option<u32>,
+    /// If set, incoming signed values with longer TTL are rejected, seconds from now
+    pub max_signed_value_ttl: Option<i32>,
+    /// If set, keys not found by full lookup are remembered as missing for this
+    /// number of seconds, and lookups for them return nothing without querying network
+    pub missing_key_ttl: Option<i32>,
+    /// Private network ID, mixed into DHT keys and node signatures to isolate
+    /// the network from nodes with another or no ID
+    pub network_id: Option<Vec<u8>>,
+    /// Overlay nodes with versions older than this number of seconds are pruned
+    /// from stored overlay nodes lists
+    pub overlay_nodes_horizon: Option<i32>,
+    /// If set, peer database is loaded from this path on start and saved to it
+    /// on shutdown
+    pub peer_db: Option<PathBuf>,
+    /// Strategy of evicting known peers over `max_known_peers`
+    pub peer_eviction: PeerEviction,
+    /// Max number of queued queries per class, excess ones are dropped
+    pub query_backlog: usize,
+    /// Node record prefixed to outgoing queries. Client-only nodes never prefix queries
+    pub query_prefix: QueryPrefix,
+    /// If set, all outgoing DHT queries of the node are limited to this rate
+    pub query_rate_limit: Option<RateLimit>,
+    /// Re-verify stored values on first read after each change and purge ones
+    /// which fail verification
+    pub reverify_stored_values: bool,
+    /// Node role
+    pub role: DhtRole,
+    /// Answer incoming FindValue queries with cached remote values as well
+    pub serve_cached_values: bool,
+    /// ADNL key tag of key signing values the node publishes about itself, e.g. own
+    /// address, if it differs from routing identity. Own node record is always signed
+    /// by node key, as peers verify it against node ID
+    pub signing_key_tag: Option<usize>,
+    /// Executor for node tasks
+    pub spawner: Arc<dyn Spawner>,
+    /// If set, accepted Store queries are recorded for abuse investigations
+    pub store_audit: Option<StoreAudit>,
+    /// If set, accepted stored values are persisted to write-ahead log at this path
+    /// and restored from it on start
+    pub store_log: Option<PathBuf>,
+    /// Number of closest peers to store values on per round; if not set, it is
+    /// chosen from network size estimate and churn of routing table
+    pub store_replicas: Option<usize>,
+    /// If set, only values signed by whitelisted keys are accepted in Store queries
+    pub store_whitelist: Option<StoreWhitelist>,
+    /// Number of Store queries processed in parallel
+    pub store_workers: usize,
+    /// If set, Bloom filter of this number of bits over stored key IDs is consulted
+    /// before storage lookups, making misses on storage-heavy nodes cheap
+    pub stored_keys_filter: Option<usize>,
+    /// Reject peers and values which don't belong to the network with `network_id`
+    pub strict_network: bool,
+    /// If set, reject peers and values exceeding limits
+    pub strict_validation: Option<ValidationLimits>,
+    /// Count bytes of DHT queries and answers per peer and per query type
+    pub traffic_stats: bool,
+}
+
+impl Default for DhtNodeOptions {
+    fn default() -> Self {
+        Self {
+            address_policy: AddressPolicy::AllowPrivate,
+            advertise_local_node: false,
+            answer_k_default: 10,
+            answer_k_max: 32,
+            cache_popular_values: false,
+            compress_overlay_nodes: false,
+            ephemeral_values: None,
+            include_local_node: true,
+            local_first_reads: false,
+            lookup_warm_start: true,
+            lookup_workers: 8,
+            max_answer_size: None,
+            max_known_peers: DhtNode::MAX_PEERS as usize,
+            max_lookup_hops: 256,
+            max_lookup_peers: 1024,
+            max_overlay_nodes: 128,
+            max_overlay_nodes_ttl: None,
+            max_peer_age: Some(24 * 3600),
+            max_peer_failures: Some(16),
+            max_signed_value_ttl: None,
+            missing_key_ttl: Some(30),
+            network_id: None,
+            overlay_nodes_horizon: Some(3600),
+            peer_db: None,
+            peer_eviction: PeerEviction::Fifo,
+            query_backlog: 256,
+            query_prefix: QueryPrefix::LocalNode(DhtNode::TIMEOUT_LOCAL_NODE),
+            query_rate_limit: None,
+            reverify_stored_values: true,
+            role: DhtRole::Full,
+            serve_cached_values: false,
+            signing_key_tag: None,
+            spawner: Arc::new(TokioSpawner),
+            store_audit: None,
+            store_log: None,
+            store_replicas: None,
+            store_whitelist: None,
+            store_workers: 4,
+            stored_keys_filter: None,
+            strict_network: false,
+            strict_validation: None,
+            traffic_stats: false,
+        }
+    }
+}
+
+/// Signed local node along with serialized query prefix
+#[derive(Default)]
+struct LocalNode {
+    node: Node,
+    query_prefix: Vec<u8>,
+}
+
+impl LocalNode {
+    fn with_node(node: Node) -> Result<Self> {
+        let mut query_prefix = Vec::new();
+        serialize_inplace(&mut query_prefix, &rpc::dht::Query { node: node.clone() })?;
+        Ok(Self { node, query_prefix })
+    }
+}
 
 /// DHT Node
 pub struct DhtNode {
     adnl: Arc<AdnlNode>,
-    buckets: DashMap<u8, DashMap<Arc<KeyId>, Node>>,
-    known_peers: AddressCache,
+    address_published_until: AtomicI32,
+    banned_peers: DashMap<Arc<KeyId>, i32>,
+    buckets: ShardedMap<Arc<KeyId>, Node>,
+    buffers: BufferPool,
+    custom_prefix: Option<Arc<LocalNode>>,
+    ephemeral_keys: DashMap<DhtKeyId, i32>,
+    #[cfg(feature = "testing")]
+    faults: RwLock<FaultInjection>,
+    find_value_hits: AtomicU64,
+    find_value_misses: AtomicU64,
+    ip_index: IpIndex,
+    key_filter: Option<KeyFilter>,
+    key_reads: DashMap<DhtKeyId, KeyPopularity>,
+    known_peers: RwLock<Arc<AddressCache>>,
+    last_lookup_at: AtomicI32,
+    last_store_at: AtomicI32,
+    local_node: RwLock<Arc<LocalNode>>,
+    lookup_graph: GraphRecorder,
+    lookup_shortlists: DashMap<u16, Vec<Arc<KeyId>>>,
+    missing_keys: DashMap<DhtKeyId, i32>,
+    namespaces: KeyNamespaces,
     node_key: Arc<KeyOption>,
-    query_prefix: Vec<u8>,
-    storage: DashMap<DhtKeyId, DhtValue>,
+    options: DhtNodeOptions,
+    overlay_cache: OverlayNodesCache,
+    overlay_verifiers: DashMap<Arc<OverlayShortId>, Arc<dyn OverlayMemberVerifier>>,
+    peer_db: PeerDb,
+    peer_stats: DashMap<Arc<KeyId>, PeerStats>,
+    pinned_values: DashMap<DhtKeyId, ()>,
+    priority_peers: DashMap<Arc<KeyId>, ()>,
+    published_keys: DashMap<DhtKeyId, i32>,
+    purged_values: AtomicU64,
+    query_pools: QueryPools,
+    rate_limiter: Option<RateLimiter>,
+    registrations: Arc<PeerRegistrations>,
+    rejections: Rejections,
+    signing_key: Arc<KeyOption>,
+    storage: ShardedMap<DhtKeyId, StoredValue>,
+    store_audit: Option<StoreAuditLog>,
+    tasks: Arc<TaskTracker>,
+    traffic: Option<TrafficStats>,
+    value_cache: DashMap<DhtKeyId, DhtValue>,
+    value_lookups: DashMap<DhtKeyId, u32>,
+    verified_values: DashMap<DhtKeyId, (i32, [u8; 32])>,
+    wal: Option<StoreWal>,
 }
 
 impl DhtNode {
-    const BITS: [u8; 16] = [4, 3, 2, 2, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0];
-
+    const ANSWER_OVERHEAD: usize = 16;
+    const MAX_CACHED_VALUES: usize = 4096;
+    const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024;
+    const MAX_SHORTLIST_PEERS: usize = 8;
+    const MAX_MISSING_KEYS: usize = 4096;
     const MAX_PEERS: u32 = 65536;
+    const MAX_REPLICAS: usize = 32;
+    const MIN_REPLICAS: usize = 6;
+    const MAX_TRACKED_KEYS: usize = 65536;
+    const MAX_VERIFIED_VALUES: usize = 65536;
     const MAX_TASKS: usize = 5;
+    const MIN_LOOKUPS_TO_CACHE: u32 = 2;
+    const TIMEOUT_ANNOUNCE: u64 = 1200; // Seconds
+    const TIMEOUT_LOCAL_NODE: i32 = 600; // Seconds
     const TIMEOUT_VALUE: i32 = 3600; // Seconds
 
     /// Constructor
     pub fn with_adnl_node(adnl: Arc<AdnlNode>, key_tag: usize) -> Result<Arc<Self>> {
+        Self::with_options(adnl, key_tag, DhtNodeOptions::default())
+    }
+
+    /// Constructor with options
+    pub fn with_options(
+        adnl: Arc<AdnlNode>,
+        key_tag: usize,
+        options: DhtNodeOptions,
+    ) -> Result<Arc<Self>> {
         let node_key = adnl.key_by_tag(key_tag)?;
-        let mut ret = Self {
-            adnl,
-            buckets: DashMap::new(),
-            known_peers: AddressCache::with_limit(Self::MAX_PEERS),
-            node_key,
-            query_prefix: Vec::new(),
-            storage: DashMap::new(),
+        let signing_key = match options.signing_key_tag {
+            Some(tag) => adnl.key_by_tag(tag)?,
+            None => node_key.clone(),
         };
-        let query = rpc::dht::Query {
-            node: ret.sign_local_node()?,
+        let storage = ShardedMap::new();
+        let wal = if let Some(path) = &options.store_log {
+            let (wal, values) = StoreWal::open(path)?;
+            let version = now();
+            for (key, value) in values {
+                if value.ttl > version {
+                    storage.insert(key, StoredValue::new(value));
+                } else {
+                    storage.remove(&key);
+                }
+            }
+            log::info!(
+                target: TARGET,
+                "Restored {} DHT values from {}",
+                storage.len(),
+                path.display()
+            );
+            Some(wal)
+        } else {
+            None
         };
-        serialize_inplace(&mut ret.query_prefix, &query)?;
-        Ok(Arc::new(ret))
+        let key_filter = options.stored_keys_filter.map(|bits| {
+            let key_filter = KeyFilter::new(bits);
+            storage.for_each(|key, _| key_filter.insert(key));
+            key_filter
+        });
+        let peer_db = PeerDb::open(options.peer_db.as_deref())?;
+        let store_audit = options
+            .store_audit
+            .as_ref()
+            .map(StoreAuditLog::open)
+            .transpose()?;
+        let traffic = if options.traffic_stats {
+            Some(TrafficStats::new())
+        } else {
+            None
+        };
+        let rate_limiter = options.query_rate_limit.as_ref().map(RateLimiter::new);
+        let custom_prefix = if let QueryPrefix::Custom(node) = &options.query_prefix {
+            Some(Arc::new(LocalNode::with_node(node.clone())?))
+        } else {
+            None
+        };
+        let local_id = node_key.id().clone();
+        let (lookup_workers, store_workers) = (options.lookup_workers, options.store_workers);
+        let query_backlog = options.query_backlog;
+        let tasks = TaskTracker::new(options.spawner.clone());
+        // Peer registrations insert registered peers into routing table of the node,
+        // query pools run query handlers on behalf of the node
+        let ret = Arc::new_cyclic(|dht| Self {
+            adnl: adnl.clone(),
+            address_published_until: AtomicI32::new(0),
+            banned_peers: DashMap::new(),
+            buckets: ShardedMap::new(),
+            buffers: BufferPool::new(),
+            custom_prefix,
+            ephemeral_keys: DashMap::new(),
+            #[cfg(feature = "testing")]
+            faults: RwLock::new(FaultInjection::default()),
+            find_value_hits: AtomicU64::new(0),
+            find_value_misses: AtomicU64::new(0),
+            ip_index: IpIndex::new(),
+            key_filter,
+            key_reads: DashMap::new(),
+            known_peers: RwLock::new(Arc::new(AddressCache::with_limit(Self::known_peers_limit(
+                &options,
+            )))),
+            last_lookup_at: AtomicI32::new(0),
+            last_store_at: AtomicI32::new(0),
+            local_node: RwLock::new(Arc::new(LocalNode::default())),
+            lookup_graph: GraphRecorder::new(),
+            lookup_shortlists: DashMap::new(),
+            missing_keys: DashMap::new(),
+            namespaces: KeyNamespaces::new(),
+            node_key,
+            options,
+            overlay_cache: OverlayNodesCache::new(),
+            overlay_verifiers: DashMap::new(),
+            peer_db,
+            peer_stats: DashMap::new(),
+            pinned_values: DashMap::new(),
+            priority_peers: DashMap::new(),
+            published_keys: DashMap::new(),
+            purged_values: AtomicU64::new(0),
+            query_pools: QueryPools::new(dht.clone(), lookup_workers, store_workers, query_backlog),
+            rate_limiter,
+            registrations: PeerRegistrations::new(adnl, local_id, dht.clone()),
+            rejections: Rejections::default(),
+            signing_key,
+            storage,
+            store_audit,
+            tasks,
+            traffic,
+            value_cache: DashMap::new(),
+            value_lookups: DashMap::new(),
+            verified_values: DashMap::new(),
+            wal,
+        });
+        ret.refresh_local_node()?;
+        Ok(ret)
     }
 
     /// Add DHT peer
-    pub fn add_peer(&self, peer: &Node) -> Result<Option<Arc<KeyId>>> {
-        use dashmap::mapref::entry::Entry;
-
-        if let Err(e) = self.verify_other_node(peer) {
-            log::warn!(target: TARGET, "Error when verifying DHT peer: {}", e);
-            return Ok(None);
+    pub fn add_peer(&self, peer: &Node) -> Result<AddPeerResult> {
+        let key = Arc::new(KeyOption::from_tl_public_key(&peer.id)?);
+        if let Some(rejected) = self.check_peer(&key, peer) {
+            return Ok(rejected);
         }
         let ret = self.adnl.add_peer(
             self.node_key.id(),
             &parse_address_list(&peer.addr_list)?,
-            &Arc::new(KeyOption::from_tl_public_key(&peer.id)?),
+            &key,
         )?;
-        let ret = if let Some(ret) = ret {
-            ret
+        if let Some(ret) = ret {
+            self.insert_peer(&ret, peer)
         } else {
-            return Ok(None);
-        };
-        if self.known_peers.put(ret.clone())? {
-            let key1 = self.node_key.id().data();
-            let key2 = ret.data();
-            let mut dist = 0;
-            for i in 0..32 {
-                match key1[i] ^ key2[i] {
-                    0 => dist += 8,
-                    x => {
-                        if (x & 0xF0) == 0 {
-                            dist += Self::BITS[(x & 0x0F) as usize] + 4
-                        } else {
-                            dist += Self::BITS[(x >> 4) as usize]
+            Ok(AddPeerResult::RejectedByAdnl)
+        }
+    }
+
+    /// Ban DHT peer: it is removed from routing table and never added again till unbanned
+    pub fn ban_peer(&self, peer: &Arc<KeyId>) {
+        log::info!(target: TARGET, "Ban DHT peer {}", peer);
+        self.banned_peers.insert(peer.clone(), now());
+        self.peer_db.update(peer, |record| {
+            record.bans += 1;
+            record.last_banned_at = now()
+        });
+        let mut banned = HashSet::new();
+        banned.insert(peer.clone());
+        if let Err(e) = self.remove_known_peers(&banned) {
+            log::warn!(target: TARGET, "Cannot remove banned DHT peer {}: {}", peer, e)
+        }
+        self.purge_values_signed_by(peer);
+    }
+
+    /// Ping all known peers with bounded parallelism
+    pub async fn check_peers(
+        dht: &Arc<Self>,
+        parallelism: usize,
+    ) -> Result<Vec<(Arc<KeyId>, PeerHealth)>> {
+        if parallelism == 0 {
+            fail!("It is useless to check peers with zero parallelism")
+        }
+        let mut ret = Vec::new();
+        let mut peers = dht
+            .known_peers_snapshot()
+            .into_iter()
+            .filter(|peer| !dht.is_banned(peer));
+        let (wait, mut queue_reader) = Wait::new();
+        loop {
+            for next in &mut peers {
+                let dht_cloned = dht.clone();
+                let wait = wait.clone();
+                let reqs = wait.request();
+                dht.spawn(async move {
+                    let rtt = match dht_cloned.ping_with_rtt(&next).await {
+                        Ok(rtt) => rtt,
+                        Err(e) => {
+                            log::debug!(target: TARGET, "Ping {} error: {}", next, e);
+                            None
                         }
-                        break;
-                    }
+                    };
+                    let health = PeerHealth {
+                        reachable: rtt.is_some(),
+                        rtt,
+                    };
+                    wait.respond(Some((next, health)))
+                });
+                if reqs >= parallelism {
+                    break;
                 }
             }
+            match wait.wait(&mut queue_reader, false).await {
+                Some(Some(health)) => ret.push(health),
+                Some(None) => (),
+                None => break,
+            }
+        }
+        Ok(ret)
+    }
 
-            let update_peer = |bucket: &DashMap<Arc<KeyId>, Node>| {
-                match bucket.entry(ret.clone()) {
-                    Entry::Occupied(entry) => {
-                        if entry.get().version < peer.version {
-                            entry.replace_entry(peer.clone());
-                        }
-                    }
-                    Entry::Vacant(entry) => {
-                        entry.insert(peer.clone());
-                    }
-                };
-            };
-
-            match self.buckets.entry(dist) {
-                Entry::Vacant(entry) => {
-                    let entry = entry.insert(DashMap::new());
-                    update_peer(entry.value());
-                }
-                Entry::Occupied(entry) => {
-                    update_peer(entry.get());
-                }
-            };
+    /// Builder of DHT key with given owner ID and name in the network of this node
+    pub fn dht_key_builder(&self, id: &Arc<KeyId>, name: &str) -> DhtKeyBuilder {
+        let builder = DhtKeyBuilder::new(id, name);
+        if let Some(network_id) = &self.options.network_id {
+            builder.network_id(network_id)
+        } else {
+            builder
         }
-        Ok(Some(ret))
     }
 
-    /// Find DHT nodes
-    pub async fn find_dht_nodes(&self, dst: &Arc<KeyId>) -> Result<bool> {
+    /// Ask peer for up to `k` DHT nodes close to this one.
+    /// Returns key IDs of peers which were not known before
+    pub async fn find_dht_nodes(&self, dst: &Arc<KeyId>, k: i32) -> Result<Vec<Arc<KeyId>>> {
         let query = rpc::dht::FindNode {
             key: ton::int256(*self.node_key.id().data()),
-            k: 10,
+            k,
         };
         let query = TLObject::new(query);
         let answer = self.query_with_prefix(dst, &query).await?;
         let answer: NodesBoxed = if let Some(answer) = answer {
             Query::parse(answer, &query)?
         } else {
-            return Ok(false);
+            return Ok(Vec::new());
         };
         let src = answer.only().nodes;
-        log::debug!(target: TARGET, "-------- Found DHT nodes:");
+        log_event!(
+            debug,
+            "find_nodes",
+            peer = dst,
+            query = "find_node",
+            outcome = "found",
+            nodes = src.len()
+        );
+        let mut ret = Vec::new();
         for node in src.deref() {
-            log::debug!(target: TARGET, "{:?}", node);
-            self.add_peer(node)?;
+            let key = KeyOption::from_tl_public_key(&node.id)?;
+            let result = self.add_suggested_peer(node)?;
+            log_event!(
+                trace,
+                "found_node",
+                peer = dst,
+                node = key.id(),
+                outcome = result.outcome()
+            );
+            if let AddPeerResult::Added(peer) = result {
+                ret.push(peer)
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Estimate number of nodes in the network from occupancy of routing buckets:
+    /// bucket with distance `d` is expected to hold `N / 2^(d + 1)` nodes.
+    /// Buckets with single node are too noisy and skipped
+    pub fn estimate_network_size(&self) -> usize {
+        let mut estimates = Vec::new();
+        let mut known = 0;
+        for i in 0..=254u8 {
+            let count = self.buckets.shard(i).len();
+            known += count;
+            if count > 1 {
+                estimates.push(
+                    count.saturating_mul(1usize.checked_shl(i as u32 + 1).unwrap_or(usize::MAX)),
+                )
+            }
+        }
+        if estimates.is_empty() {
+            return known;
+        }
+        estimates.sort_unstable();
+        estimates[estimates.len() / 2].max(known)
+    }
+
+    /// Export signed records of known peers, as used for static DHT nodes in global config.
+    /// If `only_reachable` is set, only peers which answered last ping are exported
+    pub fn export_peers(&self, limit: usize, only_reachable: bool) -> Result<Vec<Node>> {
+        if limit == 0 {
+            fail!("It is useless to export zero peers")
+        }
+        let mut ret = Vec::new();
+        for i in 0..=255 {
+            for (peer, node) in self.buckets.shard(i).iter() {
+                if only_reachable {
+                    let reachable = self
+                        .peer_stats
+                        .get(peer)
+                        .map(|stats| stats.value().is_reachable())
+                        .unwrap_or(false);
+                    if !reachable {
+                        continue;
+                    }
+                }
+                ret.push(node.clone());
+                if ret.len() == limit {
+                    return Ok(ret);
+                }
+            }
         }
-        Ok(true)
+        Ok(ret)
     }
 
     /// Fetch address of node (locally) with given key ID
@@ -204,7 +1473,7 @@ impl DhtNode {
         &self,
         key_id: &Arc<KeyId>,
     ) -> Result<Option<(IpAddress, KeyOption)>> {
-        let key = Self::dht_key_from_key_id(key_id, "address");
+        let key = self.dht_key_from_key_id(key_id, "address");
         let value = self.search_dht_key(&hash(key)?);
         if let Some(value) = value {
             let object = deserialize(&value.value.0)?;
@@ -219,104 +1488,332 @@ impl DhtNode {
         dht: &Arc<Self>,
         key_id: &Arc<KeyId>,
     ) -> Result<(IpAddress, KeyOption)> {
-        let mut addr_list = DhtNode::find_value(
+        Ok(Self::find_address_with_stats(dht, key_id).await?.0)
+    }
+
+    /// Find address of node with given key ID, reporting lookup statistics
+    pub async fn find_address_with_stats(
+        dht: &Arc<Self>,
+        key_id: &Arc<KeyId>,
+    ) -> Result<((IpAddress, KeyOption), LookupStats)> {
+        if (dht.known_peers().count() == 0) && !dht.options.local_first_reads {
+            fail!(DhtError::NoPeers)
+        }
+        let (mut addr_list, stats) = DhtNode::find_value(
             dht,
-            Self::dht_key_from_key_id(key_id, "address"),
+            dht.dht_key_from_key_id(key_id, "address"),
             |object| object.is::<AddressListBoxed>(),
             false,
             &mut None,
         )
         .await?;
-        if let Some((key, addr_list)) = addr_list.pop() {
-            Self::parse_value_as_address(key, addr_list)
+        if let Some(found) = addr_list.pop() {
+            Ok((
+                Self::parse_value_as_address(found.key, found.object)?,
+                stats,
+            ))
         } else {
-            fail!("No address found for {}", key_id)
+            fail!(DhtError::NotFound(format!(
+                "No address found for {}",
+                key_id
+            )))
+        }
+    }
+
+    /// Find address of node with given key ID, tracing queries of the lookup.
+    /// Trace is returned even when address is not found
+    pub async fn find_address_with_trace(
+        dht: &Arc<Self>,
+        key_id: &Arc<KeyId>,
+    ) -> Result<(Option<(IpAddress, KeyOption)>, LookupTrace)> {
+        let (found, trace) = Self::find_value_with_trace(
+            dht,
+            dht.dht_key_from_key_id(key_id, "address"),
+            |object| object.is::<AddressListBoxed>(),
+        )
+        .await?;
+        let address = match found {
+            Some(found) => Some(Self::parse_value_as_address(found.key, found.object)?),
+            None => None,
+        };
+        Ok((address, trace))
+    }
+
+    /// Find address of node with given key ID, accepting it only when at least
+    /// `quorum` peers return the address with the same owner key and version
+    pub async fn find_address_with_quorum(
+        dht: &Arc<Self>,
+        key_id: &Arc<KeyId>,
+        quorum: usize,
+    ) -> Result<(IpAddress, KeyOption)> {
+        if dht.known_peers().count() == 0 {
+            fail!(DhtError::NoPeers)
+        }
+        let key = dht.dht_key_from_key_id(key_id, "address");
+        let mut iter = None;
+        let mut peers = HashSet::new();
+        let mut votes: Vec<(DhtKeyDescription, AddressList, usize)> = Vec::new();
+        loop {
+            let (found, _) = DhtNode::lookup_value(
+                dht,
+                key.clone(),
+                |object| object.is::<AddressListBoxed>(),
+                true,
+                false,
+                &mut iter,
+                None,
+            )
+            .await?;
+            for found in found {
+                if !peers.insert(found.peer.clone()) {
+                    continue;
+                }
+                let addr_list = if let Ok(addr_list) = found.object.downcast::<AddressListBoxed>() {
+                    addr_list.only()
+                } else {
+                    fail!("INTERNAL ERROR: address list type mismatch in quorum search")
+                };
+                let vote = votes.iter_mut().find(|(key, list, _)| {
+                    (key.id == found.key.id) && (list.version == addr_list.version)
+                });
+                let count = if let Some((_, _, count)) = vote {
+                    *count += 1;
+                    *count
+                } else {
+                    votes.push((found.key, addr_list, 1));
+                    1
+                };
+                if count >= quorum {
+                    let (key, addr_list, _) = votes
+                        .into_iter()
+                        .find(|(_, _, count)| *count >= quorum)
+                        .ok_or_else(|| error!("INTERNAL ERROR: quorum vote lost"))?;
+                    let ip_address = parse_address_list(&addr_list)?;
+                    return Ok((ip_address, KeyOption::from_tl_public_key(&key.id)?));
+                }
+            }
+            if iter.is_none() {
+                break;
+            }
+        }
+        fail!(DhtError::NotFound(format!(
+            "No quorum of {} found for address of {}",
+            quorum, key_id
+        )))
+    }
+
+    /// Find value with given DHT key, tracing queries of the lookup
+    pub async fn find_value_with_trace(
+        dht: &Arc<Self>,
+        key: DhtKey,
+        check: impl Fn(&TLObject) -> bool + Copy + Send + 'static,
+    ) -> Result<(Option<FoundValue>, LookupTrace)> {
+        let trace = TraceSink::new();
+        let (mut found, _) =
+            Self::lookup_value(dht, key, check, false, true, &mut None, Some(trace.clone()))
+                .await?;
+        Ok((found.pop(), trace.finish()))
+    }
+
+    /// Find all values with given DHT key, ordered best first by `rank`
+    pub async fn find_values(
+        dht: &Arc<Self>,
+        key: DhtKey,
+        check: impl Fn(&TLObject) -> bool + Copy + Send + 'static,
+        rank: impl Fn(&FoundValue, &FoundValue) -> CmpOrdering,
+    ) -> Result<Vec<FoundValue>> {
+        let (mut ret, _) = DhtNode::find_value(dht, key, check, true, &mut None).await?;
+        ret.sort_by(rank);
+        Ok(ret)
+    }
+
+    /// Default ranking of found values: higher version first, then later expiration
+    pub fn prefer_newest(a: &FoundValue, b: &FoundValue) -> CmpOrdering {
+        b.version.cmp(&a.version).then(b.ttl.cmp(&a.ttl))
+    }
+
+    /// Forget that key was not found, so the next lookup queries network again
+    pub fn forget_missing_key(&self, key: &DhtKey) -> Result<bool> {
+        Ok(self.missing_keys.remove(&hash(key.clone())?).is_some())
+    }
+
+    /// Race lookups of several candidate keys, e.g. address records of service replicas.
+    /// Returns index of key and its value found first, other lookups are cancelled
+    pub async fn find_first_value(
+        dht: &Arc<Self>,
+        keys: &[DhtKey],
+        check: impl Fn(&TLObject) -> bool + Copy + Send + 'static,
+    ) -> Result<Option<(usize, FoundValue)>> {
+        if keys.is_empty() {
+            return Ok(None);
         }
+        let (wait, mut queue_reader) = Wait::new();
+        let lookups = TaskGroup::new();
+        for (i, key) in keys.iter().enumerate() {
+            let dht_cloned = dht.clone();
+            let key = key.clone();
+            let wait = wait.clone();
+            wait.request();
+            dht.spawn(lookups.run(async move {
+                match DhtNode::find_value(&dht_cloned, key, check, false, &mut None).await {
+                    Ok((mut found, _)) => wait.respond(found.pop().map(|found| (i, found))),
+                    Err(e) => {
+                        log::debug!(target: TARGET, "Lookup of candidate key {} failed: {}", i, e);
+                        wait.respond(None)
+                    }
+                }
+            }));
+        }
+        let mut ret = None;
+        while let Some(found) = wait.wait(&mut queue_reader, false).await {
+            if found.is_some() {
+                ret = found;
+                break;
+            }
+        }
+        lookups.abort();
+        Ok(ret)
     }
 
-    /// Get nodes of overlay with given ID
+    /// Get nodes of overlay with given ID, until at least `min_nodes` distinct nodes
+    /// are resolved or search is over. Nodes resolved so far are returned if search
+    /// fails midway, nodes whose resolution is aborted are kept for next search
     pub async fn find_overlay_nodes(
         dht: &Arc<Self>,
         overlay_id: &Arc<OverlayShortId>,
         iter: &mut Option<AddressCacheIterator>,
-    ) -> Result<Vec<(IpAddress, OverlayNode)>> {
+        min_nodes: usize,
+    ) -> Result<Vec<ResolvedOverlayNode>> {
         let mut ret = Vec::new();
-        let mut nodes = Vec::new();
-        log::trace!(
-            target: TARGET,
-            "-------- Overlay nodes search from {:?}",
-            iter
+        // Nodes left unresolved by previous aborted search go first
+        let mut nodes = dht.overlay_cache.take_requeued(overlay_id);
+        let mut requeued = !nodes.is_empty();
+        let mut resolved = HashSet::new();
+        log_event!(
+            trace,
+            "overlay_search",
+            key = overlay_id,
+            outcome = "started"
         );
         loop {
-            let mut nodes_lists = DhtNode::find_value(
+            let found = DhtNode::find_value(
                 dht,
-                Self::dht_key_from_key_id(overlay_id, "nodes"),
+                dht.dht_key_from_key_id(overlay_id, "nodes"),
                 |object| object.is::<OverlayNodesBoxed>(),
                 true,
                 iter,
             )
-            .await?;
-            if nodes_lists.is_empty() {
+            .await;
+            let mut nodes_lists = match found {
+                Ok((nodes_lists, _)) => nodes_lists,
+                Err(e) => {
+                    dht.overlay_cache.requeue(overlay_id, nodes);
+                    if ret.is_empty() {
+                        return Err(e);
+                    }
+                    // Nodes resolved so far are still good
+                    log_event!(
+                        debug,
+                        "overlay_search",
+                        key = overlay_id,
+                        outcome = "failed",
+                        nodes = ret.len(),
+                        error = e
+                    );
+                    break;
+                }
+            };
+            if nodes_lists.is_empty() && !requeued {
                 // No more results
                 break;
             }
-            while let Some((_, nodes_list)) = nodes_lists.pop() {
-                if let Ok(nodes_list) = nodes_list.downcast::<OverlayNodesBoxed>() {
+            requeued = false;
+            while let Some(found) = nodes_lists.pop() {
+                if let Ok(nodes_list) = found.object.downcast::<OverlayNodesBoxed>() {
                     nodes.append(&mut nodes_list.only().nodes.0)
                 } else {
                     fail!("INTERNAL ERROR: overlay nodes list type mismatch in search")
                 }
             }
             let (wait, mut queue_reader) = Wait::new();
+            let mut pending = HashMap::new();
+            let resolutions = TaskGroup::new();
             let cache = AddressCache::with_limit(Self::MAX_PEERS);
-            log::debug!(
-                target: TARGET,
-                "-------- Searching {} overlay nodes",
-                nodes.len()
+            log_event!(
+                debug,
+                "overlay_search",
+                key = overlay_id,
+                outcome = "resolving",
+                nodes = nodes.len()
             );
             while let Some(node) = nodes.pop() {
                 let node = node.clone();
                 let key = KeyOption::from_tl_public_key(&node.id)?;
-                if !cache.put(key.id().clone())? {
-                    log::trace!(
-                        target: TARGET,
-                        "-------- Overlay node {} already found",
-                        key.id()
+                if resolved.contains(key.id()) || !cache.put(key.id().clone())? {
+                    log_event!(
+                        trace,
+                        "overlay_node",
+                        key = overlay_id,
+                        peer = key.id(),
+                        outcome = "duplicate"
                     );
                     continue;
                 }
-                let dht = dht.clone();
+                pending.insert(key.id().clone(), node.clone());
+                let dht_cloned = dht.clone();
+                let overlay_id = overlay_id.clone();
                 let wait = wait.clone();
                 wait.request();
-                tokio::spawn(async move {
-                    if let Ok((ip, _)) = DhtNode::find_address(&dht, key.id()).await {
-                        log::debug!(
-                            target: TARGET,
-                            "-------- Got Overlay node {} IP: {}, key: {}",
-                            key.id(),
-                            ip,
-                            base64::encode(key.pub_key().unwrap_or(&[0u8; 32]))
+                dht.spawn(resolutions.run(async move {
+                    if let Ok((ip, _)) = DhtNode::find_address(&dht_cloned, key.id()).await {
+                        log_event!(
+                            debug,
+                            "overlay_node",
+                            key = overlay_id,
+                            peer = key.id(),
+                            outcome = "resolved",
+                            ip = ip
                         );
-                        wait.respond(Some((Some(ip), node)))
+                        wait.respond(Some((Some(ip), key, node)))
                     } else {
-                        log::trace!(
-                            target: TARGET,
-                            "-------- Overlay node {} not found",
-                            key.id()
+                        log_event!(
+                            trace,
+                            "overlay_node",
+                            key = overlay_id,
+                            peer = key.id(),
+                            outcome = "unresolved"
                         );
-                        wait.respond(Some((None, node)))
+                        wait.respond(Some((None, key, node)))
                     }
-                });
+                }));
             }
             loop {
+                if ret.len() >= min_nodes.max(1) {
+                    // Outstanding resolutions are not needed anymore, their nodes
+                    // are requeued for next search of the overlay
+                    resolutions.abort();
+                    let unresolved = pending.drain().map(|(_, node)| node);
+                    dht.overlay_cache
+                        .requeue(overlay_id, unresolved.chain(nodes.drain(..)));
+                    break;
+                }
                 match wait.wait(&mut queue_reader, false).await {
-                    Some(Some((None, node))) => nodes.push(node),
-                    Some(Some((Some(ip), node))) => ret.push((ip, node)),
+                    Some(Some((ip, adnl_key, node))) => {
+                        pending.remove(adnl_key.id());
+                        match ip {
+                            None => nodes.push(node),
+                            Some(ip) => {
+                                if resolved.insert(adnl_key.id().clone()) {
+                                    ret.push(ResolvedOverlayNode { ip, adnl_key, node })
+                                }
+                            }
+                        }
+                    }
                     _ => break,
                 }
             }
-            if !ret.is_empty() {
-                // Found some
+            if ret.len() >= min_nodes.max(1) {
+                // Found enough
                 break;
             }
             if iter.is_none() {
@@ -324,25 +1821,83 @@ impl DhtNode {
                 break;
             }
         }
-        log::trace!(
-            target: TARGET,
-            "-------- Overlay nodes to return: {}",
-            ret.len()
+        log_event!(
+            trace,
+            "overlay_search",
+            key = overlay_id,
+            outcome = "done",
+            nodes = ret.len()
         );
         Ok(ret)
     }
 
-    /// First DHT peer
+    /// First DHT peer, then next ones with the same iterator. Entries may be skipped
+    /// or repeated when peers are added concurrently, see `known_peers_snapshot`
     pub fn get_known_peer(&self, iter: &mut Option<AddressCacheIterator>) -> Option<Arc<KeyId>> {
         if let Some(iter) = iter {
-            self.known_peers.next(iter)
+            self.known_peers().next(iter)
         } else {
-            let (new_iter, first) = self.known_peers.first();
+            let (new_iter, first) = self.known_peers().first();
             iter.replace(new_iter);
             first
         }
     }
 
+    /// Iterator over snapshot of known peers, see `known_peers_snapshot`
+    pub fn iter_known_peers(&self) -> impl Iterator<Item = Arc<KeyId>> {
+        self.known_peers_snapshot().into_iter()
+    }
+
+    /// Known peers at the moment of the call, each listed once. Insertions wait
+    /// while the snapshot is taken, so peers added or removed later do not affect it
+    pub fn known_peers_snapshot(&self) -> Vec<Arc<KeyId>> {
+        let known_peers = match self.known_peers.write() {
+            Ok(known_peers) => known_peers,
+            Err(e) => e.into_inner(),
+        };
+        Self::walk_known_peers(&known_peers)
+    }
+
+    /// Unpin stored value with given key ID. Returns false if the value is not pinned
+    pub fn unpin_value(&self, key: &DhtKeyId) -> bool {
+        self.pinned_values.remove(key).is_some()
+    }
+
+    /// Unban DHT peer
+    pub fn unban_peer(&self, peer: &Arc<KeyId>) {
+        self.banned_peers.remove(peer);
+        // Unbanned peer is not held unreliable for the ban anymore
+        self.peer_db
+            .update(peer, |record| record.last_banned_at = 0);
+    }
+
+    /// Remove dead peers according to GC policy in options, returns number of removed peers
+    pub fn gc_peers(&self) -> Result<usize> {
+        let now = now();
+        let mut dead = HashSet::new();
+        for stats in self.peer_stats.iter() {
+            let too_old = self
+                .options
+                .max_peer_age
+                .map(|age| now - stats.value().last_seen_at > age)
+                .unwrap_or(false);
+            let too_faulty = self
+                .options
+                .max_peer_failures
+                .map(|failures| stats.value().failed_in_row >= failures)
+                .unwrap_or(false);
+            if (too_old || too_faulty) && !self.is_priority_peer(stats.key()) {
+                dead.insert(stats.key().clone());
+            }
+        }
+        if dead.is_empty() {
+            return Ok(0);
+        }
+        self.remove_known_peers(&dead)?;
+        log::debug!(target: TARGET, "Removed {} dead DHT peers", dead.len());
+        Ok(dead.len())
+    }
+
     /// Get known DHT nodes
     pub fn get_known_nodes(&self, limit: usize) -> Result<Vec<Node>> {
         if limit == 0 {
@@ -350,34 +1905,82 @@ impl DhtNode {
         }
         let mut ret = Vec::new();
         for i in 0..=255 {
-            if let Some(bucket) = self.buckets.get(&i) {
-                for node in bucket.value().iter() {
-                    ret.push(node.value().clone());
-                    if ret.len() == limit {
-                        return Ok(ret);
-                    }
+            for node in self.buckets.shard(i).values() {
+                ret.push(node.clone());
+                if ret.len() == limit {
+                    return Ok(ret);
                 }
             }
         }
         Ok(ret)
     }
 
-    /// Get signed address list
-    pub async fn get_signed_address_list(&self, dst: &Arc<KeyId>) -> Result<bool> {
+    /// Check if DHT peer is banned
+    pub fn is_banned(&self, peer: &Arc<KeyId>) -> bool {
+        self.banned_peers.contains_key(peer)
+    }
+
+    /// Get raw value with given DHT key ID stored on given peer
+    pub async fn get_value_from_peer(
+        &self,
+        peer: &Arc<KeyId>,
+        key: &DhtKeyId,
+    ) -> Result<Option<DhtValue>> {
+        let query = TLObject::new(rpc::dht::FindValue {
+            key: ton::int256(*key),
+            k: 6,
+        });
+        let answer = self.query(peer, &query).await?;
+        let answer: DhtValueResult = if let Some(answer) = answer {
+            Query::parse(answer, &query)?
+        } else {
+            return Ok(None);
+        };
+        match answer {
+            DhtValueResult::Dht_ValueFound(value) => Ok(Some(value.value.only())),
+            DhtValueResult::Dht_ValueNotFound(_) => Ok(None),
+        }
+    }
+
+    /// Get signed node of given peer, None if the peer has not answered
+    pub async fn get_signed_address_list(&self, dst: &Arc<KeyId>) -> Result<Option<Node>> {
         let query = TLObject::new(rpc::dht::GetSignedAddressList);
         let answer = self.query_with_prefix(dst, &query).await?;
-        let answer: NodeBoxed = if let Some(answer) = answer {
-            Query::parse(answer, &query)?
+        let node = if let Some(answer) = answer {
+            Query::parse::<TLObject, NodeBoxed>(answer, &query)?.only()
         } else {
-            return Ok(false);
+            return Ok(None);
         };
-        self.add_peer(&answer.only())?;
-        Ok(true)
+        if KeyOption::from_tl_public_key(&node.id)?.id() != dst {
+            fail!(DhtError::Verification(format!(
+                "Peer {} answered with node of another key",
+                dst
+            )))
+        }
+        if let AddPeerResult::RejectedBadSignature = self.add_peer(&node)? {
+            fail!(DhtError::Verification(format!(
+                "Bad signature of node of peer {}",
+                dst
+            )))
+        }
+        Ok(Some(node))
     }
 
     /// Get signed node
     pub fn get_signed_node(&self) -> Result<Node> {
-        self.sign_local_node()
+        Ok(self.local_node()?.node.clone())
+    }
+
+    /// Most read keys among incoming FindValue queries
+    pub fn hottest_keys(&self, limit: usize) -> Vec<KeyPopularity> {
+        let mut ret: Vec<_> = self
+            .key_reads
+            .iter()
+            .map(|reads| reads.value().clone())
+            .collect();
+        ret.sort_by(|a, b| b.reads().cmp(&a.reads()));
+        ret.truncate(limit);
+        ret
     }
 
     /// Node IP address
@@ -385,42 +1988,321 @@ impl DhtNode {
         self.adnl.ip_address()
     }
 
+    /// Join overlay: store own overlay node, then resolve up to `target` other members
+    /// and register them in ADNL as peers of the own overlay node key
+    pub async fn join_overlay(
+        dht: &Arc<Self>,
+        overlay_id: &OverlayId,
+        local_node: &OverlayNode,
+        target: usize,
+    ) -> Result<OverlayJoin> {
+        let report = Self::store_overlay_node(dht, overlay_id, local_node).await?;
+        let overlay_short_id = OverlayShortId::from_data(hash(Overlay {
+            name: ton::bytes(overlay_id.to_vec()),
+        })?);
+        let local_key = KeyOption::from_tl_public_key(&local_node.id)?;
+        let mut iter = None;
+        let mut peers = Vec::new();
+        let mut joined = HashSet::new();
+        loop {
+            let nodes =
+                Self::find_overlay_nodes(dht, &overlay_short_id, &mut iter, target + 1).await?;
+            for node in nodes {
+                if (peers.len() >= target)
+                    || (node.adnl_key.id() == local_key.id())
+                    || !joined.insert(node.adnl_key.id().clone())
+                {
+                    continue;
+                }
+                let peer_key = Arc::new(KeyOption::from_tl_public_key(&node.node.id)?);
+                if dht
+                    .adnl
+                    .add_peer(local_key.id(), &node.ip, &peer_key)?
+                    .is_some()
+                {
+                    peers.push(node)
+                }
+            }
+            if (peers.len() >= target) || iter.is_none() {
+                break;
+            }
+        }
+        log::debug!(
+            target: TARGET,
+            "Joined overlay {}: {} peers",
+            overlay_short_id,
+            peers.len()
+        );
+        Ok(OverlayJoin { report, peers })
+    }
+
     /// Node key
     pub fn key(&self) -> &Arc<KeyOption> {
         &self.node_key
     }
 
+    /// Peer statistics
+    pub fn peer_stats(&self, peer: &Arc<KeyId>) -> Option<PeerStats> {
+        self.peer_stats.get(peer).map(|stats| stats.value().clone())
+    }
+
+    /// Pin stored value with given key ID, so it is never evicted by ephemeral values
+    /// quota. Pinning does not extend the value past its signed TTL: expired pinned
+    /// values are not served like any other ones, and values of banned signers or
+    /// failing re-verification are purged anyway. Returns false if the value is
+    /// already pinned
+    pub fn pin_value(&self, key: &DhtKeyId) -> bool {
+        self.pinned_values.insert(*key, ()).is_none()
+    }
+
     /// Ping
     pub async fn ping(&self, dst: &Arc<KeyId>) -> Result<bool> {
+        Ok(self.ping_with_rtt(dst).await?.is_some())
+    }
+
+    /// Ping and measure round-trip time
+    pub async fn ping_with_rtt(&self, dst: &Arc<KeyId>) -> Result<Option<Duration>> {
         let random_id = rand::thread_rng().gen();
         let query = TLObject::new(rpc::dht::Ping { random_id });
+        let start = Instant::now();
         let answer = self.query(dst, &query).await?;
-        let answer: DhtPongBoxed = if let Some(answer) = answer {
-            Query::parse(answer, &query)?
+        let rtt = if let Some(answer) = answer {
+            let answer: DhtPongBoxed = Query::parse(answer, &query)?;
+            if answer.random_id() == &random_id {
+                Some(start.elapsed())
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        Ok(rtt)
+    }
+
+    /// Store own IP address signed by signing key set in options, or by node key
+    pub async fn publish_address(dht: &Arc<Self>) -> Result<StoreReport> {
+        let key = dht.signing_key.clone();
+        Self::store_ip_address(dht, &key).await
+    }
+
+    /// Send raw DHT query to given peer
+    pub async fn query_raw(&self, dst: &Arc<KeyId>, query: &TLObject) -> Result<Option<TLObject>> {
+        self.query(dst, query).await
+    }
+
+    /// Send raw DHT query to given peer, prefixed with own signed node
+    /// (unless the node is client-only)
+    pub async fn query_with_node_prefix_raw(
+        &self,
+        dst: &Arc<KeyId>,
+        query: &TLObject,
+    ) -> Result<Option<TLObject>> {
+        self.query_with_prefix(dst, query).await
+    }
+
+    /// Queue depth of incoming queries of given class
+    pub fn query_queue_depth(&self, class: QueryClass) -> QueueDepth {
+        self.query_pools.get(class).depth()
+    }
+
+    /// Re-sign local node with current address list, e.g. after address change
+    pub fn refresh_local_node(&self) -> Result<Node> {
+        Ok(self.update_local_node()?.node.clone())
+    }
+
+    /// Re-sign locally stored value with given name, owned by given key, with fresh TTL
+    /// and store it again, so the original payload needs not to be kept by caller
+    pub async fn refresh_value(
+        dht: &Arc<Self>,
+        name: &str,
+        key: &Arc<KeyOption>,
+    ) -> Result<StoreReport> {
+        let dht_key = dht.dht_key_from_key_id(key.id(), name);
+        let old_value = match dht.storage.get(&hash(dht_key.clone())?) {
+            Some(value) => value,
+            None => fail!(DhtError::NotFound(format!(
+                "No local value {} of {} to refresh",
+                name,
+                key.id()
+            ))),
+        };
+        if KeyOption::from_tl_public_key(&old_value.key.id)?.id() != key.id() {
+            fail!(DhtError::Rejected(format!(
+                "Local value {} of {} is signed by another key",
+                name,
+                key.id()
+            )))
+        }
+        let value = dht.sign_value(name, &old_value.value.0, key)?;
+        let ttl = value.ttl;
+        dht.process_store_signed_value(hash(dht_key.clone())?, value.clone())?;
+        let report = Self::store_value(
+            dht,
+            dht_key,
+            value,
+            |_| true,
+            false,
+            |objects| Ok(objects.iter().any(|found| found.ttl >= ttl)),
+        )
+        .await?;
+        if report.verified && (name == "address") {
+            dht.address_published_until.store(ttl, Ordering::Relaxed);
+        }
+        Ok(report)
+    }
+
+    /// Build value with given name signed by given key
+    pub fn sign_value(&self, name: &str, value: &[u8], key: &Arc<KeyOption>) -> Result<DhtValue> {
+        self.sign_indexed_value(name, 0, value, key, Self::TIMEOUT_VALUE)
+    }
+
+    /// Build value with given name signed by given key, expiring in `ttl` seconds
+    pub fn sign_value_with_ttl(
+        &self,
+        name: &str,
+        value: &[u8],
+        key: &Arc<KeyOption>,
+        ttl: i32,
+    ) -> Result<DhtValue> {
+        self.sign_indexed_value(name, 0, value, key, ttl)
+    }
+
+    /// Key signing values the node publishes about itself
+    pub fn signing_key(&self) -> &Arc<KeyOption> {
+        &self.signing_key
+    }
+
+    /// Stop background tasks and wait for spawned tasks to finish
+    pub async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        let joined = self.tasks.join(timeout).await;
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.flush().await {
+                log::warn!(target: TARGET, "Cannot flush DHT store log: {}", e)
+            }
+        }
+        if let Err(e) = self.peer_db.save() {
+            log::warn!(target: TARGET, "Cannot save DHT peer database: {}", e)
+        }
+        if joined {
+            Ok(())
         } else {
-            return Ok(false);
+            fail!(DhtError::Timeout)
+        }
+    }
+
+    /// Node status summary
+    pub fn status(&self) -> DhtStatus {
+        let mut buckets = Vec::new();
+        for i in 0..=255 {
+            let count = self.buckets.shard(i).len();
+            if count > 0 {
+                buckets.push((i, count))
+            }
+        }
+        let mut stored_signed = StorageUsage::default();
+        let mut stored_overlay_nodes = StorageUsage::default();
+        let mut stored_other = StorageUsage::default();
+        let version = now();
+        self.storage.for_each(|_, value| {
+            if value.ttl > version {
+                match value.key.update_rule {
+                    UpdateRule::Dht_UpdateRule_Signature => stored_signed.add(value),
+                    UpdateRule::Dht_UpdateRule_OverlayNodes => stored_overlay_nodes.add(value),
+                    _ => stored_other.add(value),
+                }
+            }
+        });
+        let timestamp = |time: &AtomicI32| match time.load(Ordering::Relaxed) {
+            0 => None,
+            x => Some(x),
         };
-        Ok(answer.random_id() == &random_id)
+        DhtStatus {
+            known_peers: self.known_peers().count(),
+            buckets,
+            stored_values: stored_signed.values + stored_overlay_nodes.values + stored_other.values,
+            stored_bytes: stored_signed.bytes + stored_overlay_nodes.bytes + stored_other.bytes,
+            stored_signed,
+            stored_overlay_nodes,
+            stored_other,
+            last_lookup_at: timestamp(&self.last_lookup_at),
+            last_store_at: timestamp(&self.last_store_at),
+            address_published: self.address_published_until.load(Ordering::Relaxed) > version,
+            find_value_hits: self.find_value_hits.load(Ordering::Relaxed),
+            find_value_misses: self.find_value_misses.load(Ordering::Relaxed),
+            purged_values: self.purged_values.load(Ordering::Relaxed),
+            rejections: self.rejections(),
+        }
     }
 
     /// Store own IP address
-    pub async fn store_ip_address(dht: &Arc<Self>, key: &Arc<KeyOption>) -> Result<bool> {
+    pub async fn store_ip_address(dht: &Arc<Self>, key: &Arc<KeyOption>) -> Result<StoreReport> {
+        Self::store_ip_address_with_ttl(dht, key, Self::TIMEOUT_VALUE).await
+    }
+
+    /// Store own IP address expiring in `ttl` seconds
+    pub async fn store_ip_address_with_ttl(
+        dht: &Arc<Self>,
+        key: &Arc<KeyOption>,
+        ttl: i32,
+    ) -> Result<StoreReport> {
+        let addr_list = dht.adnl.build_address_list(None)?;
+        Self::store_address_list_with_ttl(dht, key, addr_list, ttl).await
+    }
+
+    /// Store own external IP addresses (e.g. when behind NAT) instead of bound one
+    pub async fn store_external_ip_addresses(
+        dht: &Arc<Self>,
+        key: &Arc<KeyOption>,
+        ips: &[IpAddress],
+        priority: i32,
+    ) -> Result<StoreReport> {
+        if ips.is_empty() {
+            fail!("It is useless to store empty address list")
+        }
+        let mut addr_list = dht.adnl.build_address_list(None)?;
+        addr_list.addrs = ips
+            .iter()
+            .map(|ip| ip.into_udp().into_boxed())
+            .collect::<Vec<_>>()
+            .into();
+        addr_list.priority = priority;
+        Self::store_address_list(dht, key, addr_list).await
+    }
+
+    /// Store given address list as own address
+    pub async fn store_address_list(
+        dht: &Arc<Self>,
+        key: &Arc<KeyOption>,
+        addr_list: AddressList,
+    ) -> Result<StoreReport> {
+        Self::store_address_list_with_ttl(dht, key, addr_list, Self::TIMEOUT_VALUE).await
+    }
+
+    /// Store given address list as own address expiring in `ttl` seconds
+    pub async fn store_address_list_with_ttl(
+        dht: &Arc<Self>,
+        key: &Arc<KeyOption>,
+        addr_list: AddressList,
+        ttl: i32,
+    ) -> Result<StoreReport> {
         log::debug!(target: TARGET, "Storing key ID {}", key.id());
-        let value = serialize(&dht.adnl.build_address_list(None)?.into_boxed())?;
-        let value = Self::sign_value("address", &value[..], key)?;
-        let key = Self::dht_key_from_key_id(key.id(), "address");
+        let expected = parse_address_list(&addr_list)?;
+        let value = serialize(&addr_list.into_boxed())?;
+        let value = dht.sign_value_with_ttl("address", &value[..], key, ttl)?;
+        let ttl = value.ttl;
+        let key = dht.dht_key_from_key_id(key.id(), "address");
         dht.process_store_signed_value(hash(key.clone())?, value.clone())?;
-        Self::store_value(
+        let report = Self::store_value(
             dht,
             key,
             value,
             |object| object.is::<AddressListBoxed>(),
             false,
             |mut objects| {
-                while let Some((_, object)) = objects.pop() {
-                    if let Ok(addr_list) = object.downcast::<AddressListBoxed>() {
+                while let Some(found) = objects.pop() {
+                    if let Ok(addr_list) = found.object.downcast::<AddressListBoxed>() {
                         let ip = parse_address_list(&addr_list.only())?;
-                        if ip == dht.adnl.ip_address() {
+                        if ip == expected {
                             log::debug!(target: TARGET, "Checked stored address {:?}", ip);
                             return Ok(true);
                         } else {
@@ -428,7 +2310,7 @@ impl DhtNode {
                                 target: TARGET,
                                 "Found another stored address {:?}, expected {:?}",
                                 ip,
-                                dht.adnl.ip_address()
+                                expected
                             )
                         }
                     } else {
@@ -438,7 +2320,78 @@ impl DhtNode {
                 Ok(false)
             },
         )
-        .await
+        .await?;
+        if report.verified {
+            dht.address_published_until.store(ttl, Ordering::Relaxed);
+        }
+        Ok(report)
+    }
+
+    /// Store value on given peer only, returns whether the peer acknowledged it
+    pub async fn store_value_to_peer(&self, peer: &Arc<KeyId>, value: DhtValue) -> Result<bool> {
+        let query = TLObject::new(rpc::dht::Store { value });
+        let answer = self.query(peer, &query).await?;
+        if let Some(answer) = answer {
+            let _: Stored = Query::parse(answer, &query)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Store own overlay node and keep re-storing it with fresh version until cancelled
+    pub async fn announce_overlay_node(
+        dht: &Arc<Self>,
+        overlay_id: &OverlayId,
+        key: &Arc<KeyOption>,
+    ) -> Result<(StoreReport, OverlayNodeAnnouncement)> {
+        let overlay_short_id = OverlayShortId::from_data(hash(Overlay {
+            name: ton::bytes(overlay_id.to_vec()),
+        })?);
+        let node = Self::sign_overlay_node(&overlay_short_id, key)?;
+        let report = Self::store_overlay_node(dht, overlay_id, &node).await?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let announcement = OverlayNodeAnnouncement {
+            stopped: stopped.clone(),
+        };
+        let weak_dht = Arc::downgrade(dht);
+        let overlay_id = overlay_id.clone();
+        let key = key.clone();
+        let tasks = dht.tasks.clone();
+        dht.spawn(async move {
+            loop {
+                let running = tasks
+                    .sleep(Duration::from_secs(Self::TIMEOUT_ANNOUNCE))
+                    .await;
+                if !running || stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                let result = match Self::sign_overlay_node(&overlay_short_id, &key) {
+                    Ok(node) => Self::store_overlay_node(&dht, &overlay_id, &node).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(report) => log::debug!(
+                        target: TARGET,
+                        "Reannounced overlay node in {}: {} acks",
+                        overlay_short_id,
+                        report.acks()
+                    ),
+                    Err(e) => log::warn!(
+                        target: TARGET,
+                        "Cannot reannounce overlay node in {}: {}",
+                        overlay_short_id,
+                        e
+                    ),
+                }
+            }
+        });
+        Ok((report, announcement))
     }
 
     /// Store own overlay node
@@ -446,7 +2399,17 @@ impl DhtNode {
         dht: &Arc<Self>,
         overlay_id: &OverlayId,
         node: &OverlayNode,
-    ) -> Result<bool> {
+    ) -> Result<StoreReport> {
+        Self::store_overlay_node_with_ttl(dht, overlay_id, node, Self::TIMEOUT_VALUE).await
+    }
+
+    /// Store own overlay node expiring in `ttl` seconds
+    pub async fn store_overlay_node_with_ttl(
+        dht: &Arc<Self>,
+        overlay_id: &OverlayId,
+        node: &OverlayNode,
+        ttl: i32,
+    ) -> Result<StoreReport> {
         log::debug!(target: TARGET, "Storing overlay node {:?}", node);
         let overlay_id = Overlay {
             name: ton::bytes(overlay_id.to_vec()),
@@ -457,7 +2420,7 @@ impl DhtNode {
             nodes: vec![node.clone()].into(),
         }
         .into_boxed();
-        let key = Self::dht_key_from_key_id(&overlay_short_id, "nodes");
+        let key = dht.dht_key_from_key_id(&overlay_short_id, "nodes");
         let value = DhtValue {
             key: DhtKeyDescription {
                 id: overlay_id.into_boxed(),
@@ -465,7 +2428,7 @@ impl DhtNode {
                 signature: ton::bytes::default(),
                 update_rule: UpdateRule::Dht_UpdateRule_OverlayNodes,
             },
-            ttl: now() + Self::TIMEOUT_VALUE,
+            ttl: now() + ttl,
             signature: ton::bytes::default(),
             value: ton::bytes(serialize(&nodes)?),
         };
@@ -477,8 +2440,8 @@ impl DhtNode {
             |object| object.is::<OverlayNodesBoxed>(),
             true,
             |mut objects| {
-                while let Some((_, object)) = objects.pop() {
-                    if let Ok(nodes_list) = object.downcast::<OverlayNodesBoxed>() {
+                while let Some(found) = objects.pop() {
+                    if let Ok(nodes_list) = found.object.downcast::<OverlayNodesBoxed>() {
                         for found_node in nodes_list.only().nodes.0 {
                             if &found_node == node {
                                 log::debug!(target: TARGET, "Checked stored node {:?}", node);
@@ -498,33 +2461,95 @@ impl DhtNode {
     fn deserialize_overlay_nodes(value: &[u8]) -> Result<Vec<OverlayNode>> {
         let nodes = deserialize(value)?
             .downcast::<OverlayNodesBoxed>()
-            .map_err(|object| error!("Wrong OverlayNodes: {:?}", object))?;
+            .map_err(|object| {
+                error!(DhtError::Malformed(format!(
+                    "Wrong OverlayNodes: {:?}",
+                    object
+                )))
+            })?;
         Ok(nodes.only().nodes.0)
     }
 
-    fn dht_key_from_key_id(id: &Arc<KeyId>, name: &str) -> DhtKey {
-        DhtKey {
-            id: ton::int256(*id.data()),
-            idx: 0,
-            name: ton::bytes(name.as_bytes().to_vec()),
-        }
-    }
-
+    fn dht_key_from_key_id(&self, id: &Arc<KeyId>, name: &str) -> DhtKey {
+        self.dht_key_builder(id, name).build()
+    }
+
     async fn find_value(
         dht: &Arc<Self>,
         key: DhtKey,
         check: impl Fn(&TLObject) -> bool + Copy + Send + 'static,
         all: bool,
         iter_opt: &mut Option<AddressCacheIterator>,
-    ) -> Result<Vec<(DhtKeyDescription, TLObject)>> {
-        let mut current = dht.get_known_peer(iter_opt);
+    ) -> Result<(Vec<FoundValue>, LookupStats)> {
+        Self::lookup_value(dht, key, check, all, true, iter_opt, None).await
+    }
+
+    /// Look value up in network. Unless `local_reads` is set, neither local storage
+    /// nor cache of remote values answer the lookup
+    async fn lookup_value(
+        dht: &Arc<Self>,
+        key: DhtKey,
+        check: impl Fn(&TLObject) -> bool + Copy + Send + 'static,
+        all: bool,
+        local_reads: bool,
+        iter_opt: &mut Option<AddressCacheIterator>,
+        trace: Option<Arc<TraceSink>>,
+    ) -> Result<(Vec<FoundValue>, LookupStats)> {
         let mut ret = Vec::new();
+        let mut stats = LookupStats::default();
+        if !all && local_reads && dht.options.local_first_reads {
+            let key = hash(key.clone())?;
+            if let Some(value) = dht.search_published_key(&key) {
+                let object = deserialize(&value.value.0)?;
+                if check(&object) {
+                    log_event!(
+                        debug,
+                        "lookup",
+                        key = base64::encode(&key[..]),
+                        query = "find_value",
+                        outcome = "local"
+                    );
+                    ret.push(Self::found_value(dht.node_key.id(), value, object));
+                    return Ok((ret, stats));
+                }
+            }
+        }
+        let known_peers = dht.known_peers();
+        let mut current = dht.get_known_peer(iter_opt);
         let iter = if let Some(ref mut iter) = iter_opt {
             iter
         } else {
-            return Ok(ret);
+            return Ok((ret, stats));
         };
         let key = hash(key)?;
+        if dht.is_missing_key(&key) {
+            log_event!(
+                debug,
+                "lookup",
+                key = base64::encode(&key[..]),
+                query = "find_value",
+                outcome = "missing"
+            );
+            iter_opt.take();
+            return Ok((ret, stats));
+        }
+        dht.update_value_lookups(&key);
+        if !all && local_reads {
+            if let Some(value) = dht.cached_value(&key) {
+                let object = deserialize(&value.value.0)?;
+                if check(&object) {
+                    log_event!(
+                        debug,
+                        "lookup",
+                        key = base64::encode(&key[..]),
+                        query = "find_value",
+                        outcome = "cached"
+                    );
+                    ret.push(Self::found_value(dht.node_key.id(), value, object));
+                    return Ok((ret, stats));
+                }
+            }
+        }
         let query = TLObject::new(rpc::dht::FindValue {
             key: ton::int256(key),
             k: 6,
@@ -532,42 +2557,113 @@ impl DhtNode {
         let key = Arc::new(key);
         let query = Arc::new(query);
         let (wait, mut queue_reader) = Wait::new();
-        log::debug!(
-            target: TARGET,
-            "FindValue with DHT key ID {} query {:?} of {}",
-            base64::encode(&key[..]),
-            iter,
-            dht.known_peers.count()
+        log_event!(
+            debug,
+            "lookup",
+            key = base64::encode(&key[..]),
+            query = "find_value",
+            outcome = "started",
+            peers = known_peers.count()
         );
-        loop {
-            while let Some(peer) = current {
-                let dht_cloned = dht.clone();
-                let key = key.clone();
-                let peer = peer.clone();
-                let query = query.clone();
-                let wait = wait.clone();
-                let reqs = wait.request();
-                tokio::spawn(async move {
+        let max_hops = dht.options.max_lookup_hops;
+        let max_peers = dht.options.max_lookup_peers;
+        let mut visited = HashSet::new();
+        // Unreliable peers are queried only after all others
+        let mut deferred = Vec::new();
+        let spawn_query = |peer: &Arc<KeyId>, hop: u32| {
+            let dht_cloned = dht.clone();
+            let key = key.clone();
+            let peer = peer.clone();
+            let query = query.clone();
+            let wait = wait.clone();
+            let trace = trace.clone();
+            let sent_at = trace.as_ref().map(|trace| trace.start.elapsed());
+            let reqs = wait.request();
+            dht.spawn(async move {
+                let (response, found) =
                     match dht_cloned.value_query(&peer, &query, &key, check).await {
-                        Ok(found) => wait.respond(found),
+                        Ok(answer) => answer,
                         Err(e) => {
                             log::warn!(target: TARGET, "ERROR: {}", e);
-                            wait.respond(None)
+                            (PeerResponse::Error, None)
                         }
-                    }
-                });
-                current = dht.known_peers.next(iter);
-                if reqs >= Self::MAX_TASKS {
+                    };
+                dht_cloned.graph_query(&peer, response);
+                if let (Some(trace), Some(sent_at)) = (trace, sent_at) {
+                    trace.record(peer, hop, sent_at, response)
+                }
+                wait.respond(found)
+            });
+            reqs
+        };
+        // Priority peers are always queried first
+        for peer in dht.priority_peers() {
+            if dht.is_banned(&peer) || !visited.insert(peer.clone()) {
+                continue;
+            }
+            stats.contacted += 1;
+            spawn_query(&peer, stats.hops);
+        }
+        if dht.options.lookup_warm_start {
+            // Start from peers which recently had values with nearby keys
+            for peer in dht.lookup_shortlist(&key) {
+                if dht.is_banned(&peer)
+                    || dht.is_backed_off(&peer)
+                    || dht.peer_db.is_unreliable(&peer)
+                    || !visited.insert(peer.clone())
+                {
+                    continue;
+                }
+                stats.contacted += 1;
+                if spawn_query(&peer, stats.hops) >= Self::MAX_TASKS {
                     break;
                 }
             }
-            log::debug!(
-                target: TARGET,
-                "FindValue with DHT key ID {} query, {} parallel reqs, iter {:?} of {}",
-                base64::encode(&key[..]),
-                wait.count(),
-                iter,
-                dht.known_peers.count()
+        }
+        loop {
+            let limited = (stats.hops >= max_hops) || (stats.contacted >= max_peers);
+            if !limited {
+                while let Some(peer) = current {
+                    if !visited.insert(peer.clone())
+                        || dht.is_backed_off(&peer)
+                        || dht.is_banned(&peer)
+                    {
+                        // Each peer is queried at most once per lookup,
+                        // peers in backoff and banned ones are not queried at all
+                        current = known_peers.next(iter);
+                        continue;
+                    }
+                    if dht.peer_db.is_unreliable(&peer) {
+                        deferred.push(peer);
+                        current = known_peers.next(iter);
+                        continue;
+                    }
+                    let reqs = spawn_query(&peer, stats.hops);
+                    stats.contacted += 1;
+                    current = known_peers.next(iter);
+                    if (reqs >= Self::MAX_TASKS) || (stats.contacted >= max_peers) {
+                        break;
+                    }
+                }
+                if current.is_none() {
+                    while let Some(peer) = deferred.pop() {
+                        let reqs = spawn_query(&peer, stats.hops);
+                        stats.contacted += 1;
+                        if (reqs >= Self::MAX_TASKS) || (stats.contacted >= max_peers) {
+                            break;
+                        }
+                    }
+                }
+                stats.hops += 1;
+            }
+            log_event!(
+                trace,
+                "lookup",
+                key = base64::encode(&key[..]),
+                query = "find_value",
+                outcome = "round",
+                requests = wait.count(),
+                hops = stats.hops
             );
             let mut finished = false;
             loop {
@@ -584,17 +2680,44 @@ impl DhtNode {
                 }
             }
             // Stop if possible
-            if (all && (ret.len() >= Self::MAX_TASKS)) || (!all && !ret.is_empty()) || finished {
+            if (all && (ret.len() >= Self::MAX_TASKS))
+                || (!all && !ret.is_empty())
+                || (finished && (limited || deferred.is_empty()))
+            {
                 break;
             }
             if current.is_none() {
-                current = dht.known_peers.given(iter);
+                current = known_peers.given(iter);
             }
         }
         if current.is_none() {
             iter_opt.take();
+            if ret.is_empty() {
+                dht.remember_missing_key(&key)
+            }
         }
-        Ok(ret)
+        if !ret.is_empty() {
+            dht.last_lookup_at.store(now(), Ordering::Relaxed);
+            if dht.options.lookup_warm_start {
+                for found in ret.iter() {
+                    dht.update_lookup_shortlist(&key, &found.peer)
+                }
+            }
+        }
+        if all {
+            ret.sort_by(Self::prefer_newest);
+        }
+        log_event!(
+            debug,
+            "lookup",
+            key = base64::encode(&key[..]),
+            query = "find_value",
+            outcome = "done",
+            values = ret.len(),
+            hops = stats.hops,
+            contacted = stats.contacted
+        );
+        Ok((ret, stats))
     }
 
     fn parse_value_as_address(
@@ -606,45 +2729,103 @@ impl DhtNode {
             let key = KeyOption::from_tl_public_key(&key.id)?;
             Ok((ip_address, key))
         } else {
-            fail!("Address list type mismatch in DHT search")
+            fail!(DhtError::Malformed(
+                "Address list type mismatch in DHT search".to_string()
+            ))
+        }
+    }
+
+    fn found_value(peer: &Arc<KeyId>, value: DhtValue, object: TLObject) -> FoundValue {
+        let (object, version) = match object.downcast::<AddressListBoxed>() {
+            Ok(addr_list) => {
+                let version = *addr_list.version();
+                (TLObject::new(addr_list), Some(version))
+            }
+            Err(object) => (object, None),
+        };
+        FoundValue {
+            key: value.key,
+            object,
+            peer: peer.clone(),
+            ttl: value.ttl,
+            version,
         }
     }
 
+    fn known_peers(&self) -> Arc<AddressCache> {
+        match self.known_peers.read() {
+            Ok(known_peers) => known_peers.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    /// Cached local node, re-signed if address list has changed or signature is too old
+    fn local_node(&self) -> Result<Arc<LocalNode>> {
+        let local_node = match self.local_node.read() {
+            Ok(local_node) => local_node.clone(),
+            Err(e) => e.into_inner().clone(),
+        };
+        let addr_list = self.adnl.build_address_list(None)?;
+        let timeout = match self.options.query_prefix {
+            QueryPrefix::LocalNode(timeout) => timeout,
+            _ => Self::TIMEOUT_LOCAL_NODE,
+        };
+        if (local_node.node.version + timeout > now())
+            && (local_node.node.addr_list.addrs == addr_list.addrs)
+        {
+            return Ok(local_node);
+        }
+        self.update_local_node()
+    }
+
+    /// Log removal of value, so it is not restored from store log on restart
+    fn log_removed_value(&self, value: &DhtValue) {
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.remove(value) {
+                log::warn!(target: TARGET, "Cannot log removal of DHT value: {}", e)
+            }
+        }
+    }
+
+    fn log_stored_value(&self, dht_key_id: &DhtKeyId) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            if let Some(value) = self.storage.get(dht_key_id) {
+                if let Some(value) = self.stored_payload(dht_key_id, value) {
+                    wal.append(&value)?
+                }
+            }
+            if wal.needs_compaction(self.storage.len()) {
+                wal.compact()?
+            }
+        }
+        Ok(())
+    }
+
     fn process_find_node(&self, query: &rpc::dht::FindNode) -> Result<Nodes> {
         log::trace!(target: TARGET, "Process FindNode query {:?}", query);
-        let key1 = self.node_key.id().data();
-        let key2 = get256(&query.key);
-        let mut dist = 0u8;
+        // Query with k <= 0 gets default number of nodes, not all known ones
+        let k = self.answer_k(query.k);
         let mut ret = Vec::new();
-        for i in 0..32 {
-            if ret.len() == query.k as usize {
+        for bucket in distance::differing_bits(self.node_key.id().data(), get256(&query.key)) {
+            if ret.len() >= k {
                 break;
             }
-            let mut subdist = dist;
-            let mut xor = key1[i] ^ key2[i];
-            while xor != 0 {
-                if (xor & 0xF0) == 0 {
-                    subdist = subdist.saturating_add(4);
-                    xor <<= 4;
-                } else {
-                    let shift = Self::BITS[(xor >> 4) as usize];
-                    subdist = subdist.saturating_add(shift);
-                    if let Some(bucket) = self.buckets.get(&subdist) {
-                        for node in bucket.value().iter() {
-                            ret.push(node.value().clone());
-                            if ret.len() == query.k as usize {
-                                break;
-                            }
-                        }
-                    }
-                    xor <<= shift + 1;
-                    subdist = subdist.saturating_add(1);
-                }
-                if ret.len() == query.k as usize {
+            for node in self.buckets.shard(bucket).values() {
+                if ret.len() >= k {
                     break;
                 }
+                ret.push(node.clone())
             }
-            dist = dist.saturating_add(8);
+        }
+        // Client-only node never puts itself into answers
+        let client_only = self.options.role == DhtRole::ClientOnly;
+        if self.options.advertise_local_node && !client_only {
+            self.advertise_local_node(&mut ret, k)?
+        } else if self.options.include_local_node && !client_only {
+            self.include_local_node(&mut ret, get256(&query.key), k)?
+        }
+        if let Some(max_size) = self.options.max_answer_size {
+            ret = self.fit_nodes(ret, max_size)?
         }
         let ret = Nodes { nodes: ret.into() };
         log::trace!(target: TARGET, "FindNode result {:?}", ret);
@@ -653,15 +2834,37 @@ impl DhtNode {
 
     fn process_find_value(&self, query: &rpc::dht::FindValue) -> Result<DhtValueResult> {
         log::trace!(target: TARGET, "Process FindValue query {:?}", query);
-        let ret = if let Some(value) = self.search_dht_key(get256(&query.key)) {
+        let key = get256(&query.key);
+        let value = match self.namespaces.find(key)? {
+            None => self.search_dht_key(key),
+            value => value,
+        };
+        self.update_key_reads(key, value.is_some());
+        let value = match value {
+            None if self.options.serve_cached_values => self.cached_value(key),
+            value => value,
+        };
+        let max_size = self.options.max_answer_size;
+        let ret = if let Some(mut value) = value {
+            if let Some(max_size) = max_size {
+                value = self.fit_value(value, max_size)?
+            }
             ValueFound {
                 value: value.into_boxed(),
             }
             .into_boxed()
         } else {
+            let k = self.answer_k(query.k);
+            let mut nodes = self.get_known_nodes(k)?;
+            if self.options.advertise_local_node && (self.options.role != DhtRole::ClientOnly) {
+                self.advertise_local_node(&mut nodes, k)?
+            }
+            if let Some(max_size) = max_size {
+                nodes = self.fit_nodes(nodes, max_size)?
+            }
             ValueNotFound {
                 nodes: Nodes {
-                    nodes: self.get_known_nodes(query.k as usize)?.into(),
+                    nodes: nodes.into(),
                 },
             }
             .into_boxed()
@@ -676,57 +2879,582 @@ impl DhtNode {
         })
     }
 
-    fn process_store(&self, query: rpc::dht::Store) -> Result<Stored> {
-        let dht_key_id = hash(query.value.key.key.clone())?;
+    async fn process_query(&self, object: TLObject, peer: &Arc<KeyId>) -> Result<QueryResult> {
+        let received = self.incoming_query_len(&object);
+        let object = match object.downcast::<rpc::dht::Ping>() {
+            Ok(query) => {
+                let answer = self
+                    .query_pools
+                    .run(QueryClass::Routing, move |dht| dht.process_ping(&query))
+                    .await?
+                    .into_boxed();
+                self.account_served_query(peer, "ping", received, &answer);
+                return QueryResult::consume_boxed(answer);
+            }
+            Err(object) => object,
+        };
+        let object = match object.downcast::<rpc::dht::FindNode>() {
+            Ok(query) => {
+                let answer = self
+                    .query_pools
+                    .run(QueryClass::Routing, move |dht| {
+                        dht.process_find_node(&query)
+                    })
+                    .await?
+                    .into_boxed();
+                self.account_served_query(peer, "find_node", received, &answer);
+                return QueryResult::consume_boxed(answer);
+            }
+            Err(object) => object,
+        };
+        let object = match object.downcast::<rpc::dht::FindValue>() {
+            Ok(query) => {
+                let answer = self
+                    .query_pools
+                    .run(QueryClass::Lookup, move |dht| {
+                        dht.process_find_value(&query)
+                    })
+                    .await?;
+                self.account_served_query(peer, "find_value", received, &answer);
+                return QueryResult::consume_boxed(answer);
+            }
+            Err(object) => object,
+        };
+        let object = match object.downcast::<rpc::dht::GetSignedAddressList>() {
+            Ok(_) => {
+                let answer = self
+                    .query_pools
+                    .run(QueryClass::Routing, |dht| dht.get_signed_node())
+                    .await?
+                    .into_boxed();
+                self.account_served_query(peer, "get_signed_address_list", received, &answer);
+                return QueryResult::consume_boxed(answer);
+            }
+            Err(object) => object,
+        };
+        match object.downcast::<rpc::dht::Store>() {
+            Ok(query) => {
+                let sender = peer.clone();
+                let answer = self
+                    .query_pools
+                    .run(QueryClass::Store, move |dht| {
+                        dht.process_store(query, &sender)
+                    })
+                    .await?;
+                self.account_served_query(peer, "store", received, &answer);
+                QueryResult::consume_boxed(answer)
+            }
+            Err(object) => {
+                self.reject_query(peer, "unknown", RejectReason::UnsupportedQuery);
+                Ok(QueryResult::Rejected(object))
+            }
+        }
+    }
+
+    fn process_store(&self, query: rpc::dht::Store, peer: &Arc<KeyId>) -> Result<Stored> {
+        let dht_key_id = self.hash_key(&query.value.key.key)?;
         if query.value.ttl <= now() {
-            fail!(
+            fail!(DhtError::Verification(format!(
                 "Ignore expired DHT value with key {}",
                 base64::encode(&dht_key_id)
-            )
+            )))
+        }
+        if let Some(limits) = &self.options.strict_validation {
+            limits.check_value(&query.value)?
+        }
+        if self.options.strict_network {
+            self.check_network(&query.value.key.key)?
+        }
+        if let Some(whitelist) = &self.options.store_whitelist {
+            self.check_store_whitelist(whitelist, &query.value)?
+        }
+        if let UpdateRule::Dht_UpdateRule_Signature = query.value.key.update_rule {
+            let owner = KeyOption::from_tl_public_key(&query.value.key.id)?;
+            if self.is_banned(owner.id()) {
+                fail!(DhtError::Rejected(format!(
+                    "DHT value with key {} is signed by banned key {}",
+                    base64::encode(&dht_key_id),
+                    owner.id()
+                )))
+            }
+        }
+        let ephemeral = match &self.options.ephemeral_values {
+            Some(ephemeral) if ephemeral.contains(&query.value.key.key.name) => {
+                if query.value.ttl > now() + ephemeral.max_ttl {
+                    fail!(DhtError::Rejected(format!(
+                        "Too long TTL of ephemeral DHT value with key {}",
+                        base64::encode(&dht_key_id)
+                    )))
+                }
+                Some(ephemeral.max_values)
+            }
+            _ => None,
+        };
+        let audit = self.store_audit_record(peer, &query.value)?;
+        if !self.namespaces.is_empty() {
+            if let Some(handler) = self.namespaces.handler(&query.value.key.key.name) {
+                if handler.store(&dht_key_id, &query.value)? {
+                    self.audit_store(audit);
+                    return Ok(Stored::Dht_Stored);
+                }
+            }
+        }
+        let max_ttl = match query.value.key.update_rule {
+            UpdateRule::Dht_UpdateRule_Signature => self.options.max_signed_value_ttl,
+            UpdateRule::Dht_UpdateRule_OverlayNodes => self.options.max_overlay_nodes_ttl,
+            _ => None,
+        };
+        if let Some(max_ttl) = max_ttl {
+            if query.value.ttl > now() + max_ttl {
+                fail!(DhtError::Rejected(format!(
+                    "Too long TTL of DHT value with key {}: {}",
+                    base64::encode(&dht_key_id),
+                    query.value.ttl
+                )))
+            }
+        }
+        let advertised = Self::advertised_address(&query.value);
+        if let Some((_, addr_list)) = &advertised {
+            if self.ip_index.is_banned(addr_list) {
+                fail!(DhtError::Rejected(format!(
+                    "DHT value with key {} advertises banned IP address",
+                    base64::encode(&dht_key_id)
+                )))
+            }
         }
-        match query.value.key.update_rule {
+        let stored = match query.value.key.update_rule {
             UpdateRule::Dht_UpdateRule_Signature => {
                 self.process_store_signed_value(dht_key_id, query.value)?
             }
             UpdateRule::Dht_UpdateRule_OverlayNodes => {
                 self.process_store_overlay_nodes(dht_key_id, query.value)?
             }
-            _ => fail!("Unsupported store query {:?}", query),
+            _ => fail!(DhtError::UnsupportedRule(format!(
+                "Unsupported store query {:?}",
+                query
+            ))),
+        };
+        if stored {
+            self.audit_store(audit);
+            if let Some((owner, addr_list)) = advertised {
+                self.ip_index.insert(&owner, &addr_list)
+            }
+            #[cfg(feature = "testing")]
+            self.fault_corrupt_ttl(&dht_key_id);
+            self.missing_keys.remove(&dht_key_id);
+            // Cached remote value is older than just stored one
+            self.value_cache.remove(&dht_key_id);
+            if let Some(max_values) = ephemeral {
+                self.track_ephemeral_value(&dht_key_id, max_values)
+            } else {
+                self.log_stored_value(&dht_key_id)?
+            }
+        }
+        Ok(Stored::Dht_Stored)
+    }
+
+    /// Add peer suggested by other peer: it is checked inline, while ADNL registration
+    /// is deferred to background. New peers and new versions of known ones get into
+    /// routing table only once ADNL accepts them, so lookups never query unregistered
+    /// peers. Returned result is the expected one
+    fn add_suggested_peer(&self, peer: &Node) -> Result<AddPeerResult> {
+        let key = Arc::new(KeyOption::from_tl_public_key(&peer.id)?);
+        if let Some(rejected) = self.check_peer(&key, peer) {
+            return Ok(rejected);
+        }
+        let result = self.expected_insert(key.id(), peer);
+        match result {
+            AddPeerResult::Added(_) | AddPeerResult::UpdatedVersion(_) => {
+                if self.registrations.push(key, peer) {
+                    let registrations = self.registrations.clone();
+                    self.spawn(async move { registrations.drain() })
+                }
+            }
+            AddPeerResult::AlreadyKnown(_) => {
+                self.insert_peer(key.id(), peer)?;
+            }
+            _ => (),
+        }
+        Ok(result)
+    }
+
+    fn advertise_local_node(&self, nodes: &mut Vec<Node>, k: usize) -> Result<()> {
+        let local_node = self.local_node()?;
+        nodes.retain(|node| node.id != local_node.node.id);
+        nodes.insert(0, local_node.node.clone());
+        nodes.truncate(k.max(1));
+        Ok(())
+    }
+
+    /// Number of nodes to answer with: requested k of zero or below means default
+    /// number, positive one is clamped by max number
+    fn answer_k(&self, requested: i32) -> usize {
+        if requested <= 0 {
+            self.options.answer_k_default
+        } else {
+            (requested as usize).min(self.options.answer_k_max)
+        }
+    }
+
+    fn cache_value(&self, key: &DhtKeyId, value: &DhtValue) {
+        if !self.options.cache_popular_values {
+            return;
+        }
+        // Newer version of cached value replaces it whatever its popularity
+        if let Some(mut cached) = self.value_cache.get_mut(key) {
+            if cached.ttl < value.ttl {
+                *cached = value.clone()
+            }
+            return;
+        }
+        let lookups = self.value_lookups.get(key).map(|lookups| *lookups.value());
+        if lookups.unwrap_or(0) < Self::MIN_LOOKUPS_TO_CACHE {
+            return;
+        }
+        if self.value_cache.len() >= Self::MAX_CACHED_VALUES {
+            let version = now();
+            self.value_cache.retain(|_, value| value.ttl > version);
+            if self.value_cache.len() >= Self::MAX_CACHED_VALUES {
+                return;
+            }
+        }
+        let mut cached = self
+            .value_cache
+            .entry(*key)
+            .or_insert_with(|| value.clone());
+        if cached.ttl < value.ttl {
+            *cached = value.clone()
+        }
+    }
+
+    fn cached_value(&self, key: &DhtKeyId) -> Option<DhtValue> {
+        let version = now();
+        let value = self.value_cache.get(key).map(|value| value.value().clone());
+        match value {
+            Some(value) if value.ttl > version => Some(value),
+            Some(_) => {
+                self.value_cache.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn check_network(&self, key: &DhtKey) -> Result<()> {
+        if let Some(network_id) = &self.options.network_id {
+            let suffix = format!(":{}", hex::encode(network_id));
+            if !key.name.ends_with(suffix.as_bytes()) {
+                fail!(DhtError::Rejected(format!(
+                    "DHT key {:?} is from another network",
+                    key
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn check_peer(&self, key: &Arc<KeyOption>, peer: &Node) -> Option<AddPeerResult> {
+        if self.is_banned(key.id()) || self.ip_index.is_banned(&peer.addr_list) {
+            log_event!(debug, "add_peer", peer = key.id(), outcome = "banned");
+            return Some(AddPeerResult::RejectedBanned);
+        }
+        if let Some(limits) = &self.options.strict_validation {
+            if let Err(e) = limits.check_node(peer) {
+                log_event!(
+                    debug,
+                    "add_peer",
+                    peer = key.id(),
+                    outcome = "malformed",
+                    error = e
+                );
+                return Some(AddPeerResult::RejectedMalformed);
+            }
+        }
+        if !self.options.address_policy.allows(&peer.addr_list) {
+            log_event!(
+                debug,
+                "add_peer",
+                peer = key.id(),
+                outcome = "address_policy"
+            );
+            return Some(AddPeerResult::RejectedByPolicy);
+        }
+        if let Err(e) = self.verify_other_node(peer) {
+            log_event!(
+                warn,
+                "add_peer",
+                peer = key.id(),
+                outcome = "bad_signature",
+                error = e
+            );
+            return Some(AddPeerResult::RejectedBadSignature);
+        }
+        None
+    }
+
+    fn check_store_whitelist(&self, whitelist: &StoreWhitelist, value: &DhtValue) -> Result<()> {
+        let signers = match value.key.update_rule {
+            UpdateRule::Dht_UpdateRule_OverlayNodes => {
+                // Overlay nodes are signed by nodes themselves
+                let mut signers = Vec::new();
+                for node in Self::deserialize_overlay_nodes(&value.value)? {
+                    signers.push(KeyOption::from_tl_public_key(&node.id)?.id().clone())
+                }
+                signers
+            }
+            _ => vec![KeyOption::from_tl_public_key(&value.key.id)?.id().clone()],
+        };
+        for signer in signers.iter() {
+            if !whitelist.allows(signer) {
+                fail!(DhtError::Rejected(format!(
+                    "Key {} is not whitelisted to store values",
+                    signer
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn drops_values(&self, peer: &Arc<KeyId>) -> bool {
+        self.peer_stats
+            .get(peer)
+            .map(|stats| stats.value().drops_values())
+            .unwrap_or(false)
+    }
+
+    /// Result which inserting peer into routing table would have
+    fn expected_insert(&self, peer_id: &Arc<KeyId>, peer: &Node) -> AddPeerResult {
+        let dist = distance::distance(self.node_key.id().data(), peer_id.data());
+        match self.buckets.shard(dist).get(peer_id) {
+            None => AddPeerResult::Added(peer_id.clone()),
+            Some(known) if known.version < peer.version => {
+                AddPeerResult::UpdatedVersion(peer_id.clone())
+            }
+            Some(known) if known.version == peer.version => {
+                AddPeerResult::AlreadyKnown(peer_id.clone())
+            }
+            Some(_) => AddPeerResult::RejectedStaleAddress,
+        }
+    }
+
+    fn filter_stored_key(&self, key: &DhtKeyId) {
+        if let Some(key_filter) = &self.key_filter {
+            key_filter.insert(key)
+        }
+    }
+
+    fn fit_nodes(&self, mut nodes: Vec<Node>, max_size: usize) -> Result<Vec<Node>> {
+        let mut size = Self::ANSWER_OVERHEAD;
+        let mut count = 0;
+        for node in nodes.iter() {
+            size += self.buffers.serialize(&node.clone().into_boxed())?.len();
+            if size > max_size {
+                break;
+            }
+            count += 1;
+        }
+        if count < nodes.len() {
+            log::debug!(
+                target: TARGET,
+                "Answer is limited to {} of {} nodes",
+                count,
+                nodes.len()
+            );
+            nodes.truncate(count)
+        }
+        Ok(nodes)
+    }
+
+    fn fit_value(&self, mut value: DhtValue, max_size: usize) -> Result<DhtValue> {
+        let size =
+            self.buffers.serialize(&value.clone().into_boxed())?.len() + Self::ANSWER_OVERHEAD;
+        if size <= max_size {
+            return Ok(value);
+        }
+        if let UpdateRule::Dht_UpdateRule_OverlayNodes = value.key.update_rule {
+            // Overlay nodes are signed one by one, so the list may be trimmed
+            let mut nodes = Self::deserialize_overlay_nodes(&value.value)?;
+            nodes.sort_by(|a, b| b.version.cmp(&a.version));
+            let mut budget = max_size.saturating_sub(size - value.value.len());
+            let mut count = 0;
+            for node in nodes.iter() {
+                let len = self.buffers.serialize(&node.clone().into_boxed())?.len();
+                if len + Self::ANSWER_OVERHEAD > budget {
+                    break;
+                }
+                budget -= len;
+                count += 1;
+            }
+            log::debug!(
+                target: TARGET,
+                "Answer is limited to {} of {} overlay nodes",
+                count,
+                nodes.len()
+            );
+            nodes.truncate(count);
+            let nodes = OverlayNodes {
+                nodes: nodes.into(),
+            }
+            .into_boxed();
+            value.value = ton::bytes(serialize(&nodes)?);
+        } else {
+            log::debug!(
+                target: TARGET,
+                "Signed value of {} bytes exceeds answer size limit",
+                size
+            );
+        }
+        Ok(value)
+    }
+
+    /// ID of DHT key like `hash` gives, serialized into pooled buffer
+    fn hash_key(&self, key: &DhtKey) -> Result<DhtKeyId> {
+        let buf = self.buffers.serialize(&key.clone().into_boxed())?;
+        let mut ret = [0u8; 32];
+        ret.copy_from_slice(Sha256::digest(&buf[..]).as_slice());
+        Ok(ret)
+    }
+
+    /// Add own node to nodes if it is among k closest to target, replacing the farthest one
+    fn include_local_node(&self, nodes: &mut Vec<Node>, target: &[u8; 32], k: usize) -> Result<()> {
+        let local_node = self.local_node()?;
+        let own = distance::xor_metric(self.node_key.id().data(), target);
+        let mut farthest: Option<(usize, [u8; 32])> = None;
+        let mut closer = 0;
+        for (i, node) in nodes.iter().enumerate() {
+            if node.id == local_node.node.id {
+                return Ok(());
+            }
+            let dist = distance::xor_metric(&hash(node.id.clone())?, target);
+            if dist < own {
+                closer += 1
+            }
+            if farthest.map(|(_, max)| dist > max).unwrap_or(true) {
+                farthest = Some((i, dist))
+            }
+        }
+        if closer >= k.max(1) {
+            return Ok(());
+        }
+        if nodes.len() >= k.max(1) {
+            if let Some((i, _)) = farthest {
+                nodes.remove(i);
+            }
+        }
+        nodes.push(local_node.node.clone());
+        Ok(())
+    }
+
+    fn insert_peer(&self, peer_id: &Arc<KeyId>, peer: &Node) -> Result<AddPeerResult> {
+        use std::collections::hash_map::Entry;
+
+        self.peer_stats
+            .entry(peer_id.clone())
+            .or_insert_with(|| PeerStats {
+                last_seen_at: now(),
+                ..Default::default()
+            });
+        self.peer_db
+            .update(peer_id, |record| record.last_seen_at = now());
+        // Read lock keeps insertion out of snapshots and rebuilds of known peers
+        let added = match self.known_peers.read() {
+            Ok(known_peers) => known_peers.put(peer_id.clone())?,
+            Err(e) => e.into_inner().put(peer_id.clone())?,
+        };
+        let dist = distance::distance(self.node_key.id().data(), peer_id.data());
+        let result = match self.buckets.shard_mut(dist).entry(peer_id.clone()) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().version < peer.version {
+                    entry.insert(peer.clone());
+                    AddPeerResult::UpdatedVersion(peer_id.clone())
+                } else if entry.get().version == peer.version {
+                    AddPeerResult::AlreadyKnown(peer_id.clone())
+                } else {
+                    AddPeerResult::RejectedStaleAddress
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(peer.clone());
+                AddPeerResult::Added(peer_id.clone())
+            }
         };
-        Ok(Stored::Dht_Stored)
+        if let AddPeerResult::Added(_) | AddPeerResult::UpdatedVersion(_) = result {
+            // Only accepted addresses count against per-IP limits
+            self.ip_index.insert(peer_id, &peer.addr_list)
+        }
+        if added {
+            self.evict_known_peers(peer_id)?;
+        }
+        Ok(result)
+    }
+
+    fn is_backed_off(&self, peer: &Arc<KeyId>) -> bool {
+        self.peer_stats
+            .get(peer)
+            .map(|stats| stats.value().is_backed_off())
+            .unwrap_or(false)
+    }
+
+    fn is_ephemeral(&self, name: &[u8]) -> bool {
+        self.options
+            .ephemeral_values
+            .as_ref()
+            .map(|ephemeral| ephemeral.contains(name))
+            .unwrap_or(false)
+    }
+
+    fn is_missing_key(&self, key: &DhtKeyId) -> bool {
+        if let Some(expire_at) = self
+            .missing_keys
+            .get(key)
+            .map(|expire_at| *expire_at.value())
+        {
+            if expire_at > now() {
+                return true;
+            }
+            self.missing_keys
+                .remove_if(key, |_, expire_at| *expire_at <= now());
+        }
+        false
+    }
+
+    fn lookup_shortlist(&self, key: &DhtKeyId) -> Vec<Arc<KeyId>> {
+        self.lookup_shortlists
+            .get(&Self::shortlist_prefix(key))
+            .map(|peers| peers.value().clone())
+            .unwrap_or_default()
+    }
+
+    /// Max size of stored payload after decompression
+    fn max_payload_size(&self) -> usize {
+        self.options
+            .strict_validation
+            .as_ref()
+            .map(|limits| limits.max_value_size)
+            .unwrap_or(Self::MAX_DECOMPRESSED_SIZE)
     }
 
     fn process_store_overlay_nodes(&self, dht_key_id: DhtKeyId, value: DhtValue) -> Result<bool> {
-        use dashmap::mapref::entry::Entry;
+        use std::collections::hash_map::Entry;
 
         log::trace!(target: TARGET, "Process Store Overlay Nodes {:?}", value);
-        if !value.signature.is_empty() {
-            fail!("Wrong value signature for OverlayNodes")
-        }
-        if !value.key.signature.is_empty() {
-            fail!("Wrong key signature for OverlayNodes")
-        }
-        let overlay_short_id = match value.key.id {
-            PublicKey::Pub_Overlay(_) => OverlayShortId::from_data(hash_boxed(&value.key.id)?),
-            _ => fail!("Wrong key description format for OverlayNodes"),
-        };
-        if Self::dht_key_from_key_id(&overlay_short_id, "nodes") != value.key.key {
-            fail!("Wrong DHT key for OverlayNodes")
-        }
+        let overlay_short_id = self.verify_overlay_nodes_key(&value)?;
         let mut nodes_list = Self::deserialize_overlay_nodes(&value.value)?;
         let mut nodes = Vec::new();
         while let Some(node) = nodes_list.pop() {
-            if let Err(e) = OverlayUtils::verify_node(&overlay_short_id, &node) {
+            if let Err(e) = self.verify_overlay_member(&overlay_short_id, &node) {
                 log::warn!(target: TARGET, "Bad overlay node {:?}: {}", node, e)
             } else {
                 nodes.push(node)
             }
         }
         if nodes.is_empty() {
-            fail!("Empty overlay nodes list")
+            fail!(DhtError::Verification(
+                "Empty overlay nodes list".to_string()
+            ))
         }
 
-        let process_nodes = |mut old_nodes: Vec<OverlayNode>| -> Result<Option<DhtValue>> {
+        let process_nodes = |mut old_nodes: Vec<OverlayNode>| -> Result<Option<StoredValue>> {
             for node in nodes.iter() {
                 let mut found = false;
                 for old_node in old_nodes.iter_mut() {
@@ -744,34 +3472,58 @@ impl DhtNode {
                     old_nodes.push(node.clone())
                 }
             }
+            if let Some(horizon) = self.options.overlay_nodes_horizon {
+                let min_version = now() - horizon;
+                old_nodes.retain(|node| node.version >= min_version);
+            }
+            if old_nodes.is_empty() {
+                return Ok(None);
+            }
+            if old_nodes.len() > self.options.max_overlay_nodes {
+                old_nodes.sort_by(|a, b| b.version.cmp(&a.version));
+                old_nodes.truncate(self.options.max_overlay_nodes);
+            }
 
             let nodes = OverlayNodes {
                 nodes: old_nodes.into(),
             }
             .into_boxed();
-            let mut ret = value.clone();
-            ret.value = ton::bytes(serialize(&nodes)?);
-            log::trace!(target: TARGET, "Store Overlay Nodes result {:?}", ret);
+            let nodes = serialize(&nodes)?;
+            let mut ret = StoredValue::new(value.clone());
+            ret.inner.value = ton::bytes(nodes);
+            log::trace!(target: TARGET, "Store Overlay Nodes result {:?}", ret.inner);
+            if self.options.compress_overlay_nodes {
+                if let Some(compressed) = compression::compress(&ret.inner.value) {
+                    ret.inner.value = ton::bytes(compressed);
+                    ret.compressed = true
+                }
+            }
             Ok(Some(ret))
         };
 
-        Ok(match self.storage.entry(dht_key_id) {
-            Entry::Occupied(entry) => {
+        Ok(match self.storage.write(&dht_key_id).entry(dht_key_id) {
+            Entry::Occupied(mut entry) => {
                 let old_value = if entry.get().ttl < now() {
                     None
                 } else if entry.get().ttl > value.ttl {
                     return Ok(false);
                 } else {
-                    Some(&entry.get().value)
+                    Some(entry.get())
                 };
 
                 let old_nodes = if let Some(old_value) = old_value {
-                    Self::deserialize_overlay_nodes(old_value)?
+                    if old_value.compressed {
+                        let old_value =
+                            compression::decompress(&old_value.value, self.max_payload_size())?;
+                        Self::deserialize_overlay_nodes(&old_value)?
+                    } else {
+                        Self::deserialize_overlay_nodes(&old_value.value)?
+                    }
                 } else {
                     Vec::new()
                 };
                 if let Some(value) = process_nodes(old_nodes)? {
-                    entry.replace_entry(value);
+                    entry.insert(value);
                     true
                 } else {
                     false
@@ -779,6 +3531,7 @@ impl DhtNode {
             }
             Entry::Vacant(entry) => {
                 if let Some(value) = process_nodes(Vec::new())? {
+                    self.filter_stored_key(&dht_key_id);
                     entry.insert(value);
                     true
                 } else {
@@ -789,29 +3542,96 @@ impl DhtNode {
     }
 
     fn process_store_signed_value(&self, dht_key_id: DhtKeyId, value: DhtValue) -> Result<bool> {
-        use dashmap::mapref::entry::Entry;
+        use std::collections::hash_map::Entry;
 
         self.verify_value(&value)?;
 
-        Ok(match self.storage.entry(dht_key_id) {
-            Entry::Occupied(entry) => {
+        Ok(match self.storage.write(&dht_key_id).entry(dht_key_id) {
+            Entry::Occupied(mut entry) => {
                 if entry.get().ttl < value.ttl {
-                    entry.replace_entry(value);
+                    entry.insert(StoredValue::new(value));
                     true
                 } else {
                     false
                 }
             }
             Entry::Vacant(entry) => {
-                entry.insert(value);
+                self.filter_stored_key(&dht_key_id);
+                entry.insert(StoredValue::new(value));
                 true
             }
         })
     }
 
+    fn purge_values_signed_by(&self, owner: &Arc<KeyId>) {
+        let mut purged = Vec::new();
+        self.storage.retain(|key, value| {
+            if let UpdateRule::Dht_UpdateRule_Signature = value.key.update_rule {
+                match KeyOption::from_tl_public_key(&value.key.id) {
+                    Ok(signer) if signer.id() == owner => {
+                        log_event!(
+                            info,
+                            "purge",
+                            key = base64::encode(key),
+                            owner = owner,
+                            reason = "banned"
+                        );
+                        purged.push(value.inner.clone());
+                        return false;
+                    }
+                    _ => (),
+                }
+            }
+            true
+        });
+        for value in purged {
+            self.log_removed_value(&value)
+        }
+    }
+
     async fn query(&self, dst: &Arc<KeyId>, query: &TLObject) -> Result<Option<TLObject>> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if !rate_limiter.acquire(&self.tasks).await {
+                fail!("DHT node is stopped")
+            }
+        }
         let peers = AdnlPeers::with_keys(self.node_key.id().clone(), dst.clone());
-        self.adnl.query(query, &peers, None).await
+        let start = Instant::now();
+        #[cfg(feature = "testing")]
+        if self.fault_drop_query() {
+            self.update_query_stats(dst, &Ok(None), start);
+            return Ok(None);
+        }
+        let answer = self
+            .adnl
+            .query(query, &peers, self.query_timeout(dst))
+            .await;
+        self.update_query_stats(dst, &answer, start);
+        self.account_issued_query(dst, query, 0, &answer);
+        answer
+    }
+
+    fn query_kind(object: &TLObject) -> &'static str {
+        if object.is::<rpc::dht::Ping>() {
+            "ping"
+        } else if object.is::<rpc::dht::FindNode>() {
+            "find_node"
+        } else if object.is::<rpc::dht::FindValue>() {
+            "find_value"
+        } else if object.is::<rpc::dht::GetSignedAddressList>() {
+            "get_signed_address_list"
+        } else if object.is::<rpc::dht::Store>() {
+            "store"
+        } else {
+            "unknown"
+        }
+    }
+
+    fn query_timeout(&self, peer: &Arc<KeyId>) -> Option<u64> {
+        self.peer_stats
+            .get(peer)
+            .and_then(|stats| stats.value().timeout())
+            .map(|timeout| timeout.as_millis() as u64)
     }
 
     async fn query_with_prefix(
@@ -819,29 +3639,200 @@ impl DhtNode {
         dst: &Arc<KeyId>,
         query: &TLObject,
     ) -> Result<Option<TLObject>> {
+        let disabled = matches!(self.options.query_prefix, QueryPrefix::Disabled);
+        if disabled || (self.options.role == DhtRole::ClientOnly) {
+            // Do not advertise ourselves
+            return self.query(dst, query).await;
+        }
+        let local_node = match &self.custom_prefix {
+            Some(prefix) => prefix.clone(),
+            None => self.local_node()?,
+        };
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if !rate_limiter.acquire(&self.tasks).await {
+                fail!("DHT node is stopped")
+            }
+        }
         let peers = AdnlPeers::with_keys(self.node_key.id().clone(), dst.clone());
-        self.adnl
-            .query_with_prefix(Some(&self.query_prefix[..]), query, &peers, None)
-            .await
+        let start = Instant::now();
+        #[cfg(feature = "testing")]
+        if self.fault_drop_query() {
+            self.update_query_stats(dst, &Ok(None), start);
+            return Ok(None);
+        }
+        let answer = self
+            .adnl
+            .query_with_prefix(
+                Some(&local_node.query_prefix[..]),
+                query,
+                &peers,
+                self.query_timeout(dst),
+            )
+            .await;
+        self.update_query_stats(dst, &answer, start);
+        self.account_issued_query(dst, query, local_node.query_prefix.len(), &answer);
+        answer
     }
 
-    fn search_dht_key(&self, key: &DhtKeyId) -> Option<DhtValue> {
+    fn remember_missing_key(&self, key: &DhtKeyId) {
+        let ttl = match self.options.missing_key_ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
         let version = now();
-        if let Some(value) = self.storage.get(key) {
-            if value.value().ttl > version {
-                Some(value.value().clone())
-            } else {
-                None
+        if self.missing_keys.len() >= Self::MAX_MISSING_KEYS {
+            self.missing_keys
+                .retain(|_, expire_at| *expire_at > version);
+            if self.missing_keys.len() >= Self::MAX_MISSING_KEYS {
+                return;
             }
-        } else {
-            None
         }
+        self.missing_keys.insert(*key, version + ttl);
+    }
+
+    /// Remove peers from routing table and known peers
+    fn remove_known_peers(&self, dead: &HashSet<Arc<KeyId>>) -> Result<()> {
+        self.buckets.retain(|peer, _| !dead.contains(peer));
+        for peer in dead.iter() {
+            self.peer_stats.remove(peer);
+        }
+        self.ip_index.remove(dead);
+        // AddressCache cannot drop entries, so it is rebuilt from alive peers,
+        // insertions wait for the rebuild
+        let mut old = match self.known_peers.write() {
+            Ok(old) => old,
+            Err(e) => e.into_inner(),
+        };
+        let known_peers = AddressCache::with_limit(Self::known_peers_limit(&self.options));
+        for peer in Self::walk_known_peers(&old) {
+            if !dead.contains(&peer) {
+                known_peers.put(peer)?;
+            }
+        }
+        *old = Arc::new(known_peers);
+        Ok(())
+    }
+
+    /// More replicas on small networks and on routing tables with many failing peers
+    fn replication_factor(&self) -> usize {
+        if let Some(replicas) = self.options.store_replicas {
+            return replicas.max(1);
+        }
+        let size = self.estimate_network_size().max(1);
+        let base = Self::MIN_REPLICAS + 10usize.saturating_sub((size as f64).log2() as usize);
+        let mut peers = 0;
+        let mut failing = 0;
+        self.buckets.for_each(|peer, _| {
+            peers += 1;
+            let failed = self
+                .peer_stats
+                .get(peer)
+                .map(|stats| stats.value().failed_in_row > 0)
+                .unwrap_or(false);
+            if failed {
+                failing += 1
+            }
+        });
+        let replicas = base + base * failing / peers.max(1);
+        replicas.min(Self::MAX_REPLICAS)
+    }
+
+    fn reverify_stored_value(&self, key: &DhtKeyId, value: &mut DhtValue) -> bool {
+        let digest = Sha256::digest(&value.value.0);
+        let digest = *arrayref::array_ref!(digest.as_slice(), 0, 32);
+        if let Some(verified) = self.verified_values.get(key) {
+            if *verified.value() == (value.ttl, digest) {
+                return true;
+            }
+        }
+        match self.verify_found_value(key, value) {
+            Ok(()) => {
+                // Value with dropped overlay nodes is verified again on next read
+                if Sha256::digest(&value.value.0).as_slice() != &digest[..] {
+                    return true;
+                }
+                if self.verified_values.len() >= Self::MAX_VERIFIED_VALUES {
+                    self.verified_values.clear()
+                }
+                self.verified_values.insert(*key, (value.ttl, digest));
+                true
+            }
+            Err(e) => {
+                log_event!(
+                    warn,
+                    "purge",
+                    key = base64::encode(key),
+                    reason = "verification",
+                    error = e
+                );
+                let mut shard = self.storage.write(key);
+                let purged = if shard.get(key).map(|stored| stored.ttl) == Some(value.ttl) {
+                    shard.remove(key)
+                } else {
+                    None
+                };
+                drop(shard);
+                if let Some(purged) = purged {
+                    self.log_removed_value(&purged)
+                }
+                self.verified_values.remove(key);
+                self.purged_values.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    fn search_dht_key(&self, key: &DhtKeyId) -> Option<DhtValue> {
+        if let Some(key_filter) = &self.key_filter {
+            if !key_filter.may_contain(key) {
+                return None;
+            }
+        }
+        let version = now();
+        let value = self.storage.get(key).filter(|value| value.ttl > version)?;
+        let mut value = self.stored_payload(key, value)?;
+        if self.options.reverify_stored_values && !self.reverify_stored_value(key, &mut value) {
+            return None;
+        }
+        Some(value)
+    }
+
+    /// Locally stored value published by this node
+    fn search_published_key(&self, key: &DhtKeyId) -> Option<DhtValue> {
+        let version = now();
+        match self.published_keys.get(key) {
+            Some(ttl) if *ttl.value() > version => (),
+            _ => return None,
+        }
+        self.search_dht_key(key)
+    }
+
+    fn sign_indexed_value(
+        &self,
+        name: &str,
+        idx: i32,
+        value: &[u8],
+        key: &Arc<KeyOption>,
+        ttl: i32,
+    ) -> Result<DhtValue> {
+        let value = DhtValue {
+            key: self.sign_key_description(name, idx, key)?,
+            ttl: now() + ttl,
+            signature: ton::bytes::default(),
+            value: ton::bytes(value.to_vec()),
+        };
+        Ok(sign!(value, key))
     }
 
-    fn sign_key_description(name: &str, key: &Arc<KeyOption>) -> Result<DhtKeyDescription> {
+    fn sign_key_description(
+        &self,
+        name: &str,
+        idx: i32,
+        key: &Arc<KeyOption>,
+    ) -> Result<DhtKeyDescription> {
         let key_description = DhtKeyDescription {
             id: key.as_tl_public_key()?,
-            key: Self::dht_key_from_key_id(key.id(), name),
+            key: self.dht_key_builder(key.id(), name).idx(idx).build(),
             signature: ton::bytes::default(),
             update_rule: UpdateRule::Dht_UpdateRule_Signature,
         };
@@ -855,17 +3846,44 @@ impl DhtNode {
             signature: ton::bytes::default(),
             version: now(),
         };
-        Ok(sign!(local_node, self.node_key))
+        if let Some(network_id) = &self.options.network_id {
+            // Salted signature is accepted by peers of the same network only
+            let local_node = local_node.into_boxed();
+            let mut buf = serialize(&local_node)?;
+            buf.extend_from_slice(network_id);
+            let signature = self.node_key.sign(&buf)?;
+            let mut local_node = local_node.only();
+            local_node.signature.0 = signature.to_vec();
+            Ok(local_node)
+        } else {
+            Ok(sign!(local_node, self.node_key))
+        }
     }
 
-    fn sign_value(name: &str, value: &[u8], key: &Arc<KeyOption>) -> Result<DhtValue> {
-        let value = DhtValue {
-            key: Self::sign_key_description(name, key)?,
-            ttl: now() + Self::TIMEOUT_VALUE,
-            signature: ton::bytes::default(),
-            value: ton::bytes(value.to_vec()),
-        };
-        Ok(sign!(value, key))
+    fn sign_overlay_node(
+        overlay_short_id: &Arc<OverlayShortId>,
+        key: &Arc<KeyOption>,
+    ) -> Result<OverlayNode> {
+        let version = now();
+        let to_sign = OverlayNodeToSign {
+            id: AdnlShortId {
+                id: ton::int256(*key.id().data()),
+            },
+            overlay: ton::int256(*overlay_short_id.data()),
+            version,
+        }
+        .into_boxed();
+        let signature = key.sign(&serialize(&to_sign)?)?;
+        Ok(OverlayNode {
+            id: key.as_tl_public_key()?,
+            overlay: ton::int256(*overlay_short_id.data()),
+            version,
+            signature: ton::bytes(signature.to_vec()),
+        })
+    }
+
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        self.options.spawner.spawn(self.tasks.track(task))
     }
 
     async fn store_value(
@@ -874,37 +3892,89 @@ impl DhtNode {
         value: DhtValue,
         check_type: impl Fn(&TLObject) -> bool + Copy + Send + 'static,
         check_all: bool,
-        check_vals: impl Fn(Vec<(DhtKeyDescription, TLObject)>) -> Result<bool>,
-    ) -> Result<bool> {
+        check_vals: impl Fn(Vec<FoundValue>) -> Result<bool>,
+    ) -> Result<StoreReport> {
+        let key_id = hash(key.clone())?;
+        dht.missing_keys.remove(&key_id);
+        dht.value_cache.remove(&key_id);
+        dht.track_published_key(&key_id, value.ttl);
+        // Own values are persisted as well, so they survive restart
+        if !dht.is_ephemeral(&key.name) {
+            dht.log_stored_value(&key_id)?
+        }
         let query = rpc::dht::Store { value };
         let query = Arc::new(TLObject::new(query));
-        let (mut iter, mut peer) = dht.known_peers.first();
+        let mut candidates = dht.known_peers_snapshot();
+        candidates.retain(|peer| {
+            !dht.is_banned(peer)
+                && (dht.is_priority_peer(peer)
+                    || (!dht.is_backed_off(peer) && !dht.drops_values(peer)))
+        });
+        // Each round stores on next closest peers to the key, priority peers go first
+        candidates.sort_by_cached_key(|peer| {
+            (
+                !dht.is_priority_peer(peer),
+                distance::xor_metric(peer.data(), &key_id),
+            )
+        });
+        let replicas = dht.replication_factor();
         let (wait, mut queue_reader) = Wait::new();
-        while peer.is_some() {
-            while let Some(next) = peer {
-                peer = dht.known_peers.next(&mut iter);
-                let dht = dht.clone();
+        let mut report = StoreReport::default();
+        for round in candidates.chunks(replicas) {
+            for next in round {
+                let next = next.clone();
+                let dht_cloned = dht.clone();
                 let query = query.clone();
                 let wait = wait.clone();
                 wait.request();
-                tokio::spawn(async move {
-                    let ret = match dht.query(&next, &query).await {
+                report.contacted += 1;
+                dht.spawn(async move {
+                    let ret = match dht_cloned.query(&next, &query).await {
                         Ok(Some(answer)) => {
                             match Query::parse::<TLObject, Stored>(answer, &query) {
-                                Ok(_) => Some(()), // Probably stored
-                                Err(answer) => {
-                                    log::debug!(
-                                        target: TARGET,
-                                        "Improper store reply: {:?}",
-                                        answer
+                                Ok(_) => {
+                                    // Probably stored
+                                    log_event!(
+                                        debug,
+                                        "store",
+                                        peer = next,
+                                        query = "store",
+                                        outcome = "stored"
+                                    );
+                                    Some(next)
+                                }
+                                Err(_) => {
+                                    log_event!(
+                                        debug,
+                                        "store",
+                                        peer = next,
+                                        query = "store",
+                                        outcome = "improper_reply"
                                     );
                                     None
                                 }
                             }
                         }
-                        Ok(None) => None, // No reply at all
+                        Ok(None) => {
+                            // No reply at all
+                            log_event!(
+                                debug,
+                                "store",
+                                peer = next,
+                                query = "store",
+                                outcome = "no_answer"
+                            );
+                            None
+                        }
                         Err(e) => {
-                            log::warn!(target: TARGET, "Store error: {:?}", e);
+                            log_event!(
+                                warn,
+                                "store",
+                                peer = next,
+                                query = "store",
+                                outcome = "error",
+                                error = e
+                            );
                             None
                         }
                     };
@@ -912,16 +3982,94 @@ impl DhtNode {
                 });
             }
 
-            while wait.wait(&mut queue_reader, false).await.is_some() {}
+            loop {
+                match wait.wait(&mut queue_reader, false).await {
+                    Some(Some(confirmed)) => report.confirmed.push(confirmed),
+                    Some(None) => (),
+                    None => break,
+                }
+            }
+            if report.acks() > 0 {
+                dht.last_store_at.store(now(), Ordering::Relaxed);
+            }
 
-            let vals =
-                DhtNode::find_value(dht, key.clone(), check_type, check_all, &mut None).await?;
+            // Verification never reads local copies, they would confirm any store
+            let (vals, _) = DhtNode::lookup_value(
+                dht,
+                key.clone(),
+                check_type,
+                check_all,
+                false,
+                &mut None,
+                None,
+            )
+            .await?;
             if check_vals(vals)? {
-                return Ok(true);
+                report.verified = true;
+                return Ok(report);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Stored value with payload as it was signed. Only payloads compressed by us
+    /// are decompressed
+    fn stored_payload(&self, key: &DhtKeyId, stored: StoredValue) -> Option<DhtValue> {
+        if !stored.compressed {
+            return Some(stored.inner);
+        }
+        let mut value = stored.inner;
+        match compression::decompress(&value.value, self.max_payload_size()) {
+            Ok(data) => {
+                value.value = ton::bytes(data);
+                Some(value)
+            }
+            Err(e) => {
+                log::warn!(
+                    target: TARGET,
+                    "Bad stored value with key {}: {}",
+                    base64::encode(key),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn track_ephemeral_value(&self, key: &DhtKeyId, max_values: usize) {
+        let ttl = match self.storage.get(key) {
+            Some(value) => value.ttl,
+            None => return,
+        };
+        self.ephemeral_keys.insert(*key, ttl);
+        if self.ephemeral_keys.len() <= max_values {
+            return;
+        }
+        // Evict values expiring first, with some slack to not sort on each store
+        let mut expiring = self
+            .ephemeral_keys
+            .iter()
+            .filter(|entry| !self.pinned_values.contains_key(entry.key()))
+            .map(|entry| (*entry.value(), *entry.key()))
+            .collect::<Vec<_>>();
+        expiring.sort_unstable();
+        let evict = expiring.len().saturating_sub(max_values * 9 / 10);
+        for (_, key) in expiring.into_iter().take(evict) {
+            self.ephemeral_keys.remove(&key);
+            // Value may be in store log if it was stored before its name became ephemeral
+            if let Some(value) = self.storage.remove(&key) {
+                self.log_removed_value(&value)
             }
-            peer = dht.known_peers.next(&mut iter);
         }
-        Ok(false)
+        log::debug!(target: TARGET, "Evicted {} ephemeral DHT values", evict);
+    }
+
+    fn track_published_key(&self, key: &DhtKeyId, ttl: i32) {
+        if self.published_keys.len() >= Self::MAX_TRACKED_KEYS {
+            let version = now();
+            self.published_keys.retain(|_, ttl| *ttl > version);
+        }
+        self.published_keys.insert(*key, ttl);
     }
 
     async fn value_query(
@@ -930,94 +4078,350 @@ impl DhtNode {
         query: &Arc<TLObject>,
         key: &Arc<DhtKeyId>,
         check: impl Fn(&TLObject) -> bool,
-    ) -> Result<Option<(DhtKeyDescription, TLObject)>> {
+    ) -> Result<(PeerResponse, Option<FoundValue>)> {
         let answer = self.query(peer, query).await?;
         if let Some(answer) = answer {
             let answer: DhtValueResult = Query::parse(answer, &query)?;
             match answer {
                 DhtValueResult::Dht_ValueFound(value) => {
-                    let value = value.value.only();
-                    log::debug!(
-                        target: TARGET,
-                        "Found value for DHT key ID {}: {:?}/{:?}",
-                        base64::encode(&key[..]),
-                        value.key,
-                        value.value
-                    );
+                    let mut value = value.value.only();
+                    if let Err(e) = self.verify_found_value(key, &mut value) {
+                        log_event!(
+                            warn,
+                            "value_query",
+                            peer = peer,
+                            key = base64::encode(&key[..]),
+                            query = "find_value",
+                            outcome = "bad_value",
+                            error = e
+                        );
+                        return Ok((PeerResponse::Invalid, None));
+                    }
                     let object = deserialize(&value.value.0)?;
                     if check(&object) {
-                        return Ok(Some((value.key, object)));
+                        log_event!(
+                            debug,
+                            "value_query",
+                            peer = peer,
+                            key = base64::encode(&key[..]),
+                            query = "find_value",
+                            outcome = "found"
+                        );
+                        self.cache_value(key, &value);
+                        let found = Self::found_value(peer, value, object);
+                        return Ok((PeerResponse::Found, Some(found)));
                     }
-                    log::debug!(target: TARGET, "Improper value found, object {:?}", object);
+                    log_event!(
+                        debug,
+                        "value_query",
+                        peer = peer,
+                        key = base64::encode(&key[..]),
+                        query = "find_value",
+                        outcome = "improper_value"
+                    );
+                    Ok((PeerResponse::Invalid, None))
                 }
                 DhtValueResult::Dht_ValueNotFound(nodes) => {
                     let nodes = nodes.nodes.nodes;
-                    log::debug!(
-                        target: TARGET,
-                        "Value not found on {} for DHT key ID {}, suggested {} other nodes",
-                        peer,
-                        base64::encode(&key[..]),
-                        nodes.len()
+                    log_event!(
+                        debug,
+                        "value_query",
+                        peer = peer,
+                        key = base64::encode(&key[..]),
+                        query = "find_value",
+                        outcome = "not_found",
+                        nodes = nodes.len()
                     );
                     for node in nodes.iter() {
-                        self.add_peer(node)?;
+                        let key = KeyOption::from_tl_public_key(&node.id)?;
+                        if self.peer_db.is_unreliable(key.id()) {
+                            log_event!(debug, "add_peer", peer = key.id(), outcome = "unreliable");
+                            continue;
+                        }
+                        self.graph_suggestion(peer, key.id());
+                        self.add_suggested_peer(node)?;
                     }
+                    Ok((PeerResponse::NotFound, None))
                 }
             }
         } else {
-            log::debug!(
-                target: TARGET,
-                "No answer from {} to FindValue with DHT key ID {} query",
-                peer,
-                base64::encode(&key[..])
+            log_event!(
+                debug,
+                "value_query",
+                peer = peer,
+                key = base64::encode(&key[..]),
+                query = "find_value",
+                outcome = "no_answer"
             );
+            Ok((PeerResponse::Timeout, None))
+        }
+    }
+
+    fn update_key_reads(&self, key: &DhtKeyId, hit: bool) {
+        let counter = if hit {
+            &self.find_value_hits
+        } else {
+            &self.find_value_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let reads = if let Some(reads) = self.key_reads.get_mut(key) {
+            Some(reads)
+        } else if self.key_reads.len() < Self::MAX_TRACKED_KEYS {
+            Some(self.key_reads.entry(*key).or_insert_with(|| KeyPopularity {
+                key: *key,
+                ..Default::default()
+            }))
+        } else {
+            // Too many keys tracked, count totals only
+            None
+        };
+        if let Some(mut reads) = reads {
+            if hit {
+                reads.hits += 1
+            } else {
+                reads.misses += 1
+            }
+        }
+    }
+
+    fn update_local_node(&self) -> Result<Arc<LocalNode>> {
+        let local_node = Arc::new(LocalNode::with_node(self.sign_local_node()?)?);
+        match self.local_node.write() {
+            Ok(mut old) => *old = local_node.clone(),
+            Err(e) => *e.into_inner() = local_node.clone(),
+        }
+        Ok(local_node)
+    }
+
+    /// Shortlists are shared by keys with the same 16-bit prefix: peers found close
+    /// to one key are close to its neighbours too, and there are at most 65536 lists
+    fn shortlist_prefix(key: &DhtKeyId) -> u16 {
+        u16::from_be_bytes([key[0], key[1]])
+    }
+
+    fn update_lookup_shortlist(&self, key: &DhtKeyId, peer: &Arc<KeyId>) {
+        let mut peers = self
+            .lookup_shortlists
+            .entry(Self::shortlist_prefix(key))
+            .or_insert_with(Vec::new);
+        peers.retain(|known| known != peer);
+        peers.insert(0, peer.clone());
+        peers.truncate(Self::MAX_SHORTLIST_PEERS)
+    }
+
+    fn update_peer_stats(&self, peer: &Arc<KeyId>, update: impl FnOnce(&mut PeerStats)) {
+        update(
+            self.peer_stats
+                .entry(peer.clone())
+                .or_insert_with(|| PeerStats {
+                    last_seen_at: now(),
+                    ..Default::default()
+                })
+                .value_mut(),
+        )
+    }
+
+    fn update_query_stats(
+        &self,
+        peer: &Arc<KeyId>,
+        answer: &Result<Option<TLObject>>,
+        start: Instant,
+    ) {
+        let answered = matches!(answer, Ok(Some(_)));
+        self.peer_db.update(peer, |record| {
+            if answered {
+                record.answered += 1;
+                record.last_seen_at = now()
+            } else {
+                record.failed += 1
+            }
+        });
+        self.update_peer_stats(peer, |stats| {
+            stats.update(answered);
+            if answered {
+                stats.update_rtt(start.elapsed())
+            }
+        })
+    }
+
+    fn update_value_lookups(&self, key: &DhtKeyId) {
+        if let Some(mut lookups) = self.value_lookups.get_mut(key) {
+            *lookups.value_mut() += 1;
+            return;
+        }
+        if self.value_lookups.len() < Self::MAX_TRACKED_KEYS {
+            *self.value_lookups.entry(*key).or_insert(0) += 1
+        }
+    }
+
+    /// Verify value found remotely or read from storage. Invalid overlay nodes are
+    /// dropped from the value like stores do, it fails only if no valid nodes are left
+    fn verify_found_value(&self, key: &DhtKeyId, value: &mut DhtValue) -> Result<()> {
+        if value.ttl <= now() {
+            fail!(DhtError::Verification(format!(
+                "Expired DHT value with key {}",
+                base64::encode(key)
+            )))
+        }
+        if &self.hash_key(&value.key.key)? != key {
+            fail!(DhtError::Verification(format!(
+                "DHT value with key {} has another key",
+                base64::encode(key)
+            )))
+        }
+        if let Some(limits) = &self.options.strict_validation {
+            limits.check_value(value)?
+        }
+        match value.key.update_rule {
+            UpdateRule::Dht_UpdateRule_Signature => self.verify_value(value),
+            UpdateRule::Dht_UpdateRule_OverlayNodes => {
+                let overlay_short_id = self.verify_overlay_nodes_key(value)?;
+                let nodes = Self::deserialize_overlay_nodes(&value.value)?;
+                let count = nodes.len();
+                let mut valid = Vec::with_capacity(count);
+                for node in nodes {
+                    if let Err(e) = self.verify_overlay_member(&overlay_short_id, &node) {
+                        log::debug!(target: TARGET, "Bad overlay node {:?}: {}", node, e)
+                    } else {
+                        valid.push(node)
+                    }
+                }
+                if valid.is_empty() {
+                    fail!(DhtError::Verification(format!(
+                        "No valid overlay nodes in DHT value with key {}",
+                        base64::encode(key)
+                    )))
+                }
+                if valid.len() < count {
+                    let nodes = OverlayNodes {
+                        nodes: valid.into(),
+                    }
+                    .into_boxed();
+                    value.value = ton::bytes(serialize(&nodes)?)
+                }
+                Ok(())
+            }
+            _ => fail!(DhtError::UnsupportedRule(format!(
+                "Unsupported update rule in DHT value with key {}",
+                base64::encode(key)
+            ))),
         }
-        Ok(None)
     }
 
     fn verify_other_node(&self, node: &Node) -> Result<()> {
-        let other_key = KeyOption::from_tl_public_key(&node.id)?;
-        let mut node = node.clone();
-        verify!(node, other_key);
-        Ok(())
+        let verify = |salt: &[u8]| -> Result<()> {
+            let other_key = KeyOption::from_tl_public_key(&node.id)?;
+            let mut node = node.clone();
+            let signature = mem::replace(&mut node.signature.0, Vec::new());
+            let mut buf = self.buffers.serialize(&node.into_boxed())?;
+            buf.extend_from_slice(salt);
+            other_key.verify(&buf[..], &signature[..])?;
+            Ok(())
+        };
+        let result = match &self.options.network_id {
+            Some(network_id) => match verify(&network_id[..]) {
+                Err(_) if !self.options.strict_network => verify(&[]),
+                result => result,
+            },
+            None => verify(&[]),
+        };
+        result.map_err(|e| error!(DhtError::Verification(e.to_string())))
+    }
+
+    fn verify_overlay_nodes_key(&self, value: &DhtValue) -> Result<Arc<OverlayShortId>> {
+        if !value.signature.is_empty() {
+            fail!(DhtError::Verification(
+                "Wrong value signature for OverlayNodes".to_string()
+            ))
+        }
+        if !value.key.signature.is_empty() {
+            fail!(DhtError::Verification(
+                "Wrong key signature for OverlayNodes".to_string()
+            ))
+        }
+        let overlay_short_id = match value.key.id {
+            PublicKey::Pub_Overlay(_) => OverlayShortId::from_data(hash_boxed(&value.key.id)?),
+            _ => fail!(DhtError::Malformed(
+                "Wrong key description format for OverlayNodes".to_string()
+            )),
+        };
+        if self.dht_key_from_key_id(&overlay_short_id, "nodes") != value.key.key {
+            fail!(DhtError::Verification(
+                "Wrong DHT key for OverlayNodes".to_string()
+            ))
+        }
+        Ok(overlay_short_id)
     }
 
     fn verify_value(&self, value: &DhtValue) -> Result<()> {
-        let other_key = KeyOption::from_tl_public_key(&value.key.id)?;
-        let mut key = value.key.clone();
-        verify!(key, other_key);
-        let mut value = value.clone();
-        verify!(value, other_key);
-        Ok(())
+        let verify = || -> Result<()> {
+            let other_key = KeyOption::from_tl_public_key(&value.key.id)?;
+            let mut key = value.key.clone();
+            let signature = mem::replace(&mut key.signature.0, Vec::new());
+            let buf = self.buffers.serialize(&key.into_boxed())?;
+            other_key.verify(&buf[..], &signature[..])?;
+            drop(buf);
+            let mut value = value.clone();
+            let signature = mem::replace(&mut value.signature.0, Vec::new());
+            let buf = self.buffers.serialize(&value.into_boxed())?;
+            other_key.verify(&buf[..], &signature[..])?;
+            Ok(())
+        };
+        verify().map_err(|e| error!(DhtError::Verification(e.to_string())))
+    }
+
+    /// Peers of address cache, each listed once
+    fn walk_known_peers(known_peers: &AddressCache) -> Vec<Arc<KeyId>> {
+        let limit = known_peers.count() as usize;
+        let mut seen = HashSet::with_capacity(limit);
+        let mut ret = Vec::with_capacity(limit);
+        let (mut iter, mut peer) = known_peers.first();
+        while let Some(next) = peer {
+            if seen.insert(next.clone()) {
+                ret.push(next)
+            }
+            if seen.len() >= limit {
+                break;
+            }
+            peer = known_peers.next(&mut iter);
+        }
+        ret
     }
 }
 
 #[async_trait::async_trait]
 impl Subscriber for DhtNode {
-    async fn try_consume_query(&self, object: TLObject, _peers: &AdnlPeers) -> Result<QueryResult> {
-        let object = match object.downcast::<rpc::dht::Ping>() {
-            Ok(query) => return QueryResult::consume(self.process_ping(&query)?),
-            Err(object) => object,
-        };
-        let object = match object.downcast::<rpc::dht::FindNode>() {
-            Ok(query) => return QueryResult::consume(self.process_find_node(&query)?),
-            Err(object) => object,
-        };
-        let object = match object.downcast::<rpc::dht::FindValue>() {
-            Ok(query) => return QueryResult::consume_boxed(self.process_find_value(&query)?),
-            Err(object) => object,
-        };
-        let object = match object.downcast::<rpc::dht::GetSignedAddressList>() {
-            Ok(_) => return QueryResult::consume(self.get_signed_node()?),
-            Err(object) => object,
-        };
-        match object.downcast::<rpc::dht::Store>() {
-            Ok(query) => QueryResult::consume_boxed(self.process_store(query)?),
-            Err(object) => {
-                log::warn!(target: TARGET, "Unexpected DHT query {:?}", object);
-                Ok(QueryResult::Rejected(object))
+    async fn try_consume_query(&self, object: TLObject, peers: &AdnlPeers) -> Result<QueryResult> {
+        if peers.local() != self.node_key.id() {
+            // Query to another DHT identity over the same ADNL node
+            return Ok(QueryResult::Rejected(object));
+        }
+        #[cfg(feature = "testing")]
+        if let Some(delay) = self.fault_answer_delay() {
+            self.options.spawner.sleep(delay).await
+        }
+        if let Some(mut stats) = self.peer_stats.get_mut(peers.other()) {
+            // Peer which queries us is alive again
+            stats.value_mut().last_seen_at = now();
+            stats.value_mut().backoff_until = 0;
+        }
+        let allowed = match self.options.role {
+            DhtRole::Full => true,
+            DhtRole::ClientOnly => {
+                !object.is::<rpc::dht::Store>() && !object.is::<rpc::dht::FindValue>()
             }
+            DhtRole::BootstrapOnly => !object.is::<rpc::dht::Store>(),
+        };
+        if !allowed {
+            self.reject_query(peers.other(), Self::query_kind(&object), RejectReason::Role);
+            return Ok(QueryResult::Rejected(object));
+        }
+        let kind = Self::query_kind(&object);
+        let ret = self.process_query(object, peers.other()).await;
+        if let Err(e) = &ret {
+            self.reject_query(peers.other(), kind, RejectReason::from_error(e))
         }
+        ret
     }
 
     async fn try_consume_query_bundle(
@@ -1025,7 +4429,7 @@ impl Subscriber for DhtNode {
         mut objects: Vec<TLObject>,
         peers: &AdnlPeers,
     ) -> Result<QueryResult> {
-        if objects.len() != 2 {
+        if (objects.len() != 2) || (peers.local() != self.node_key.id()) {
             return Ok(QueryResult::RejectedBundle(objects));
         }
         let other_node = match objects.remove(0).downcast::<rpc::dht::Query>() {
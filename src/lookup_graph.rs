@@ -0,0 +1,206 @@
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use adnl::common::{now, KeyId};
+
+use crate::{DhtNode, PeerResponse};
+
+/// Kind of edge of lookup graph
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphEdgeKind {
+    /// Source queried target, which gave response
+    Queried(PeerResponse),
+    /// Source suggested target as closer to looked up key
+    Suggested,
+}
+
+/// Edge of lookup graph
+#[derive(Clone, Debug)]
+pub struct GraphEdge {
+    /// Source node
+    pub from: Arc<KeyId>,
+    /// Target node
+    pub to: Arc<KeyId>,
+    /// Edge kind
+    pub kind: GraphEdgeKind,
+    /// Time the edge was recorded
+    pub at: i32,
+}
+
+/// Graph of value lookups recorded over time window
+#[derive(Clone, Debug, Default)]
+pub struct LookupGraph {
+    /// Edges in order of recording
+    pub edges: Vec<GraphEdge>,
+}
+
+impl LookupGraph {
+    /// Export graph in Graphviz DOT format: queries are solid edges labelled with
+    /// responses, suggestions are dashed edges
+    pub fn to_dot(&self) -> String {
+        let mut ret = String::from("digraph lookups {\n");
+        for edge in self.edges.iter() {
+            let _ = match edge.kind {
+                GraphEdgeKind::Queried(response) => writeln!(
+                    ret,
+                    "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                    edge.from,
+                    edge.to,
+                    response_name(response)
+                ),
+                GraphEdgeKind::Suggested => writeln!(
+                    ret,
+                    "  \"{}\" -> \"{}\" [style=dashed];",
+                    edge.from, edge.to
+                ),
+            };
+        }
+        ret.push_str("}\n");
+        ret
+    }
+
+    /// Export graph in JSON graph format with `nodes` and `edges` arrays
+    pub fn to_json(&self) -> String {
+        let mut unique = HashSet::new();
+        let mut nodes = Vec::new();
+        for edge in self.edges.iter() {
+            for node in [&edge.from, &edge.to].iter() {
+                if unique.insert(*node) {
+                    nodes.push(*node)
+                }
+            }
+        }
+        let mut ret = String::from("{\"nodes\":[");
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                ret.push(',')
+            }
+            let _ = write!(ret, "{{\"id\":\"{}\"}}", node);
+        }
+        ret.push_str("],\"edges\":[");
+        for (i, edge) in self.edges.iter().enumerate() {
+            if i > 0 {
+                ret.push(',')
+            }
+            let (kind, response) = match edge.kind {
+                GraphEdgeKind::Queried(response) => ("queried", response_name(response)),
+                GraphEdgeKind::Suggested => ("suggested", ""),
+            };
+            let _ = write!(
+                ret,
+                "{{\"source\":\"{}\",\"target\":\"{}\",\"kind\":\"{}\",\"response\":\"{}\",\"at\":{}}}",
+                edge.from, edge.to, kind, response, edge.at
+            );
+        }
+        ret.push_str("]}");
+        ret
+    }
+}
+
+/// Recorder of lookup graph edges while recording window is open
+pub(crate) struct GraphRecorder {
+    edges: Mutex<Vec<GraphEdge>>,
+    until: AtomicI32,
+}
+
+impl GraphRecorder {
+    const MAX_EDGES: usize = 65536;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            edges: Mutex::new(Vec::new()),
+            until: AtomicI32::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<GraphEdge>> {
+        match self.edges.lock() {
+            Ok(edges) => edges,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn record(&self, from: &Arc<KeyId>, to: &Arc<KeyId>, kind: GraphEdgeKind) {
+        let at = now();
+        if self.until.load(Ordering::Relaxed) < at {
+            return;
+        }
+        let mut edges = self.lock();
+        if edges.len() < Self::MAX_EDGES {
+            edges.push(GraphEdge {
+                from: from.clone(),
+                to: to.clone(),
+                kind,
+                at,
+            })
+        }
+    }
+}
+
+fn response_name(response: PeerResponse) -> &'static str {
+    match response {
+        PeerResponse::Found => "found",
+        PeerResponse::NotFound => "not_found",
+        PeerResponse::Invalid => "invalid",
+        PeerResponse::Timeout => "timeout",
+        PeerResponse::Error => "error",
+    }
+}
+
+impl DhtNode {
+    /// Graph of value lookups recorded so far
+    pub fn lookup_graph(&self) -> LookupGraph {
+        LookupGraph {
+            edges: self.lookup_graph.lock().clone(),
+        }
+    }
+
+    /// Drop recorded lookup graph and record new one during given time window
+    pub fn record_lookup_graph(&self, window: Duration) {
+        self.lookup_graph.lock().clear();
+        let window = i32::try_from(window.as_secs()).unwrap_or(i32::MAX);
+        self.lookup_graph
+            .until
+            .store(now().saturating_add(window), Ordering::Relaxed)
+    }
+
+    pub(crate) fn graph_query(&self, peer: &Arc<KeyId>, response: PeerResponse) {
+        self.lookup_graph
+            .record(self.node_key.id(), peer, GraphEdgeKind::Queried(response))
+    }
+
+    pub(crate) fn graph_suggestion(&self, from: &Arc<KeyId>, to: &Arc<KeyId>) {
+        self.lookup_graph.record(from, to, GraphEdgeKind::Suggested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lists_each_node_once() {
+        let a = KeyId::from_data([1; 32]);
+        let b = KeyId::from_data([2; 32]);
+        let edge = |from: &Arc<KeyId>, to: &Arc<KeyId>| GraphEdge {
+            from: from.clone(),
+            to: to.clone(),
+            kind: GraphEdgeKind::Suggested,
+            at: 0,
+        };
+        let graph = LookupGraph {
+            edges: vec![edge(&a, &b), edge(&b, &a), edge(&a, &b)],
+        };
+        let json = graph.to_json();
+        assert_eq!(json.matches("{\"id\":").count(), 2);
+        assert_eq!(json.matches("\"source\":").count(), 3);
+    }
+}
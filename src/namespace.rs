@@ -0,0 +1,94 @@
+use std::sync::{Arc, RwLock};
+
+use ton_api::ton::dht::value::Value as DhtValue;
+use ton_types::Result;
+
+use crate::{DhtKeyId, DhtNode};
+
+/// Handler intercepting Store and FindValue queries for keys of some namespace,
+/// e.g. to serve generated values or to bridge to external database
+pub trait KeyNamespaceHandler: Send + Sync {
+    /// Handle incoming Store of value with key name starting with namespace.
+    /// TTL, network and whitelist are already checked, signature is not.
+    /// Returns false to fall back to default storage
+    fn store(&self, key_id: &DhtKeyId, value: &DhtValue) -> Result<bool>;
+    /// Handle incoming FindValue. Query carries key ID only, so handler has to
+    /// recognize IDs of its keys itself. Returns None to fall back to default storage
+    fn find(&self, key_id: &DhtKeyId) -> Result<Option<DhtValue>>;
+}
+
+type Handlers = Vec<(Vec<u8>, Arc<dyn KeyNamespaceHandler>)>;
+
+/// Registered key namespace handlers
+pub(crate) struct KeyNamespaces {
+    handlers: RwLock<Handlers>,
+}
+
+impl KeyNamespaces {
+    pub(crate) fn new() -> Self {
+        Self {
+            handlers: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Ask handlers for value with given key ID in order of registration
+    pub(crate) fn find(&self, key_id: &DhtKeyId) -> Result<Option<DhtValue>> {
+        let handlers = self.snapshot();
+        for (_, handler) in handlers.iter() {
+            if let Some(value) = handler.find(key_id)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Handler of the longest namespace matching key name
+    pub(crate) fn handler(&self, name: &[u8]) -> Option<Arc<dyn KeyNamespaceHandler>> {
+        self.snapshot()
+            .into_iter()
+            .filter(|(namespace, _)| name.starts_with(namespace))
+            .max_by_key(|(namespace, _)| namespace.len())
+            .map(|(_, handler)| handler)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.snapshot().is_empty()
+    }
+
+    fn snapshot(&self) -> Handlers {
+        match self.handlers.read() {
+            Ok(handlers) => handlers.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Handlers)) {
+        match self.handlers.write() {
+            Ok(mut handlers) => update(&mut *handlers),
+            Err(e) => update(&mut *e.into_inner()),
+        }
+    }
+}
+
+impl DhtNode {
+    /// Register handler for keys with names starting with given namespace,
+    /// replacing previous handler of the same namespace
+    pub fn register_namespace(&self, namespace: &str, handler: Arc<dyn KeyNamespaceHandler>) {
+        let namespace = namespace.as_bytes().to_vec();
+        self.namespaces.update(|handlers| {
+            handlers.retain(|(registered, _)| registered != &namespace);
+            handlers.push((namespace, handler))
+        })
+    }
+
+    /// Remove handler of given namespace
+    pub fn unregister_namespace(&self, namespace: &str) -> bool {
+        let mut removed = false;
+        self.namespaces.update(|handlers| {
+            let count = handlers.len();
+            handlers.retain(|(registered, _)| registered != namespace.as_bytes());
+            removed = handlers.len() < count
+        });
+        removed
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use adnl::common::KeyId;
+use dashmap::DashMap;
+use overlay::OverlayShortId;
+use tokio::sync::watch;
+use ton_api::ton::overlay::node::Node as OverlayNode;
+
+use crate::{DhtNode, ResolvedOverlayNode, TARGET};
+
+/// Cache of continually refreshed overlay nodes
+pub(crate) struct OverlayNodesCache {
+    overlays: DashMap<Arc<OverlayShortId>, Arc<TrackedOverlay>>,
+    requeued: DashMap<Arc<OverlayShortId>, Vec<OverlayNode>>,
+}
+
+impl OverlayNodesCache {
+    const MAX_REQUEUED_NODES: usize = 256;
+    const MAX_REQUEUED_OVERLAYS: usize = 1024;
+    const MIN_NODES: usize = 5;
+    const REFRESH_INTERVAL_MS: u64 = 5000;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            overlays: DashMap::new(),
+            requeued: DashMap::new(),
+        }
+    }
+
+    /// Keep overlay nodes left unresolved by aborted search, next search of the
+    /// overlay starts with them
+    pub(crate) fn requeue(
+        &self,
+        overlay_id: &Arc<OverlayShortId>,
+        nodes: impl IntoIterator<Item = OverlayNode>,
+    ) {
+        let mut nodes = nodes.into_iter().peekable();
+        if nodes.peek().is_none() {
+            return;
+        }
+        if !self.requeued.contains_key(overlay_id)
+            && (self.requeued.len() >= Self::MAX_REQUEUED_OVERLAYS)
+        {
+            return;
+        }
+        let mut requeued = self
+            .requeued
+            .entry(overlay_id.clone())
+            .or_insert_with(Vec::new);
+        requeued.extend(nodes);
+        requeued.truncate(Self::MAX_REQUEUED_NODES)
+    }
+
+    /// Take overlay nodes requeued by previous search of the overlay
+    pub(crate) fn take_requeued(&self, overlay_id: &Arc<OverlayShortId>) -> Vec<OverlayNode> {
+        self.requeued
+            .remove(overlay_id)
+            .map(|(_, nodes)| nodes)
+            .unwrap_or_default()
+    }
+}
+
+struct TrackedOverlay {
+    changes: watch::Sender<u32>,
+    generation: AtomicU32,
+    nodes: DashMap<Arc<KeyId>, Arc<ResolvedOverlayNode>>,
+    receiver: watch::Receiver<u32>,
+    stopped: AtomicBool,
+}
+
+impl TrackedOverlay {
+    fn merge(&self, found: Vec<ResolvedOverlayNode>) {
+        use dashmap::mapref::entry::Entry;
+
+        let mut changed = false;
+        for node in found {
+            match self.nodes.entry(node.adnl_key.id().clone()) {
+                Entry::Occupied(entry) => {
+                    if entry.get().node.version < node.node.version {
+                        entry.replace_entry(Arc::new(node));
+                        changed = true;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(Arc::new(node));
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+            self.changes.send(generation).ok();
+        }
+    }
+}
+
+impl DhtNode {
+    /// Start tracking nodes of overlay with given ID in background.
+    /// Returned receiver is notified each time the set of nodes changes
+    pub fn track_overlay(
+        dht: &Arc<Self>,
+        overlay_id: &Arc<OverlayShortId>,
+    ) -> watch::Receiver<u32> {
+        use dashmap::mapref::entry::Entry;
+
+        let tracked = match dht.overlay_cache.overlays.entry(overlay_id.clone()) {
+            Entry::Occupied(entry) => return entry.get().receiver.clone(),
+            Entry::Vacant(entry) => {
+                let (changes, receiver) = watch::channel(0);
+                let tracked = Arc::new(TrackedOverlay {
+                    changes,
+                    generation: AtomicU32::new(0),
+                    nodes: DashMap::new(),
+                    receiver,
+                    stopped: AtomicBool::new(false),
+                });
+                entry.insert(tracked.clone());
+                tracked
+            }
+        };
+        let receiver = tracked.receiver.clone();
+        let weak_dht = Arc::downgrade(dht);
+        let overlay_id = overlay_id.clone();
+        let tasks = dht.tasks.clone();
+        dht.spawn(async move {
+            let mut iter = None;
+            while !tracked.stopped.load(Ordering::Relaxed) && !tasks.is_stopped() {
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                match DhtNode::find_overlay_nodes(
+                    &dht,
+                    &overlay_id,
+                    &mut iter,
+                    OverlayNodesCache::MIN_NODES,
+                )
+                .await
+                {
+                    Ok(found) => tracked.merge(found),
+                    Err(e) => log::warn!(
+                        target: TARGET,
+                        "Cannot refresh nodes of overlay {}: {}",
+                        overlay_id,
+                        e
+                    ),
+                }
+                drop(dht);
+                let running = tasks
+                    .sleep(Duration::from_millis(
+                        OverlayNodesCache::REFRESH_INTERVAL_MS,
+                    ))
+                    .await;
+                if !running {
+                    break;
+                }
+            }
+            log::debug!(target: TARGET, "Stopped tracking overlay {}", overlay_id);
+        });
+        receiver
+    }
+
+    /// Stop tracking nodes of overlay with given ID
+    pub fn untrack_overlay(&self, overlay_id: &Arc<OverlayShortId>) {
+        if let Some((_, tracked)) = self.overlay_cache.overlays.remove(overlay_id) {
+            tracked.stopped.store(true, Ordering::Relaxed)
+        }
+    }
+
+    /// Currently known nodes of tracked overlay with given ID
+    pub fn current_nodes(&self, overlay_id: &Arc<OverlayShortId>) -> Vec<Arc<ResolvedOverlayNode>> {
+        if let Some(tracked) = self.overlay_cache.overlays.get(overlay_id) {
+            tracked
+                .value()
+                .nodes
+                .iter()
+                .map(|node| node.value().clone())
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ton_api::ton::{self, pub_::publickey::Ed25519};
+    use ton_api::IntoBoxed;
+
+    fn node(version: i32) -> OverlayNode {
+        OverlayNode {
+            id: Ed25519 {
+                key: ton::int256([version as u8; 32]),
+            }
+            .into_boxed(),
+            overlay: ton::int256([1; 32]),
+            version,
+            signature: ton::bytes::default(),
+        }
+    }
+
+    #[test]
+    fn requeued_nodes_are_taken_once() {
+        let cache = OverlayNodesCache::new();
+        let overlay_id = OverlayShortId::from_data([1; 32]);
+        cache.requeue(&overlay_id, Vec::new());
+        assert!(cache.requeued.is_empty());
+        cache.requeue(&overlay_id, vec![node(1), node(2)]);
+        cache.requeue(&overlay_id, vec![node(3)]);
+        let versions: Vec<i32> = cache
+            .take_requeued(&overlay_id)
+            .iter()
+            .map(|node| node.version)
+            .collect();
+        assert_eq!(versions, vec![1, 2, 3]);
+        assert!(cache.take_requeued(&overlay_id).is_empty());
+    }
+
+    #[test]
+    fn requeued_nodes_are_capped() {
+        let cache = OverlayNodesCache::new();
+        let overlay_id = OverlayShortId::from_data([1; 32]);
+        let count = OverlayNodesCache::MAX_REQUEUED_NODES as i32 + 10;
+        cache.requeue(&overlay_id, (0..count).map(node));
+        assert_eq!(
+            cache.take_requeued(&overlay_id).len(),
+            OverlayNodesCache::MAX_REQUEUED_NODES
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use overlay::{OverlayShortId, OverlayUtils};
+use ton_api::ton::overlay::node::Node as OverlayNode;
+use ton_types::{error, Result};
+
+use crate::{DhtError, DhtNode};
+
+/// Verifier of members of semi-private overlay, e.g. checking certificates
+/// issued to member keys by overlay owners
+pub trait OverlayMemberVerifier: Send + Sync {
+    /// Check that node may be listed in overlay nodes, node signature is already checked
+    fn verify(&self, overlay_id: &Arc<OverlayShortId>, node: &OverlayNode) -> Result<()>;
+}
+
+impl DhtNode {
+    /// Register verifier of nodes of semi-private overlay with given ID, replacing
+    /// previous one. Stored and found nodes of this overlay which fail verification
+    /// are rejected
+    pub fn register_overlay_verifier(
+        &self,
+        overlay_id: &Arc<OverlayShortId>,
+        verifier: Arc<dyn OverlayMemberVerifier>,
+    ) {
+        self.overlay_verifiers.insert(overlay_id.clone(), verifier);
+    }
+
+    /// Remove verifier of overlay with given ID
+    pub fn unregister_overlay_verifier(&self, overlay_id: &Arc<OverlayShortId>) -> bool {
+        self.overlay_verifiers.remove(overlay_id).is_some()
+    }
+
+    pub(crate) fn verify_overlay_member(
+        &self,
+        overlay_id: &Arc<OverlayShortId>,
+        node: &OverlayNode,
+    ) -> Result<()> {
+        OverlayUtils::verify_node(overlay_id, node)?;
+        let verifier = self
+            .overlay_verifiers
+            .get(overlay_id)
+            .map(|verifier| verifier.value().clone());
+        if let Some(verifier) = verifier {
+            verifier
+                .verify(overlay_id, node)
+                .map_err(|e| error!(DhtError::Verification(e.to_string())))?
+        }
+        Ok(())
+    }
+}
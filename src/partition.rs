@@ -0,0 +1,242 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use adnl::common::{KeyId, Wait};
+use sha2::{Digest, Sha256};
+use ton_types::{fail, Result};
+
+use crate::{distance, storage_probe::PeerValueAnswer, DhtKeyId, DhtNode, TARGET};
+
+/// Outcome of resolving key through one peer set
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathOutcome {
+    /// Value with given SHA-256 of payload was found
+    Found([u8; 32]),
+    /// Peers of the set returned values with different payloads
+    Conflicting,
+    /// Peers answered without the value
+    NotFound,
+    /// No peer of the set answered
+    NoAnswer,
+}
+
+/// Outcomes of resolving one key through disjoint peer sets
+#[derive(Clone, Debug)]
+pub struct ConsistencyReport {
+    /// Key ID
+    pub key: DhtKeyId,
+    /// Outcome per peer set
+    pub paths: Vec<PathOutcome>,
+}
+
+impl ConsistencyReport {
+    /// Whether peers disagree: peers of some set returned different values, peer sets
+    /// found different values, or some found the value while others did not
+    pub fn is_divergent(&self) -> bool {
+        if self.paths.contains(&PathOutcome::Conflicting) {
+            return true;
+        }
+        let mut answered = self
+            .paths
+            .iter()
+            .filter(|outcome| **outcome != PathOutcome::NoAnswer);
+        match answered.next() {
+            Some(first) => answered.any(|outcome| outcome != first),
+            None => false,
+        }
+    }
+}
+
+/// Handle of background partition monitoring
+pub struct PartitionMonitor {
+    reports: Arc<Mutex<Vec<ConsistencyReport>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl PartitionMonitor {
+    /// Stop monitoring
+    pub fn cancel(&self) {
+        self.stopped.store(true, Ordering::Relaxed)
+    }
+
+    /// Reports of last check round
+    pub fn last_reports(&self) -> Vec<ConsistencyReport> {
+        match self.reports.lock() {
+            Ok(reports) => reports.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+}
+
+impl DhtNode {
+    const PEERS_PER_PATH: usize = 8;
+
+    /// Resolve each key through `paths` disjoint sets of known peers closest to the key
+    /// and compare outcomes. Values are verified by update rule of their keys.
+    /// Divergence may signal network partition or censorship
+    pub async fn check_consistency(
+        dht: &Arc<Self>,
+        keys: &[DhtKeyId],
+        paths: usize,
+    ) -> Result<Vec<ConsistencyReport>> {
+        if paths < 2 {
+            fail!("At least two peer sets are required to check consistency")
+        }
+        let mut peers = Vec::new();
+        dht.buckets.for_each(|peer, _| peers.push(peer.clone()));
+        let mut ret = Vec::with_capacity(keys.len());
+        for key in keys {
+            peers.sort_by_cached_key(|peer| distance::xor_metric(peer.data(), key));
+            let mut outcomes = Vec::with_capacity(paths);
+            for path in 0..paths {
+                // Closest peers are dealt round-robin between sets
+                let set: Vec<_> = peers
+                    .iter()
+                    .skip(path)
+                    .step_by(paths)
+                    .take(Self::PEERS_PER_PATH)
+                    .cloned()
+                    .collect();
+                outcomes.push(Self::resolve_through(dht, key, set).await)
+            }
+            let report = ConsistencyReport {
+                key: *key,
+                paths: outcomes,
+            };
+            if report.is_divergent() {
+                log_event!(
+                    warn,
+                    "partition",
+                    key = base64::encode(key),
+                    outcome = "divergent",
+                    paths = format!("{:?}", report.paths)
+                );
+            }
+            ret.push(report)
+        }
+        Ok(ret)
+    }
+
+    /// Each `interval` check consistency of keys like `check_consistency` does
+    pub fn monitor_partitions(
+        dht: &Arc<Self>,
+        keys: Vec<DhtKeyId>,
+        paths: usize,
+        interval: Duration,
+    ) -> PartitionMonitor {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let monitor = PartitionMonitor {
+            reports: reports.clone(),
+            stopped: stopped.clone(),
+        };
+        let weak_dht = Arc::downgrade(dht);
+        let tasks = dht.tasks.clone();
+        dht.spawn(async move {
+            loop {
+                let running = tasks.sleep(interval).await;
+                if !running || stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                match Self::check_consistency(&dht, &keys, paths).await {
+                    Ok(new_reports) => match reports.lock() {
+                        Ok(mut old) => *old = new_reports,
+                        Err(e) => *e.into_inner() = new_reports,
+                    },
+                    Err(e) => log::warn!(target: TARGET, "Cannot check DHT consistency: {}", e),
+                }
+            }
+        });
+        monitor
+    }
+
+    async fn resolve_through(dht: &Arc<Self>, key: &DhtKeyId, set: Vec<Arc<KeyId>>) -> PathOutcome {
+        let (wait, mut queue_reader) = Wait::new();
+        for peer in set {
+            let dht_cloned = dht.clone();
+            let key = *key;
+            let wait = wait.clone();
+            wait.request();
+            dht.spawn(async move {
+                let outcome = match dht_cloned.query_value(&peer, &key).await {
+                    PeerValueAnswer::Found(value) => {
+                        let mut digest = [0u8; 32];
+                        digest.copy_from_slice(Sha256::digest(&value.value.0).as_slice());
+                        PathOutcome::Found(digest)
+                    }
+                    PeerValueAnswer::NotFound | PeerValueAnswer::Invalid => PathOutcome::NotFound,
+                    PeerValueAnswer::NoAnswer => PathOutcome::NoAnswer,
+                };
+                wait.respond(Some(outcome))
+            });
+        }
+        let mut ret = PathOutcome::NoAnswer;
+        while let Some(answer) = wait.wait(&mut queue_reader, false).await {
+            if let Some(answer) = answer {
+                ret = merge_outcomes(ret, answer)
+            }
+        }
+        ret
+    }
+}
+
+/// Outcome of peer set out of outcome of its peers so far and of one more peer
+fn merge_outcomes(set: PathOutcome, peer: PathOutcome) -> PathOutcome {
+    match (set, peer) {
+        (PathOutcome::Conflicting, _) | (_, PathOutcome::Conflicting) => PathOutcome::Conflicting,
+        (PathOutcome::Found(a), PathOutcome::Found(b)) if a != b => PathOutcome::Conflicting,
+        (PathOutcome::Found(digest), _) | (_, PathOutcome::Found(digest)) => {
+            PathOutcome::Found(digest)
+        }
+        (PathOutcome::NotFound, _) | (_, PathOutcome::NotFound) => PathOutcome::NotFound,
+        (PathOutcome::NoAnswer, PathOutcome::NoAnswer) => PathOutcome::NoAnswer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(outcomes: &[PathOutcome]) -> PathOutcome {
+        outcomes.iter().fold(PathOutcome::NoAnswer, |set, peer| {
+            merge_outcomes(set, *peer)
+        })
+    }
+
+    #[test]
+    fn set_with_different_values_is_conflicting() {
+        let set = merge(&[
+            PathOutcome::Found([1; 32]),
+            PathOutcome::NotFound,
+            PathOutcome::Found([2; 32]),
+        ]);
+        assert_eq!(set, PathOutcome::Conflicting);
+        let report = ConsistencyReport {
+            key: [0; 32],
+            paths: vec![set, PathOutcome::NoAnswer],
+        };
+        assert!(report.is_divergent());
+    }
+
+    #[test]
+    fn found_value_wins_over_missing_one_in_set() {
+        let set = merge(&[
+            PathOutcome::NoAnswer,
+            PathOutcome::NotFound,
+            PathOutcome::Found([1; 32]),
+            PathOutcome::Found([1; 32]),
+        ]);
+        assert_eq!(set, PathOutcome::Found([1; 32]));
+        assert_eq!(merge(&[PathOutcome::NoAnswer]), PathOutcome::NoAnswer);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use adnl::common::{now, KeyId};
+use dashmap::DashMap;
+use ton_types::{fail, Result};
+
+use crate::{format::FileFormat, DhtError, DhtNode, TARGET};
+
+/// Size of encoded record: key ID followed by record fields
+const RECORD_SIZE: usize = 64;
+
+/// Long-term history of DHT peer, kept across restarts
+#[derive(Clone, Debug, Default)]
+pub struct PeerRecord {
+    /// Time the peer first became known
+    pub first_seen_at: i32,
+    /// Time the peer was last seen
+    pub last_seen_at: i32,
+    /// Number of answered queries
+    pub answered: u64,
+    /// Number of unanswered queries
+    pub failed: u64,
+    /// Number of times the peer was banned
+    pub bans: u32,
+    /// Time of last ban
+    pub last_banned_at: i32,
+}
+
+impl PeerRecord {
+    const BAN_MEMORY_SEC: i32 = 24 * 3600;
+    const MIN_QUERIES: u64 = 16;
+
+    /// Peer was banned during last day and not unbanned since, or answers less than
+    /// 20% of enough queries
+    pub fn is_unreliable(&self) -> bool {
+        ((self.bans > 0) && (now().saturating_sub(self.last_banned_at) < Self::BAN_MEMORY_SEC))
+            || ((self.answered + self.failed >= Self::MIN_QUERIES)
+                && (self.answered * 4 < self.failed))
+    }
+}
+
+/// Peer records, optionally persisted to file
+pub(crate) struct PeerDb {
+    path: Option<PathBuf>,
+    records: DashMap<Arc<KeyId>, PeerRecord>,
+}
+
+impl PeerDb {
+    const MAX_RECORDS: usize = 65536;
+
+    /// Open database, loading records from file at given path if any
+    pub(crate) fn open(path: Option<&Path>) -> Result<Self> {
+        let records = DashMap::new();
+        if let Some(path) = path.filter(|path| path.exists()) {
+            let mut data = Vec::new();
+            File::open(path)?.read_to_end(&mut data)?;
+            let (start, legacy) = FileFormat::PeerDb.read_header(&data, path, is_legacy)?;
+            if (data.len() - start) % RECORD_SIZE != 0 {
+                fail!(DhtError::Malformed(format!(
+                    "Bad size of peer database {}: {}",
+                    path.display(),
+                    data.len()
+                )))
+            }
+            for chunk in data[start..].chunks(RECORD_SIZE) {
+                let (peer, record) = decode_record(chunk);
+                records.insert(peer, record);
+            }
+            if legacy {
+                FileFormat::PeerDb.migrate(path, &data[start..])?
+            }
+            log::info!(
+                target: TARGET,
+                "Loaded {} DHT peer records from {}",
+                records.len(),
+                path.display()
+            );
+        }
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            records,
+        })
+    }
+
+    pub(crate) fn get(&self, peer: &Arc<KeyId>) -> Option<PeerRecord> {
+        self.records.get(peer).map(|record| record.value().clone())
+    }
+
+    pub(crate) fn is_unreliable(&self, peer: &Arc<KeyId>) -> bool {
+        self.records
+            .get(peer)
+            .map(|record| record.value().is_unreliable())
+            .unwrap_or(false)
+    }
+
+    /// Save records to file, returns number of saved records
+    pub(crate) fn save(&self) -> Result<usize> {
+        let path = if let Some(path) = &self.path {
+            path
+        } else {
+            return Ok(0);
+        };
+        let mut data = Vec::with_capacity(self.records.len() * RECORD_SIZE);
+        for record in self.records.iter() {
+            encode_record(&mut data, record.key(), record.value())
+        }
+        FileFormat::PeerDb.write(path, &data)?;
+        Ok(data.len() / RECORD_SIZE)
+    }
+
+    /// Update record of peer, new records are not added when database is full
+    pub(crate) fn update(&self, peer: &Arc<KeyId>, update: impl FnOnce(&mut PeerRecord)) {
+        if let Some(mut record) = self.records.get_mut(peer) {
+            update(record.value_mut());
+            return;
+        }
+        if self.records.len() >= Self::MAX_RECORDS {
+            return;
+        }
+        let now = now();
+        let mut record = self
+            .records
+            .entry(peer.clone())
+            .or_insert_with(|| PeerRecord {
+                first_seen_at: now,
+                last_seen_at: now,
+                ..Default::default()
+            });
+        update(record.value_mut())
+    }
+}
+
+impl DhtNode {
+    /// Long-term history of DHT peer
+    pub fn peer_record(&self, peer: &Arc<KeyId>) -> Option<PeerRecord> {
+        self.peer_db.get(peer)
+    }
+
+    /// Save peer database to file set in options. Returns number of saved records
+    pub fn save_peer_db(&self) -> Result<usize> {
+        self.peer_db.save()
+    }
+}
+
+fn decode_record(data: &[u8]) -> (Arc<KeyId>, PeerRecord) {
+    let peer = KeyId::from_data(*arrayref::array_ref!(data, 0, 32));
+    let record = PeerRecord {
+        first_seen_at: i32::from_le_bytes(*arrayref::array_ref!(data, 32, 4)),
+        last_seen_at: i32::from_le_bytes(*arrayref::array_ref!(data, 36, 4)),
+        answered: u64::from_le_bytes(*arrayref::array_ref!(data, 40, 8)),
+        failed: u64::from_le_bytes(*arrayref::array_ref!(data, 48, 8)),
+        bans: u32::from_le_bytes(*arrayref::array_ref!(data, 56, 4)),
+        last_banned_at: i32::from_le_bytes(*arrayref::array_ref!(data, 60, 4)),
+    };
+    (peer, record)
+}
+
+fn encode_record(data: &mut Vec<u8>, peer: &Arc<KeyId>, record: &PeerRecord) {
+    data.extend_from_slice(peer.data());
+    data.extend_from_slice(&record.first_seen_at.to_le_bytes());
+    data.extend_from_slice(&record.last_seen_at.to_le_bytes());
+    data.extend_from_slice(&record.answered.to_le_bytes());
+    data.extend_from_slice(&record.failed.to_le_bytes());
+    data.extend_from_slice(&record.bans.to_le_bytes());
+    data.extend_from_slice(&record.last_banned_at.to_le_bytes());
+}
+
+/// Database of version 0 consists of records with sane timestamps
+fn is_legacy(data: &[u8]) -> bool {
+    (data.len() % RECORD_SIZE == 0)
+        && data.chunks(RECORD_SIZE).all(|chunk| {
+            let (_, record) = decode_record(chunk);
+            (record.first_seen_at > 0)
+                && (record.first_seen_at <= record.last_seen_at)
+                && (record.last_banned_at >= 0)
+        })
+}
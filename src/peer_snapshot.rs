@@ -0,0 +1,181 @@
+use std::{cmp::Ordering as CmpOrdering, fs::File, io::Read, path::Path, time::Duration};
+
+use adnl::common::{deserialize, serialize};
+use ton_api::ton::dht::{node::Node, Node as NodeBoxed};
+use ton_api::IntoBoxed;
+use ton_types::{error, fail, Result};
+
+use crate::{format::FileFormat, DhtError, DhtNode, PeerStats, TARGET};
+
+/// TL constructor ID of `dht.node`
+const NODE_CONSTRUCTOR: u32 = 0x84533248;
+/// Size of encoded peer statistics preceding TL node in snapshot record
+const STATS_SIZE: usize = 44;
+
+impl DhtNode {
+    /// Restore peers along with their statistics from snapshot saved by `save_peers`.
+    /// Historically reliable peers are added first, so they are queried first.
+    /// Bad records are skipped with warning, truncated tail is ignored.
+    /// Returns number of restored peers
+    pub fn load_peers(&self, path: &Path) -> Result<usize> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        let (start, legacy) = FileFormat::PeerSnapshot.read_header(&data, path, is_legacy)?;
+        let mut records = Vec::new();
+        let mut pos = start;
+        while pos < data.len() {
+            if pos + 4 > data.len() {
+                log::warn!(
+                    target: TARGET,
+                    "Truncated record at {} in peers snapshot {}",
+                    pos,
+                    path.display()
+                );
+                break;
+            }
+            let len = u32::from_le_bytes(*arrayref::array_ref!(data, pos, 4)) as usize;
+            pos += 4;
+            if pos + len > data.len() {
+                log::warn!(
+                    target: TARGET,
+                    "Truncated record at {} in peers snapshot {}",
+                    pos,
+                    path.display()
+                );
+                break;
+            }
+            match decode_record(&data[pos..pos + len]) {
+                Ok(record) => records.push(record),
+                Err(e) => log::warn!(
+                    target: TARGET,
+                    "Skip bad record at {} in peers snapshot {}: {}",
+                    pos,
+                    path.display(),
+                    e
+                ),
+            }
+            pos += len;
+        }
+        if legacy {
+            FileFormat::PeerSnapshot.migrate(path, &data[start..])?
+        }
+        records.sort_by(|(_, a), (_, b)| compare_reliability(a, b));
+        let mut ret = 0;
+        for (node, stats) in records {
+            match self.add_peer(&node) {
+                Ok(result) => {
+                    if let Some(peer) = result.peer_id() {
+                        self.peer_stats.insert(peer.clone(), stats);
+                        ret += 1
+                    }
+                }
+                Err(e) => log::warn!(target: TARGET, "Cannot restore peer: {}", e),
+            }
+        }
+        log::info!(
+            target: TARGET,
+            "Restored {} DHT peers from {}",
+            ret,
+            path.display()
+        );
+        Ok(ret)
+    }
+
+    /// Save up to `limit` known peers along with their statistics, most reliable first.
+    /// Returns number of saved peers
+    pub fn save_peers(&self, path: &Path, limit: usize) -> Result<usize> {
+        let mut records = Vec::new();
+        for i in 0..=255 {
+            for (peer, node) in self.buckets.shard(i).iter() {
+                let stats = self
+                    .peer_stats
+                    .get(peer)
+                    .map(|stats| stats.value().clone())
+                    .unwrap_or_default();
+                records.push((node.clone(), stats))
+            }
+        }
+        records.sort_by(|(_, a), (_, b)| compare_reliability(a, b));
+        records.truncate(limit);
+        let mut data = Vec::new();
+        for (node, stats) in records.iter() {
+            let node = serialize(&node.clone().into_boxed())?;
+            let len = (STATS_SIZE + node.len()) as u32;
+            data.extend_from_slice(&len.to_le_bytes());
+            data.extend_from_slice(&encode_stats(stats));
+            data.extend_from_slice(&node);
+        }
+        FileFormat::PeerSnapshot.write(path, &data)?;
+        Ok(records.len())
+    }
+}
+
+/// More reliable peers go first: fewer failures in row, then higher answer rate,
+/// then lower RTT
+fn compare_reliability(a: &PeerStats, b: &PeerStats) -> CmpOrdering {
+    let rate = |stats: &PeerStats| stats.answered * 1000 / (stats.answered + stats.failed).max(1);
+    let rtt = |stats: &PeerStats| stats.avg_rtt.unwrap_or(PeerStats::MAX_TIMEOUT);
+    a.failed_in_row
+        .cmp(&b.failed_in_row)
+        .then(rate(b).cmp(&rate(a)))
+        .then(rtt(a).cmp(&rtt(b)))
+}
+
+/// Last RTT and backoff are not persisted: they are meaningless after restart,
+/// the same goes for storage probe counters which restart from scratch
+fn decode_record(data: &[u8]) -> Result<(Node, PeerStats)> {
+    if data.len() < STATS_SIZE {
+        fail!(DhtError::Malformed(format!(
+            "Too short peer record: {} bytes",
+            data.len()
+        )))
+    }
+    let node = deserialize(&data[STATS_SIZE..])?
+        .downcast::<NodeBoxed>()
+        .map_err(|object| {
+            error!(DhtError::Malformed(format!(
+                "Wrong peer node: {:?}",
+                object
+            )))
+        })?
+        .only();
+    Ok((node, decode_stats(&data[..STATS_SIZE])))
+}
+
+fn decode_stats(data: &[u8]) -> PeerStats {
+    let duration = |micros: u64| match micros {
+        0 => None,
+        x => Some(Duration::from_micros(x)),
+    };
+    PeerStats {
+        avg_rtt: duration(u64::from_le_bytes(*arrayref::array_ref!(data, 28, 8))),
+        rtt_var: duration(u64::from_le_bytes(*arrayref::array_ref!(data, 36, 8))),
+        answered: u64::from_le_bytes(*arrayref::array_ref!(data, 0, 8)),
+        failed: u64::from_le_bytes(*arrayref::array_ref!(data, 8, 8)),
+        failed_in_row: u32::from_le_bytes(*arrayref::array_ref!(data, 16, 4)),
+        last_answered_at: i32::from_le_bytes(*arrayref::array_ref!(data, 20, 4)),
+        last_seen_at: i32::from_le_bytes(*arrayref::array_ref!(data, 24, 4)),
+        ..Default::default()
+    }
+}
+
+fn encode_stats(stats: &PeerStats) -> Vec<u8> {
+    let micros = |duration: Option<Duration>| duration.map(|x| x.as_micros() as u64).unwrap_or(0);
+    let mut ret = Vec::with_capacity(STATS_SIZE);
+    ret.extend_from_slice(&stats.answered.to_le_bytes());
+    ret.extend_from_slice(&stats.failed.to_le_bytes());
+    ret.extend_from_slice(&stats.failed_in_row.to_le_bytes());
+    ret.extend_from_slice(&stats.last_answered_at.to_le_bytes());
+    ret.extend_from_slice(&stats.last_seen_at.to_le_bytes());
+    ret.extend_from_slice(&micros(stats.avg_rtt).to_le_bytes());
+    ret.extend_from_slice(&micros(stats.rtt_var).to_le_bytes());
+    ret
+}
+
+/// Snapshot of version 0 consists of statistics followed by boxed nodes
+fn is_legacy(data: &[u8]) -> bool {
+    FileFormat::is_record_log(data, false, |record| {
+        (record.len() > STATS_SIZE)
+            && record[STATS_SIZE..].starts_with(&NODE_CONSTRUCTOR.to_le_bytes())
+    })
+}
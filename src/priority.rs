@@ -0,0 +1,113 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use adnl::common::{KeyId, Wait};
+use ton_api::ton::dht::node::Node;
+use ton_types::Result;
+
+use crate::{AddPeerResult, DhtNode, TARGET};
+
+/// Handle of background pinging of priority peers
+pub struct PriorityPing {
+    stopped: Arc<AtomicBool>,
+}
+
+impl PriorityPing {
+    /// Stop pinging
+    pub fn cancel(&self) {
+        self.stopped.store(true, Ordering::Relaxed)
+    }
+}
+
+impl DhtNode {
+    /// Add DHT peer and mark it as priority one: it is never removed from routing table,
+    /// always queried first in lookups and stores
+    pub fn add_priority_peer(&self, peer: &Node) -> Result<AddPeerResult> {
+        let ret = self.add_peer(peer)?;
+        if let Some(peer_id) = ret.peer_id() {
+            self.priority_peers.insert(peer_id.clone(), ());
+        }
+        Ok(ret)
+    }
+
+    /// Check if DHT peer is priority one
+    pub fn is_priority_peer(&self, peer: &Arc<KeyId>) -> bool {
+        self.priority_peers.contains_key(peer)
+    }
+
+    /// Each `interval` ping all priority peers, keeping their statistics fresh
+    pub fn ping_priority_peers(dht: &Arc<Self>, interval: Duration) -> PriorityPing {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let ping = PriorityPing {
+            stopped: stopped.clone(),
+        };
+        let weak_dht = Arc::downgrade(dht);
+        let tasks = dht.tasks.clone();
+        dht.spawn(async move {
+            loop {
+                let running = tasks.sleep(interval).await;
+                if !running || stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let dht = if let Some(dht) = weak_dht.upgrade() {
+                    dht
+                } else {
+                    break;
+                };
+                let peers = dht.priority_peers();
+                if peers.is_empty() {
+                    continue;
+                }
+                let (wait, mut queue_reader) = Wait::new();
+                for peer in peers.iter() {
+                    let dht_cloned = dht.clone();
+                    let peer = peer.clone();
+                    let wait = wait.clone();
+                    wait.request();
+                    dht.spawn(async move {
+                        let answered = match dht_cloned.ping(&peer).await {
+                            Ok(answered) => answered,
+                            Err(e) => {
+                                log::debug!(target: TARGET, "Ping {} error: {}", peer, e);
+                                false
+                            }
+                        };
+                        wait.respond(Some(answered))
+                    });
+                }
+                let mut answered = 0;
+                while let Some(answer) = wait.wait(&mut queue_reader, false).await {
+                    if let Some(true) = answer {
+                        answered += 1
+                    }
+                }
+                log::debug!(
+                    target: TARGET,
+                    "{} of {} priority DHT peers answered ping",
+                    answered,
+                    peers.len()
+                );
+            }
+        });
+        ping
+    }
+
+    /// Priority DHT peers
+    pub fn priority_peers(&self) -> Vec<Arc<KeyId>> {
+        self.priority_peers
+            .iter()
+            .map(|peer| peer.key().clone())
+            .collect()
+    }
+
+    /// Unmark priority DHT peer, it stays known as regular one.
+    /// Returns false if the peer is not priority one
+    pub fn remove_priority_peer(&self, peer: &Arc<KeyId>) -> bool {
+        self.priority_peers.remove(peer).is_some()
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Weak,
+};
+
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+use ton_types::{error, fail, Result};
+
+use crate::{DhtError, DhtNode};
+
+/// Class of incoming DHT queries, each class is dispatched independently
+/// so cheap queries are never starved by expensive ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueryClass {
+    /// Ping, FindNode, GetSignedAddressList: processed immediately
+    Routing,
+    /// FindValue
+    Lookup,
+    /// Store
+    Store,
+}
+
+/// Queue depth of incoming queries class
+#[derive(Clone, Debug, Default)]
+pub struct QueueDepth {
+    /// Number of queries being processed
+    pub active: usize,
+    /// Number of queries waiting for a worker
+    pub queued: usize,
+}
+
+/// Bounded pool of workers processing incoming queries
+pub(crate) struct QueryPool {
+    active: Arc<AtomicUsize>,
+    backlog: AtomicUsize,
+    max_backlog: usize,
+    workers: Option<Arc<Semaphore>>,
+}
+
+impl QueryPool {
+    pub(crate) fn with_workers(workers: usize, max_backlog: usize) -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(0)),
+            backlog: AtomicUsize::new(0),
+            max_backlog,
+            workers: Some(Arc::new(Semaphore::new(workers))),
+        }
+    }
+
+    pub(crate) fn unbounded() -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(0)),
+            backlog: AtomicUsize::new(0),
+            max_backlog: 0,
+            workers: None,
+        }
+    }
+
+    /// Acquire worker, waiting in backlog if all workers are busy.
+    /// Fails if backlog is full
+    pub(crate) async fn acquire(&self) -> Result<QueryWorker> {
+        let permit = if let Some(workers) = &self.workers {
+            if let Ok(permit) = workers.clone().try_acquire_owned() {
+                Some(permit)
+            } else {
+                let _queued = BacklogGuard::enter(self)?;
+                let permit = workers.clone().acquire_owned().await;
+                Some(permit.map_err(|e| error!("Cannot acquire query worker: {}", e))?)
+            }
+        } else {
+            None
+        };
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Ok(QueryWorker {
+            active: self.active.clone(),
+            _permit: permit,
+        })
+    }
+
+    pub(crate) fn depth(&self) -> QueueDepth {
+        QueueDepth {
+            active: self.active.load(Ordering::Relaxed),
+            queued: self.backlog.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Place in backlog of query pool, freed when waiting query gets a worker
+/// or is dropped
+struct BacklogGuard<'a>(&'a AtomicUsize);
+
+impl<'a> BacklogGuard<'a> {
+    fn enter(pool: &'a QueryPool) -> Result<Self> {
+        if pool.backlog.fetch_add(1, Ordering::Relaxed) >= pool.max_backlog {
+            pool.backlog.fetch_sub(1, Ordering::Relaxed);
+            fail!(DhtError::Overloaded(format!(
+                "Query backlog of {} is full",
+                pool.max_backlog
+            )))
+        }
+        Ok(Self(&pool.backlog))
+    }
+}
+
+impl Drop for BacklogGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Busy worker of query pool
+pub(crate) struct QueryWorker {
+    active: Arc<AtomicUsize>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for QueryWorker {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Query pools for all query classes
+pub(crate) struct QueryPools {
+    dht: Weak<DhtNode>,
+    lookup: QueryPool,
+    routing: QueryPool,
+    store: QueryPool,
+}
+
+impl QueryPools {
+    pub(crate) fn new(
+        dht: Weak<DhtNode>,
+        lookup_workers: usize,
+        store_workers: usize,
+        max_backlog: usize,
+    ) -> Self {
+        Self {
+            dht,
+            lookup: QueryPool::with_workers(lookup_workers, max_backlog),
+            routing: QueryPool::unbounded(),
+            store: QueryPool::with_workers(store_workers, max_backlog),
+        }
+    }
+
+    pub(crate) fn get(&self, class: QueryClass) -> &QueryPool {
+        match class {
+            QueryClass::Lookup => &self.lookup,
+            QueryClass::Routing => &self.routing,
+            QueryClass::Store => &self.store,
+        }
+    }
+
+    /// Run query handler by worker of class pool. Routing handlers are cheap and run
+    /// inline, other ones run in separate tasks, so they do not stall ADNL receive
+    /// path. The worker is held till handler finishes even if the query is dropped
+    pub(crate) async fn run<R: Send + 'static>(
+        &self,
+        class: QueryClass,
+        handler: impl FnOnce(&DhtNode) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let dht = self
+            .dht
+            .upgrade()
+            .ok_or_else(|| error!(DhtError::Overloaded("DHT node is stopped".to_string())))?;
+        let worker = self.get(class).acquire().await?;
+        if class == QueryClass::Routing {
+            return handler(&dht);
+        }
+        let (sender, receiver) = oneshot::channel();
+        let node = dht.clone();
+        dht.spawn(async move {
+            let _worker = worker;
+            sender.send(handler(&node)).ok();
+        });
+        receiver
+            .await
+            .map_err(|_| error!("DHT query worker is stopped"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn full_backlog_is_refused() {
+        let pool = QueryPool::with_workers(1, 1);
+        let _busy = pool.acquire().await.unwrap();
+        let mut waiting = Box::pin(pool.acquire());
+        assert!(poll_once(&mut waiting).await.is_none());
+        assert_eq!(pool.depth().queued, 1);
+        assert!(pool.acquire().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dropped_query_leaves_backlog() {
+        let pool = QueryPool::with_workers(1, 1);
+        let busy = pool.acquire().await.unwrap();
+        {
+            let mut waiting = Box::pin(pool.acquire());
+            assert!(poll_once(&mut waiting).await.is_none());
+            assert_eq!(pool.depth().queued, 1);
+        }
+        assert_eq!(pool.depth().queued, 0);
+        drop(busy);
+        let _worker = pool.acquire().await.unwrap();
+        assert_eq!(pool.depth().active, 1);
+    }
+
+    /// Poll future for a moment, None if it is still pending
+    async fn poll_once<F: std::future::Future + Unpin>(future: &mut F) -> Option<F::Output> {
+        tokio::time::timeout(std::time::Duration::from_millis(10), future)
+            .await
+            .ok()
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::spawner::TaskTracker;
+
+/// Rate limit of outgoing DHT queries
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// Sustained number of queries per second
+    pub queries_per_sec: u32,
+    /// Number of queries which may be sent at once after idle period
+    pub burst: u32,
+}
+
+/// Token bucket shared by all outgoing queries of node
+pub(crate) struct RateLimiter {
+    burst: f64,
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: &RateLimit) -> Self {
+        let burst = limit.burst.max(1) as f64;
+        Self {
+            burst,
+            rate: limit.queries_per_sec.max(1) as f64,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Wait till query may be sent. Returns false if node tasks are stopped meanwhile
+    pub(crate) async fn acquire(&self, tasks: &TaskTracker) -> bool {
+        loop {
+            let delay = {
+                let mut state = match self.state.lock() {
+                    Ok(state) => state,
+                    Err(e) => e.into_inner(),
+                };
+                let (tokens, refilled_at) = &mut *state;
+                let now = Instant::now();
+                *tokens = (*tokens + now.duration_since(*refilled_at).as_secs_f64() * self.rate)
+                    .min(self.burst);
+                *refilled_at = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return true;
+                }
+                Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+            };
+            if !tasks.sleep(delay).await {
+                return false;
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+};
+
+use adnl::common::{KeyId, KeyOption};
+use adnl::node::{parse_address_list, AdnlNode};
+use dashmap::DashMap;
+use ton_api::ton::dht::node::Node;
+use ton_types::Result;
+
+use crate::{DhtNode, TARGET};
+
+/// Queue of peers to register in ADNL off the lookup path. Pending registrations
+/// of the same peer are coalesced, only the newest node version is registered.
+/// Registered peers are inserted into routing table of the node, peers refused by
+/// ADNL never get there
+pub(crate) struct PeerRegistrations {
+    adnl: Arc<AdnlNode>,
+    dht: Weak<DhtNode>,
+    draining: AtomicBool,
+    local_id: Arc<KeyId>,
+    pending: DashMap<Arc<KeyId>, (Arc<KeyOption>, Node)>,
+}
+
+impl PeerRegistrations {
+    pub(crate) fn new(adnl: Arc<AdnlNode>, local_id: Arc<KeyId>, dht: Weak<DhtNode>) -> Arc<Self> {
+        Arc::new(Self {
+            adnl,
+            dht,
+            draining: AtomicBool::new(false),
+            local_id,
+            pending: DashMap::new(),
+        })
+    }
+
+    /// Register all pending peers till queue is empty
+    pub(crate) fn drain(&self) {
+        loop {
+            let peers: Vec<Arc<KeyId>> = self.pending.iter().map(|e| e.key().clone()).collect();
+            if peers.is_empty() {
+                self.draining.store(false, Ordering::Release);
+                // Peer may have been queued right before the flag was reset
+                if self.pending.is_empty() || self.draining.swap(true, Ordering::AcqRel) {
+                    break;
+                }
+                continue;
+            }
+            for peer in peers {
+                if let Some((_, (key, node))) = self.pending.remove(&peer) {
+                    if let Err(e) = self.register(&key, &node) {
+                        log::debug!(target: TARGET, "Cannot register DHT peer {}: {}", peer, e)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue peer registration. Returns true if caller has to start draining
+    pub(crate) fn push(&self, key: Arc<KeyOption>, node: &Node) -> bool {
+        let mut entry = self
+            .pending
+            .entry(key.id().clone())
+            .or_insert_with(|| (key.clone(), node.clone()));
+        if entry.value().1.version < node.version {
+            *entry.value_mut() = (key, node.clone())
+        }
+        drop(entry);
+        !self.draining.swap(true, Ordering::AcqRel)
+    }
+
+    fn register(&self, key: &Arc<KeyOption>, node: &Node) -> Result<()> {
+        let dht = if let Some(dht) = self.dht.upgrade() {
+            dht
+        } else {
+            return Ok(());
+        };
+        // Peer may have been banned while its registration was pending
+        if dht.is_banned(key.id()) {
+            return Ok(());
+        }
+        let addrs = parse_address_list(&node.addr_list)?;
+        match self.adnl.add_peer(&self.local_id, &addrs, key)? {
+            Some(peer) => {
+                dht.insert_peer(&peer, node)?;
+            }
+            None => log::debug!(target: TARGET, "DHT peer {} rejected by ADNL", key.id()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use adnl::common::KeyId;
+
+use crate::{DhtError, DhtNode, TARGET};
+
+/// Reason of rejecting incoming DHT query. DHT protocol has no error answers,
+/// so the remote just gets no answer, while reasons are counted and logged locally
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RejectReason {
+    /// Query is not served in node role
+    Role,
+    /// Node is overloaded with queries
+    RateLimited,
+    /// Value or node exceeds configured validation limits, e.g. of value size
+    Quota,
+    /// Signature, TTL or key check of value failed
+    BadSignature,
+    /// Value update rule is not supported
+    UnsupportedRule,
+    /// Query type is not supported
+    UnsupportedQuery,
+    /// Query is not allowed by node policy, e.g. whitelist or ban
+    Policy,
+    /// Query cannot be parsed or has unexpected format
+    Malformed,
+    /// Query processing failed for other reason
+    Internal,
+}
+
+impl RejectReason {
+    const ALL: [RejectReason; 9] = [
+        RejectReason::Role,
+        RejectReason::RateLimited,
+        RejectReason::Quota,
+        RejectReason::BadSignature,
+        RejectReason::UnsupportedRule,
+        RejectReason::UnsupportedQuery,
+        RejectReason::Policy,
+        RejectReason::Malformed,
+        RejectReason::Internal,
+    ];
+
+    /// Classify error of query processing
+    pub fn from_error(error: &failure::Error) -> Self {
+        match error.downcast_ref::<DhtError>() {
+            Some(DhtError::Overloaded(_)) => RejectReason::RateLimited,
+            Some(DhtError::QuotaExceeded(_)) => RejectReason::Quota,
+            Some(DhtError::Verification(_)) => RejectReason::BadSignature,
+            Some(DhtError::UnsupportedRule(_)) => RejectReason::UnsupportedRule,
+            Some(DhtError::Rejected(_)) => RejectReason::Policy,
+            Some(DhtError::Malformed(_)) => RejectReason::Malformed,
+            _ => RejectReason::Internal,
+        }
+    }
+
+    /// Short name used in events
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RejectReason::Role => "role",
+            RejectReason::RateLimited => "rate_limited",
+            RejectReason::Quota => "quota",
+            RejectReason::BadSignature => "bad_signature",
+            RejectReason::UnsupportedRule => "unsupported_rule",
+            RejectReason::UnsupportedQuery => "unsupported_query",
+            RejectReason::Policy => "policy",
+            RejectReason::Malformed => "malformed",
+            RejectReason::Internal => "internal",
+        }
+    }
+}
+
+/// Counters of rejected incoming queries per reason
+#[derive(Default)]
+pub(crate) struct Rejections {
+    counters: [AtomicU64; 9],
+}
+
+impl DhtNode {
+    /// Numbers of rejected incoming queries per reason, zero ones are omitted
+    pub fn rejections(&self) -> Vec<(RejectReason, u64)> {
+        RejectReason::ALL
+            .iter()
+            .zip(self.rejections.counters.iter())
+            .map(|(reason, counter)| (*reason, counter.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    /// Count and log rejected incoming query
+    pub(crate) fn reject_query(
+        &self,
+        peer: &Arc<KeyId>,
+        query: &'static str,
+        reason: RejectReason,
+    ) {
+        self.rejections.counters[reason as usize].fetch_add(1, Ordering::Relaxed);
+        log_event!(
+            debug,
+            "incoming_query",
+            peer = peer,
+            query = query,
+            outcome = "rejected",
+            reason = reason.as_str()
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+/// Task spawned by DHT node
+pub type SpawnedTask = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Executor and timer used by DHT node to run background and lookup tasks.
+/// All sleeps and timeouts of node tasks go through it, only host name resolution
+/// by `SystemResolver` and `build_dht_node_info_with_resolver` needs Tokio runtime
+pub trait Spawner: Send + Sync {
+    /// Future which completes after given duration
+    fn sleep(&self, duration: Duration) -> SpawnedTask;
+    /// Run task in background
+    fn spawn(&self, task: SpawnedTask);
+}
+
+impl fmt::Debug for dyn Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Spawner")
+    }
+}
+
+/// Default spawner running tasks on current Tokio runtime
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioSpawner;
+
+impl Spawner for TokioSpawner {
+    fn sleep(&self, duration: Duration) -> SpawnedTask {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn spawn(&self, task: SpawnedTask) {
+        tokio::spawn(task);
+    }
+}
+
+/// Group of tasks aborted together once their results are no longer needed
+pub(crate) struct TaskGroup {
+    aborted: AtomicBool,
+    aborting: Notify,
+}
+
+impl TaskGroup {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            aborted: AtomicBool::new(false),
+            aborting: Notify::new(),
+        })
+    }
+
+    /// Abort all tasks of the group, they are dropped at their next await point
+    pub(crate) fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+        self.aborting.notify_waiters()
+    }
+
+    /// Wrap task into the group
+    pub(crate) fn run(
+        self: &Arc<Self>,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let group = self.clone();
+        Abortable {
+            aborting: Box::pin(async move {
+                let aborting = group.aborting.notified();
+                if !group.aborted.load(Ordering::Relaxed) {
+                    aborting.await
+                }
+            }),
+            task: Box::pin(task),
+        }
+    }
+}
+
+struct Abortable {
+    aborting: SpawnedTask,
+    task: SpawnedTask,
+}
+
+impl Future for Abortable {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if this.aborting.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(());
+        }
+        this.task.as_mut().poll(cx)
+    }
+}
+
+/// Tracker of tasks spawned by DHT node, joined on shutdown
+pub(crate) struct TaskTracker {
+    active: AtomicUsize,
+    finished: Notify,
+    spawner: Arc<dyn Spawner>,
+    stopped: AtomicBool,
+    stopping: Notify,
+}
+
+impl TaskTracker {
+    pub(crate) fn new(spawner: Arc<dyn Spawner>) -> Arc<Self> {
+        Arc::new(Self {
+            active: AtomicUsize::new(0),
+            finished: Notify::new(),
+            spawner,
+            stopped: AtomicBool::new(false),
+            stopping: Notify::new(),
+        })
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Stop background tasks and wait for all tasks to finish.
+    /// Returns false if some tasks are still running after timeout
+    pub(crate) async fn join(&self, timeout: Duration) -> bool {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.stopping.notify_waiters();
+        let finished = async {
+            loop {
+                let notified = self.finished.notified();
+                if self.active.load(Ordering::Acquire) == 0 {
+                    break;
+                }
+                notified.await
+            }
+        };
+        Timeout {
+            task: Box::pin(finished),
+            timer: self.spawner.sleep(timeout),
+        }
+        .await
+        .is_some()
+    }
+
+    /// Sleep unless stopped. Returns false if stopped
+    pub(crate) async fn sleep(&self, duration: Duration) -> bool {
+        let stopping = self.stopping.notified();
+        if self.is_stopped() {
+            return false;
+        }
+        Timeout {
+            task: Box::pin(stopping),
+            timer: self.spawner.sleep(duration),
+        }
+        .await
+        .is_none()
+    }
+
+    /// Run task till it finishes, duration elapses or tracker is stopped.
+    /// Returns None if task has not finished
+    pub(crate) async fn timeout<T>(
+        &self,
+        duration: Duration,
+        task: impl Future<Output = T> + Send,
+    ) -> Option<T> {
+        Timeout {
+            task: Box::pin(task),
+            timer: Box::pin(async move {
+                self.sleep(duration).await;
+            }),
+        }
+        .await
+    }
+
+    pub(crate) fn track(
+        self: &Arc<Self>,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> SpawnedTask {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        let guard = TaskGuard(self.clone());
+        Box::pin(async move {
+            let _guard = guard;
+            task.await
+        })
+    }
+}
+
+struct Timeout<'a, T> {
+    task: Pin<Box<dyn Future<Output = T> + Send + 'a>>,
+    timer: Pin<Box<dyn Future<Output = ()> + Send + 'a>>,
+}
+
+impl<T> Future for Timeout<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        if let Poll::Ready(ret) = this.task.as_mut().poll(cx) {
+            return Poll::Ready(Some(ret));
+        }
+        if this.timer.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+struct TaskGuard(Arc<TaskTracker>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.finished.notify_waiters()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spawner whose timers fire at once
+    struct InstantSpawner;
+
+    impl Spawner for InstantSpawner {
+        fn sleep(&self, _duration: Duration) -> SpawnedTask {
+            Box::pin(async {})
+        }
+
+        fn spawn(&self, task: SpawnedTask) {
+            tokio::spawn(task);
+        }
+    }
+
+    #[tokio::test]
+    async fn timers_go_through_spawner() {
+        let tasks = TaskTracker::new(Arc::new(InstantSpawner));
+        let never = tokio::sync::Notify::new();
+        assert!(tasks.sleep(Duration::from_secs(3600)).await);
+        assert!(tasks
+            .timeout(Duration::from_secs(3600), never.notified())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn timeout_returns_finished_task() {
+        let tasks = TaskTracker::new(Arc::new(TokioSpawner));
+        assert_eq!(
+            tasks.timeout(Duration::from_secs(1), async { 1 }).await,
+            Some(1)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_elapses_or_stops() {
+        let tasks = TaskTracker::new(Arc::new(TokioSpawner));
+        let never = tokio::sync::Notify::new();
+        assert!(tasks
+            .timeout(Duration::from_secs(1), never.notified())
+            .await
+            .is_none());
+        assert!(tasks.join(Duration::from_secs(1)).await);
+        assert!(tasks
+            .timeout(Duration::from_secs(3600), never.notified())
+            .await
+            .is_none());
+    }
+}
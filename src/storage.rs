@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use ton_api::ton::dht::value::Value as DhtValue;
+
+/// Value in local storage
+#[derive(Clone)]
+pub(crate) struct StoredValue {
+    /// Payload was compressed by us, payloads received from peers are never
+    /// treated as compressed whatever bytes they start with
+    pub(crate) compressed: bool,
+    pub(crate) inner: DhtValue,
+}
+
+impl StoredValue {
+    pub(crate) fn new(value: DhtValue) -> Self {
+        Self {
+            compressed: false,
+            inner: value,
+        }
+    }
+}
+
+impl Deref for StoredValue {
+    type Target = DhtValue;
+    fn deref(&self) -> &DhtValue {
+        &self.inner
+    }
+}
+
+impl DerefMut for StoredValue {
+    fn deref_mut(&mut self) -> &mut DhtValue {
+        &mut self.inner
+    }
+}
+
+/// Map split into fixed array of shards, each guarded by its own lightweight lock,
+/// so operations on keys from different shards never contend
+pub(crate) struct ShardedMap<K, V> {
+    shards: Vec<RwLock<HashMap<K, V>>>,
+}
+
+impl<K: Eq + Hash, V> ShardedMap<K, V> {
+    pub(crate) const SHARDS: usize = 256;
+
+    pub(crate) fn new() -> Self {
+        let mut shards = Vec::with_capacity(Self::SHARDS);
+        shards.resize_with(Self::SHARDS, || RwLock::new(HashMap::new()));
+        Self { shards }
+    }
+
+    /// Call function for each entry, locking one shard at a time
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&K, &V)) {
+        for i in 0..Self::SHARDS {
+            for (key, value) in self.shard(i as u8).iter() {
+                f(key, value)
+            }
+        }
+    }
+
+    /// Total number of entries
+    pub(crate) fn len(&self) -> usize {
+        (0..Self::SHARDS).map(|i| self.shard(i as u8).len()).sum()
+    }
+
+    /// Keep only entries matching predicate
+    pub(crate) fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for i in 0..Self::SHARDS {
+            self.shard_mut(i as u8).retain(|key, value| f(key, value))
+        }
+    }
+
+    /// Read access to shard with given index
+    pub(crate) fn shard(&self, index: u8) -> RwLockReadGuard<'_, HashMap<K, V>> {
+        match self.shards[index as usize].read() {
+            Ok(shard) => shard,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Write access to shard with given index
+    pub(crate) fn shard_mut(&self, index: u8) -> RwLockWriteGuard<'_, HashMap<K, V>> {
+        match self.shards[index as usize].write() {
+            Ok(shard) => shard,
+            Err(e) => e.into_inner(),
+        }
+    }
+}
+
+/// Storage keyed by hashes, sharded by the first byte of the key
+impl<V> ShardedMap<[u8; 32], V> {
+    pub(crate) fn get(&self, key: &[u8; 32]) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key[0]).get(key).cloned()
+    }
+
+    pub(crate) fn insert(&self, key: [u8; 32], value: V) -> Option<V> {
+        self.shard_mut(key[0]).insert(key, value)
+    }
+
+    pub(crate) fn remove(&self, key: &[u8; 32]) -> Option<V> {
+        self.shard_mut(key[0]).remove(key)
+    }
+
+    /// Write access to the shard holding given key
+    pub(crate) fn write(&self, key: &[u8; 32]) -> RwLockWriteGuard<'_, HashMap<[u8; 32], V>> {
+        self.shard_mut(key[0])
+    }
+}
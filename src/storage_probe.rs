@@ -0,0 +1,115 @@
+use std::{sync::Arc, time::Duration};
+
+use adnl::common::{KeyId, Query, Wait};
+use ton_api::ton::{self, rpc, TLObject};
+use ton_types::{fail, Result};
+
+use crate::{DhtKeyId, DhtNode, DhtValue, DhtValueResult, StoreReport, TARGET};
+
+/// Outcome of probing peer for value it acknowledged to store
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProbeOutcome {
+    /// Peer returned the value
+    Retained,
+    /// Peer answered without the value or with value which failed verification
+    Dropped,
+    /// Peer did not answer
+    NoAnswer,
+}
+
+/// Answer of single peer to FindValue query
+pub(crate) enum PeerValueAnswer {
+    /// Peer returned value which passed verification by update rule of its key
+    Found(DhtValue),
+    /// Peer answered without the value
+    NotFound,
+    /// Peer returned value which failed verification, or malformed answer
+    Invalid,
+    /// Peer did not answer
+    NoAnswer,
+}
+
+impl DhtNode {
+    /// After `delay`, query each peer which confirmed the store for the value with given
+    /// key ID. Peers answering without the value are counted in their statistics
+    /// and are avoided in further stores
+    pub async fn audit_storage(
+        dht: &Arc<Self>,
+        key: &DhtKeyId,
+        report: &StoreReport,
+        delay: Duration,
+    ) -> Result<Vec<(Arc<KeyId>, ProbeOutcome)>> {
+        if !dht.tasks.sleep(delay).await {
+            fail!("DHT node is stopped")
+        }
+        let (wait, mut queue_reader) = Wait::new();
+        for peer in report.confirmed.iter() {
+            let dht_cloned = dht.clone();
+            let peer = peer.clone();
+            let key = *key;
+            let wait = wait.clone();
+            wait.request();
+            dht.spawn(async move {
+                let outcome = match dht_cloned.query_value(&peer, &key).await {
+                    PeerValueAnswer::Found(_) => ProbeOutcome::Retained,
+                    PeerValueAnswer::NotFound | PeerValueAnswer::Invalid => ProbeOutcome::Dropped,
+                    PeerValueAnswer::NoAnswer => ProbeOutcome::NoAnswer,
+                };
+                if outcome != ProbeOutcome::NoAnswer {
+                    dht_cloned.update_peer_stats(&peer, |stats| {
+                        stats.update_probes(outcome == ProbeOutcome::Retained)
+                    })
+                }
+                wait.respond(Some((peer, outcome)))
+            });
+        }
+        let mut ret = Vec::new();
+        while let Some(answer) = wait.wait(&mut queue_reader, false).await {
+            if let Some(answer) = answer {
+                if answer.1 == ProbeOutcome::Dropped {
+                    log_event!(
+                        info,
+                        "storage_probe",
+                        peer = answer.0,
+                        key = base64::encode(key),
+                        outcome = "dropped"
+                    );
+                }
+                ret.push(answer)
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Query peer for value with given key ID, found value is verified like values
+    /// found by lookups
+    pub(crate) async fn query_value(&self, peer: &Arc<KeyId>, key: &DhtKeyId) -> PeerValueAnswer {
+        let query = TLObject::new(rpc::dht::FindValue {
+            key: ton::int256(*key),
+            k: 6,
+        });
+        let answer = match self.query(peer, &query).await {
+            Ok(Some(answer)) => answer,
+            Ok(None) => return PeerValueAnswer::NoAnswer,
+            Err(e) => {
+                log::debug!(target: TARGET, "FindValue query to {} error: {}", peer, e);
+                return PeerValueAnswer::NoAnswer;
+            }
+        };
+        let mut value = match Query::parse::<TLObject, DhtValueResult>(answer, &query) {
+            Ok(DhtValueResult::Dht_ValueFound(value)) => value.value.only(),
+            Ok(DhtValueResult::Dht_ValueNotFound(_)) => return PeerValueAnswer::NotFound,
+            Err(e) => {
+                log::debug!(target: TARGET, "Bad FindValue answer from {}: {}", peer, e);
+                return PeerValueAnswer::Invalid;
+            }
+        };
+        match self.verify_found_value(key, &mut value) {
+            Ok(()) => PeerValueAnswer::Found(value),
+            Err(e) => {
+                log::debug!(target: TARGET, "Bad value from {}: {}", peer, e);
+                PeerValueAnswer::Invalid
+            }
+        }
+    }
+}
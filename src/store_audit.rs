@@ -0,0 +1,217 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use adnl::common::{hash, now, KeyId};
+use ton_api::ton::dht::value::Value as DhtValue;
+use ton_types::Result;
+
+use crate::{DhtNode, TARGET};
+
+/// Audit of accepted Store queries
+#[derive(Clone, Debug)]
+pub struct StoreAudit {
+    /// Max number of records kept in memory, oldest ones are dropped above it
+    pub capacity: usize,
+    /// If set, records are also appended to this file as tab-separated lines.
+    /// Key names are escaped, so names received from peers cannot forge columns
+    /// or lines
+    pub path: Option<PathBuf>,
+}
+
+impl Default for StoreAudit {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            path: None,
+        }
+    }
+}
+
+/// Record of accepted Store query
+#[derive(Clone, Debug)]
+pub struct StoreAuditRecord {
+    /// Time the value was accepted
+    pub accepted_at: i32,
+    /// ADNL ID of peer which sent the query
+    pub peer: Arc<KeyId>,
+    /// ID of key the value is signed with
+    pub signer: Arc<KeyId>,
+    /// Key name of the value
+    pub name: String,
+    /// Key index of the value
+    pub idx: i32,
+    /// Size of the value in bytes
+    pub size: usize,
+    /// TTL of the value
+    pub ttl: i32,
+}
+
+/// Bounded ring of store audit records, optionally persisted by background
+/// writer thread
+pub(crate) struct StoreAuditLog {
+    capacity: usize,
+    file: Option<Mutex<mpsc::Sender<String>>>,
+    records: Mutex<VecDeque<StoreAuditRecord>>,
+}
+
+impl StoreAuditLog {
+    pub(crate) fn open(audit: &StoreAudit) -> Result<Self> {
+        let file = if let Some(path) = &audit.path {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let (sender, receiver) = mpsc::channel();
+            thread::Builder::new()
+                .name("dht-store-audit".to_string())
+                .spawn(move || Self::write_lines(file, receiver))?;
+            Some(Mutex::new(sender))
+        } else {
+            None
+        };
+        Ok(Self {
+            capacity: audit.capacity.max(1),
+            file,
+            records: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    fn push(&self, record: StoreAuditRecord) {
+        if let Some(file) = &self.file {
+            let file = match file.lock() {
+                Ok(file) => file,
+                Err(e) => e.into_inner(),
+            };
+            if file.send(Self::format_line(&record)).is_err() {
+                log::warn!(target: TARGET, "DHT store audit writer is stopped")
+            }
+        }
+        let mut records = self.lock();
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record)
+    }
+
+    /// Tab-separated line of record, key name is escaped as Rust string literal
+    fn format_line(record: &StoreAuditRecord) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            record.accepted_at,
+            record.peer,
+            record.signer,
+            record.name.escape_default(),
+            record.idx,
+            record.size,
+            record.ttl
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<StoreAuditRecord>> {
+        match self.records.lock() {
+            Ok(records) => records,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Append queued lines to file, flushing after each batch, till log is dropped
+    fn write_lines(file: File, receiver: mpsc::Receiver<String>) {
+        let mut file = BufWriter::new(file);
+        while let Ok(line) = receiver.recv() {
+            let mut written = file.write_all(line.as_bytes());
+            for line in receiver.try_iter() {
+                if written.is_err() {
+                    break;
+                }
+                written = file.write_all(line.as_bytes())
+            }
+            if let Err(e) = written.and_then(|_| file.flush()) {
+                log::warn!(target: TARGET, "Cannot write DHT store audit record: {}", e)
+            }
+        }
+    }
+}
+
+impl DhtNode {
+    /// Recent accepted Store queries in order of acceptance, if store audit is enabled
+    pub fn store_audit_records(&self) -> Vec<StoreAuditRecord> {
+        self.store_audit
+            .as_ref()
+            .map(|audit| audit.lock().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Prepare audit record of value received from peer, if store audit is enabled
+    pub(crate) fn store_audit_record(
+        &self,
+        peer: &Arc<KeyId>,
+        value: &DhtValue,
+    ) -> Result<Option<StoreAuditRecord>> {
+        if self.store_audit.is_none() {
+            return Ok(None);
+        }
+        let record = StoreAuditRecord {
+            accepted_at: now(),
+            peer: peer.clone(),
+            signer: KeyId::from_data(hash(value.key.id.clone())?),
+            name: String::from_utf8_lossy(&value.key.key.name.0).into_owned(),
+            idx: value.key.key.idx,
+            size: value.value.len(),
+            ttl: value.ttl,
+        };
+        Ok(Some(record))
+    }
+
+    /// Remember accepted Store query
+    pub(crate) fn audit_store(&self, record: Option<StoreAuditRecord>) {
+        if let (Some(audit), Some(record)) = (&self.store_audit, record) {
+            audit.push(record)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, idx: i32) -> StoreAuditRecord {
+        StoreAuditRecord {
+            accepted_at: 1,
+            peer: KeyId::from_data([1; 32]),
+            signer: KeyId::from_data([2; 32]),
+            name: name.to_string(),
+            idx,
+            size: 3,
+            ttl: 4,
+        }
+    }
+
+    #[test]
+    fn remote_name_cannot_forge_columns_or_lines() {
+        let line = StoreAuditLog::format_line(&record("a\tb\nc\\d", 0));
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1);
+        let columns: Vec<&str> = line.trim_end().split('\t').collect();
+        assert_eq!(columns.len(), 7);
+        assert_eq!(columns[3], "a\\tb\\nc\\\\d");
+    }
+
+    #[test]
+    fn oldest_records_are_dropped_above_capacity() {
+        let log = StoreAuditLog::open(&StoreAudit {
+            capacity: 2,
+            path: None,
+        })
+        .unwrap();
+        for idx in 0..3 {
+            log.push(record("address", idx))
+        }
+        let records = log.lock();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].idx, 1);
+        assert_eq!(records[1].idx, 2);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::HashSet,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+};
+
+use adnl::common::{now, KeyOption};
+use adnl::node::{AdnlNode, AdnlNodeConfig};
+use ton_api::ton::dht::ValueResult as DhtValueResult;
+use ton_api::ton::{self, rpc};
+
+use crate::{
+    AddPeerResult, AddressPolicy, DhtNode, DhtNodeOptions, DhtRole, EventField, PeerEviction,
+    PeerRecord, PeerStats, RejectReason, ValidationLimits,
+};
+
+const KEY_TAG: usize = 1;
+
+static NEXT_PORT: AtomicU16 = AtomicU16::new(47100);
+
+/// DHT node on loopback with fresh key, ADNL is not started
+async fn test_node(options: DhtNodeOptions) -> Arc<DhtNode> {
+    let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+    let (_, config) = AdnlNodeConfig::with_ip_address_and_key_type(
+        &format!("127.0.0.1:{}", port),
+        KeyOption::KEY_ED25519,
+        vec![KEY_TAG],
+    )
+    .unwrap();
+    let adnl = AdnlNode::with_config(config).await.unwrap();
+    DhtNode::with_options(adnl, KEY_TAG, options).unwrap()
+}
+
+fn local_options() -> DhtNodeOptions {
+    DhtNodeOptions {
+        address_policy: AddressPolicy::AllowPrivate,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn banned_peer_is_forgotten() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    let peer_id = peer.node_key.id().clone();
+    let peer_node = peer.get_signed_node().unwrap();
+    assert!(matches!(
+        dht.add_peer(&peer_node).unwrap(),
+        AddPeerResult::Added(_)
+    ));
+    assert!(dht.known_peers_snapshot().contains(&peer_id));
+    dht.ban_peer(&peer_id);
+    assert!(!dht.known_peers_snapshot().contains(&peer_id));
+    assert!(dht.peer_stats(&peer_id).is_none());
+    assert!(dht.get_known_nodes(16).unwrap().is_empty());
+    assert!(matches!(
+        dht.add_peer(&peer_node).unwrap(),
+        AddPeerResult::RejectedBanned
+    ));
+}
+
+#[tokio::test]
+async fn unbanned_peer_can_be_added_again() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    let peer_id = peer.node_key.id().clone();
+    dht.ban_peer(&peer_id);
+    dht.unban_peer(&peer_id);
+    let added = dht.add_peer(&peer.get_signed_node().unwrap()).unwrap();
+    assert!(matches!(added, AddPeerResult::Added(_)));
+    let known: HashSet<_> = dht.known_peers_snapshot().into_iter().collect();
+    assert!(known.contains(&peer_id));
+}
+
+#[tokio::test]
+async fn suggested_peer_is_inserted_once_registered() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    let peer_id = peer.node_key.id().clone();
+    let result = dht
+        .add_suggested_peer(&peer.get_signed_node().unwrap())
+        .unwrap();
+    assert!(matches!(result, AddPeerResult::Added(_)));
+    // Test runtime is single-threaded, so spawned registration has not run yet
+    assert!(!dht.known_peers_snapshot().contains(&peer_id));
+    assert!(dht.peer_stats(&peer_id).is_none());
+    dht.registrations.drain();
+    assert!(dht.known_peers_snapshot().contains(&peer_id));
+    assert_eq!(dht.get_known_nodes(16).unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn suggested_peer_banned_while_pending_is_not_inserted() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    let peer_id = peer.node_key.id().clone();
+    dht.add_suggested_peer(&peer.get_signed_node().unwrap())
+        .unwrap();
+    dht.ban_peer(&peer_id);
+    dht.registrations.drain();
+    assert!(!dht.known_peers_snapshot().contains(&peer_id));
+    assert!(dht.get_known_nodes(16).unwrap().is_empty());
+}
+
+#[test]
+fn event_fields_are_quoted_when_needed() {
+    assert_eq!(EventField(&"peer-1").to_string(), "peer-1");
+    assert_eq!(EventField(&42).to_string(), "42");
+    assert_eq!(EventField(&"").to_string(), "\"\"");
+    assert_eq!(EventField(&"a b").to_string(), "\"a b\"");
+    assert_eq!(EventField(&"x=1\n\"y\"").to_string(), "\"x=1\\n\\\"y\\\"\"");
+}
+
+#[tokio::test]
+async fn value_over_limits_is_rejected_by_quota() {
+    let dht = test_node(local_options()).await;
+    let limits = ValidationLimits::default();
+    let data = vec![0; limits.max_value_size + 1];
+    let value = dht.sign_value("address", &data, dht.key()).unwrap();
+    let error = limits.check_value(&value).unwrap_err();
+    assert_eq!(RejectReason::from_error(&error), RejectReason::Quota);
+}
+
+#[test]
+fn old_storage_probes_fade() {
+    let mut stats = PeerStats::default();
+    for _ in 0..PeerStats::MAX_PROBES {
+        stats.update_probes(false)
+    }
+    assert!(stats.drops_values());
+    for _ in 0..PeerStats::MAX_PROBES {
+        stats.update_probes(true)
+    }
+    assert!(!stats.drops_values());
+    assert!(stats.probes_retained + stats.probes_dropped <= PeerStats::MAX_PROBES);
+}
+
+#[tokio::test]
+async fn fifo_eviction_keeps_priority_peers_and_cleans_indexes() {
+    let dht = test_node(DhtNodeOptions {
+        max_known_peers: 10,
+        peer_eviction: PeerEviction::Fifo,
+        ..local_options()
+    })
+    .await;
+    let priority = test_node(local_options()).await;
+    dht.add_priority_peer(&priority.get_signed_node().unwrap())
+        .unwrap();
+    let mut peers = Vec::new();
+    for _ in 0..10 {
+        let peer = test_node(local_options()).await;
+        dht.add_peer(&peer.get_signed_node().unwrap()).unwrap();
+        peers.push(peer.node_key.id().clone())
+    }
+    let known = dht.known_peers_snapshot();
+    assert!(known.len() <= 10);
+    assert!(known.contains(priority.node_key.id()));
+    let evicted = &peers[0];
+    assert!(!known.contains(evicted));
+    assert!(dht.peer_stats(evicted).is_none());
+    assert!(!dht.keys_by_ip(&Ipv4Addr::LOCALHOST).contains(evicted));
+    let routed = dht.get_known_nodes(16).unwrap();
+    assert_eq!(routed.len(), known.len());
+}
+
+#[tokio::test]
+async fn ban_is_not_held_against_peer_forever() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    let peer_id = peer.node_key.id().clone();
+    dht.ban_peer(&peer_id);
+    assert!(dht.peer_db.is_unreliable(&peer_id));
+    dht.unban_peer(&peer_id);
+    assert!(!dht.peer_db.is_unreliable(&peer_id));
+    let old_ban = PeerRecord {
+        bans: 3,
+        last_banned_at: now() - 2 * 24 * 3600,
+        ..Default::default()
+    };
+    assert!(!old_ban.is_unreliable());
+}
+
+#[tokio::test]
+async fn shortlists_are_shared_by_longer_key_prefix() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    let peer_id = peer.node_key.id().clone();
+    let mut key = [0u8; 32];
+    key[0] = 0xaa;
+    key[1] = 0x01;
+    dht.update_lookup_shortlist(&key, &peer_id);
+    let mut near = key;
+    near[31] = 0xff;
+    assert_eq!(dht.lookup_shortlist(&near), vec![peer_id]);
+    let mut far = key;
+    far[1] = 0x02;
+    assert!(dht.lookup_shortlist(&far).is_empty());
+}
+
+#[tokio::test]
+async fn bad_snapshot_records_are_skipped() {
+    let dht = test_node(local_options()).await;
+    let peer = test_node(local_options()).await;
+    dht.add_peer(&peer.get_signed_node().unwrap()).unwrap();
+    let path = std::env::temp_dir().join(format!("dht-peers-{}", std::process::id()));
+    assert_eq!(dht.save_peers(&path, 16).unwrap(), 1);
+    let mut data = std::fs::read(&path).unwrap();
+    // Record with garbage node, then truncated length prefix
+    data.extend_from_slice(&48u32.to_le_bytes());
+    data.extend_from_slice(&[0xab; 48]);
+    data.extend_from_slice(&[1, 2]);
+    std::fs::write(&path, &data).unwrap();
+    let restored = test_node(local_options()).await;
+    assert_eq!(restored.load_peers(&path).unwrap(), 1);
+    let stats = restored.peer_stats(peer.node_key.id()).unwrap();
+    assert!(stats.last_rtt.is_none());
+    std::fs::remove_file(&path).ok();
+}
+
+#[tokio::test]
+async fn client_only_node_never_answers_with_itself() {
+    let dht = test_node(DhtNodeOptions {
+        advertise_local_node: true,
+        include_local_node: true,
+        role: DhtRole::ClientOnly,
+        ..local_options()
+    })
+    .await;
+    let peer = test_node(local_options()).await;
+    dht.add_peer(&peer.get_signed_node().unwrap()).unwrap();
+    let is_local = |node: &ton_api::ton::dht::node::Node| {
+        KeyOption::from_tl_public_key(&node.id).unwrap().id() == dht.node_key.id()
+    };
+    let key = ton::int256(*dht.node_key.id().data());
+    let nodes = dht
+        .process_find_node(&rpc::dht::FindNode { key, k: 10 })
+        .unwrap();
+    assert_eq!(nodes.nodes.len(), 1);
+    assert!(!nodes.nodes.iter().any(is_local));
+    let key = ton::int256([0xee; 32]);
+    match dht
+        .process_find_value(&rpc::dht::FindValue { key, k: 10 })
+        .unwrap()
+    {
+        DhtValueResult::Dht_ValueNotFound(found) => {
+            assert!(!found.nodes.nodes.iter().any(is_local))
+        }
+        other => panic!("Unexpected answer {:?}", other),
+    }
+}
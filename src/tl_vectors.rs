@@ -0,0 +1,160 @@
+//! TL encoding vectors of DHT queries and answers. Vectors are encoded by hand
+//! from `ton_api.tl` the way the reference node does it: constructor IDs are CRC32
+//! of schema lines, integers are little endian, bytes are length prefixed and
+//! padded to 4 bytes, bare fields have no constructor ID
+
+use adnl::common::{deserialize, serialize};
+use ton_api::ton::dht::key::Key as DhtKey;
+use ton_api::ton::dht::keydescription::KeyDescription as DhtKeyDescription;
+use ton_api::ton::dht::node::Node;
+use ton_api::ton::dht::nodes::Nodes;
+use ton_api::ton::dht::value::Value as DhtValue;
+use ton_api::ton::dht::valueresult::{ValueFound, ValueNotFound};
+use ton_api::ton::dht::{Stored, UpdateRule, ValueResult as DhtValueResult};
+use ton_api::ton::pub_::publickey::Ed25519;
+use ton_api::ton::{rpc, TLObject};
+use ton_api::{ton, IntoBoxed};
+
+/// `dht.findValue key:int256 k:int`
+const FIND_VALUE: &str = concat!(
+    "11604bae",
+    "1111111111111111111111111111111111111111111111111111111111111111",
+    "06000000",
+);
+
+/// `dht.store value:dht.value`, value is bare
+const STORE: &str = concat!(
+    "12429334",
+    "2222222222222222222222222222222222222222222222222222222222222222",
+    "0761646472657373",
+    "00000000",
+    "c6b41348",
+    "3333333333333333333333333333333333333333333333333333333333333333",
+    "f7319fcc",
+    "00000000",
+    "03616263",
+    "00105e5f",
+    "0444444444000000",
+);
+
+/// `dht.valueFound value:dht.Value`, value is boxed
+const VALUE_FOUND: &str = concat!(
+    "74f70ce4",
+    "cb27ad90",
+    "2222222222222222222222222222222222222222222222222222222222222222",
+    "0761646472657373",
+    "00000000",
+    "c6b41348",
+    "3333333333333333333333333333333333333333333333333333333333333333",
+    "f7319fcc",
+    "00000000",
+    "03616263",
+    "00105e5f",
+    "0444444444000000",
+);
+
+/// `dht.valueNotFound nodes:dht.nodes` with empty nodes vector
+const VALUE_NOT_FOUND: &str = "680562a200000000";
+
+/// `dht.stored`
+const STORED: &str = "08fb2670";
+
+fn vector(hex_str: &str) -> Vec<u8> {
+    hex::decode(hex_str).unwrap()
+}
+
+fn value() -> DhtValue {
+    DhtValue {
+        key: DhtKeyDescription {
+            id: Ed25519 {
+                key: ton::int256([0x33; 32]),
+            }
+            .into_boxed(),
+            key: DhtKey {
+                id: ton::int256([0x22; 32]),
+                idx: 0,
+                name: ton::bytes(b"address".to_vec()),
+            },
+            signature: ton::bytes::default(),
+            update_rule: UpdateRule::Dht_UpdateRule_Signature,
+        },
+        ttl: 1600000000,
+        signature: ton::bytes(vec![0x44; 4]),
+        value: ton::bytes(b"abc".to_vec()),
+    }
+}
+
+/// Vector parses and serializes back byte to byte
+fn assert_roundtrip(hex_str: &str) -> TLObject {
+    let data = vector(hex_str);
+    let object = deserialize(&data).unwrap();
+    assert_eq!(serialize(&object).unwrap(), data);
+    object
+}
+
+#[test]
+fn find_value_matches_vector() {
+    let query = TLObject::new(rpc::dht::FindValue {
+        key: ton::int256([0x11; 32]),
+        k: 6,
+    });
+    assert_eq!(serialize(&query).unwrap(), vector(FIND_VALUE));
+    let query = assert_roundtrip(FIND_VALUE)
+        .downcast::<rpc::dht::FindValue>()
+        .unwrap();
+    assert_eq!(query.key.0, [0x11; 32]);
+    assert_eq!(query.k, 6);
+}
+
+#[test]
+fn store_matches_vector() {
+    let query = TLObject::new(rpc::dht::Store { value: value() });
+    assert_eq!(serialize(&query).unwrap(), vector(STORE));
+    let query = assert_roundtrip(STORE)
+        .downcast::<rpc::dht::Store>()
+        .unwrap();
+    assert_eq!(query.value.key.key.name.0, b"address".to_vec());
+    assert_eq!(query.value.ttl, 1600000000);
+    assert_eq!(query.value.value.0, b"abc".to_vec());
+}
+
+#[test]
+fn value_found_matches_vector() {
+    let answer = ValueFound {
+        value: value().into_boxed(),
+    }
+    .into_boxed();
+    assert_eq!(serialize(&answer).unwrap(), vector(VALUE_FOUND));
+    let answer = assert_roundtrip(VALUE_FOUND)
+        .downcast::<DhtValueResult>()
+        .unwrap();
+    match answer {
+        DhtValueResult::Dht_ValueFound(found) => {
+            let value = found.value.only();
+            assert_eq!(value.key.key.id.0, [0x22; 32]);
+            assert_eq!(value.signature.0, vec![0x44; 4]);
+        }
+        other => panic!("valueFound is parsed as {:?}", other),
+    }
+}
+
+#[test]
+fn value_not_found_matches_vector() {
+    let answer = ValueNotFound {
+        nodes: Nodes {
+            nodes: Vec::<Node>::new().into(),
+        },
+    }
+    .into_boxed();
+    assert_eq!(serialize(&answer).unwrap(), vector(VALUE_NOT_FOUND));
+    let answer = assert_roundtrip(VALUE_NOT_FOUND)
+        .downcast::<DhtValueResult>()
+        .unwrap();
+    assert!(matches!(answer, DhtValueResult::Dht_ValueNotFound(_)));
+}
+
+#[test]
+fn stored_matches_vector() {
+    assert_eq!(serialize(&Stored::Dht_Stored).unwrap(), vector(STORED));
+    assert!(assert_roundtrip(STORED).downcast::<Stored>().is_ok());
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use adnl::common::KeyId;
+use dashmap::DashMap;
+use ton_api::{ton::TLObject, BoxedSerialize};
+use ton_types::Result;
+
+use crate::{DhtNode, TARGET};
+
+/// Traffic of DHT queries in bytes
+#[derive(Clone, Debug, Default)]
+pub struct Traffic {
+    /// Number of queries
+    pub queries: u64,
+    /// Bytes sent
+    pub sent: u64,
+    /// Bytes received
+    pub received: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, sent: usize, received: usize) {
+        self.queries += 1;
+        self.sent += sent as u64;
+        self.received += received as u64;
+    }
+}
+
+/// Traffic of one type of DHT queries
+#[derive(Clone, Debug, Default)]
+pub struct QueryTraffic {
+    /// Queries issued by us and their answers
+    pub issued: Traffic,
+    /// Queries served by us and our answers
+    pub served: Traffic,
+}
+
+/// Traffic counters per peer and per query type
+pub(crate) struct TrafficStats {
+    by_peer: DashMap<Arc<KeyId>, Traffic>,
+    by_query: DashMap<&'static str, QueryTraffic>,
+}
+
+impl TrafficStats {
+    const MAX_PEERS: usize = 65536;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            by_peer: DashMap::new(),
+            by_query: DashMap::new(),
+        }
+    }
+
+    fn add(
+        &self,
+        peer: &Arc<KeyId>,
+        kind: &'static str,
+        issued: bool,
+        sent: usize,
+        received: usize,
+    ) {
+        let mut query = self
+            .by_query
+            .entry(kind)
+            .or_insert_with(QueryTraffic::default);
+        if issued {
+            query.value_mut().issued.add(sent, received)
+        } else {
+            query.value_mut().served.add(sent, received)
+        }
+        drop(query);
+        if let Some(mut traffic) = self.by_peer.get_mut(peer) {
+            traffic.value_mut().add(sent, received);
+            return;
+        }
+        if self.by_peer.len() < Self::MAX_PEERS {
+            self.by_peer
+                .entry(peer.clone())
+                .or_insert_with(Traffic::default)
+                .value_mut()
+                .add(sent, received)
+        }
+    }
+}
+
+impl DhtNode {
+    /// Traffic exchanged with DHT peer, if traffic accounting is enabled in options
+    pub fn peer_traffic(&self, peer: &Arc<KeyId>) -> Option<Traffic> {
+        self.traffic
+            .as_ref()?
+            .by_peer
+            .get(peer)
+            .map(|traffic| traffic.value().clone())
+    }
+
+    /// Traffic per query type, if traffic accounting is enabled in options
+    pub fn query_traffic(&self) -> Vec<(&'static str, QueryTraffic)> {
+        let traffic = if let Some(traffic) = &self.traffic {
+            traffic
+        } else {
+            return Vec::new();
+        };
+        let mut ret: Vec<_> = traffic
+            .by_query
+            .iter()
+            .map(|query| (*query.key(), query.value().clone()))
+            .collect();
+        ret.sort_by_key(|(kind, _)| *kind);
+        ret
+    }
+
+    /// Peers with the most traffic, in descending order of total bytes
+    pub fn top_peers_by_traffic(&self, limit: usize) -> Vec<(Arc<KeyId>, Traffic)> {
+        let traffic = if let Some(traffic) = &self.traffic {
+            traffic
+        } else {
+            return Vec::new();
+        };
+        let mut ret: Vec<_> = traffic
+            .by_peer
+            .iter()
+            .map(|peer| (peer.key().clone(), peer.value().clone()))
+            .collect();
+        ret.sort_by_key(|(_, traffic)| std::cmp::Reverse(traffic.sent + traffic.received));
+        ret.truncate(limit);
+        ret
+    }
+
+    /// Account query issued by us along with its answer
+    pub(crate) fn account_issued_query(
+        &self,
+        peer: &Arc<KeyId>,
+        query: &TLObject,
+        prefix_len: usize,
+        answer: &Result<Option<TLObject>>,
+    ) {
+        let traffic = if let Some(traffic) = &self.traffic {
+            traffic
+        } else {
+            return;
+        };
+        let sent = self.serialized_len(query) + prefix_len;
+        let received = match answer {
+            Ok(Some(answer)) => self.serialized_len(answer),
+            _ => 0,
+        };
+        traffic.add(peer, Self::query_kind(query), true, sent, received)
+    }
+
+    /// Account query served by us along with our answer
+    pub(crate) fn account_served_query<T: BoxedSerialize>(
+        &self,
+        peer: &Arc<KeyId>,
+        kind: &'static str,
+        received: usize,
+        answer: &T,
+    ) {
+        if let Some(traffic) = &self.traffic {
+            traffic.add(peer, kind, false, self.serialized_len(answer), received)
+        }
+    }
+
+    /// Size of incoming query, zero if traffic accounting is disabled
+    pub(crate) fn incoming_query_len(&self, query: &TLObject) -> usize {
+        if self.traffic.is_some() {
+            self.serialized_len(query)
+        } else {
+            0
+        }
+    }
+
+    fn serialized_len<T: BoxedSerialize>(&self, object: &T) -> usize {
+        match self.buffers.serialize(object) {
+            Ok(buf) => buf.len(),
+            Err(e) => {
+                log::debug!(target: TARGET, "Cannot measure size of TL object: {}", e);
+                0
+            }
+        }
+    }
+}
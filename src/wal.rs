@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+use adnl::common::{deserialize, hash, now, serialize};
+use tokio::sync::oneshot;
+use ton_api::ton::dht::value::Value as DhtValue;
+use ton_api::ton::dht::Value as DhtValueBoxed;
+use ton_api::{ton, IntoBoxed};
+use ton_types::{error, fail, Result};
+
+use crate::{format::FileFormat, DhtError, DhtKeyId, TARGET};
+
+/// Append-only log of accepted DHT values after format header, each record is
+/// serialized value prefixed with its length (u32, little endian). Records are
+/// written, synced and compacted by background writer thread, so store queries
+/// never wait for disk
+/// TL constructor ID of `dht.value`
+const VALUE_CONSTRUCTOR: u32 = 0x90ad27cb;
+
+pub(crate) struct StoreWal {
+    appended: Arc<AtomicUsize>,
+    compacting: Arc<AtomicBool>,
+    sender: Mutex<mpsc::Sender<Command>>,
+}
+
+enum Command {
+    Append(Vec<u8>),
+    Compact,
+    Flush(oneshot::Sender<()>),
+}
+
+struct Writer {
+    appended: Arc<AtomicUsize>,
+    compacting: Arc<AtomicBool>,
+    file: File,
+    path: PathBuf,
+}
+
+impl StoreWal {
+    const MIN_RECORDS_TO_COMPACT: usize = 1024;
+
+    /// Open log and replay values from it in order of appending
+    pub(crate) fn open(path: &Path) -> Result<(Self, Vec<(DhtKeyId, DhtValue)>)> {
+        let mut data = Vec::new();
+        if path.exists() {
+            File::open(path)?.read_to_end(&mut data)?;
+        }
+        let (start, legacy) = FileFormat::StoreLog.read_header(&data, path, Self::is_legacy)?;
+        let mut values = Vec::new();
+        if data.is_empty() {
+            FileFormat::StoreLog.write(path, &[])?
+        } else {
+            let pos = Self::read_records(&data, start, &mut values);
+            if legacy {
+                FileFormat::StoreLog.migrate(path, &data[start..pos])?
+            } else if pos < data.len() {
+                // Torn write after crash
+                log::warn!(
+                    target: TARGET,
+                    "Truncate DHT store log {} from {} to {} bytes",
+                    path.display(),
+                    data.len(),
+                    pos
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(pos as u64)?;
+            }
+        }
+        let appended = Arc::new(AtomicUsize::new(values.len()));
+        let compacting = Arc::new(AtomicBool::new(false));
+        let writer = Writer {
+            appended: appended.clone(),
+            compacting: compacting.clone(),
+            file: Self::open_for_append(path)?,
+            path: path.to_path_buf(),
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("dht-store-log".to_string())
+            .spawn(move || writer.run(receiver))?;
+        let wal = Self {
+            appended,
+            compacting,
+            sender: Mutex::new(sender),
+        };
+        Ok((wal, values))
+    }
+
+    /// Queue value to append to log
+    pub(crate) fn append(&self, value: &DhtValue) -> Result<()> {
+        self.send(Command::Append(Self::encode(value)?))
+    }
+
+    /// Queue rewrite of log with values which are not expired or superseded yet,
+    /// unless one is already queued
+    pub(crate) fn compact(&self) -> Result<()> {
+        if self.compacting.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.send(Command::Compact)
+    }
+
+    /// Wait till all queued records are written and synced
+    pub(crate) async fn flush(&self) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Command::Flush(sender))?;
+        receiver
+            .await
+            .map_err(|_| error!("DHT store log writer is stopped"))
+    }
+
+    /// Queue tombstone of value: expired record with the same key which supersedes
+    /// the value on replay and is dropped with it by compaction
+    pub(crate) fn remove(&self, value: &DhtValue) -> Result<()> {
+        let tombstone = DhtValue {
+            key: value.key.clone(),
+            ttl: 0,
+            signature: ton::bytes::default(),
+            value: ton::bytes::default(),
+        };
+        self.append(&tombstone)
+    }
+
+    /// Check if log has grown much bigger than live values set
+    pub(crate) fn needs_compaction(&self, live: usize) -> bool {
+        self.appended.load(Ordering::Relaxed) > Self::MIN_RECORDS_TO_COMPACT.max(live * 2)
+    }
+
+    fn decode(record: &[u8]) -> Result<(DhtKeyId, DhtValue)> {
+        let value = deserialize(record)?
+            .downcast::<DhtValueBoxed>()
+            .map_err(|object| {
+                error!(DhtError::Malformed(format!(
+                    "Unexpected DHT store log record {:?}",
+                    object
+                )))
+            })?
+            .only();
+        Ok((hash(value.key.key.clone())?, value))
+    }
+
+    fn encode(value: &DhtValue) -> Result<Vec<u8>> {
+        let data = serialize(&value.clone().into_boxed())?;
+        let mut record = Vec::with_capacity(data.len() + 4);
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&data);
+        Ok(record)
+    }
+
+    /// Log of version 0 consists of boxed values
+    fn is_legacy(data: &[u8]) -> bool {
+        FileFormat::is_record_log(data, true, |record| {
+            record.starts_with(&VALUE_CONSTRUCTOR.to_le_bytes())
+        })
+    }
+
+    fn open_for_append(path: &Path) -> Result<File> {
+        Ok(OpenOptions::new().create(true).append(true).open(path)?)
+    }
+
+    /// Decode records starting at given offset, returns offset after last good record
+    fn read_records(data: &[u8], start: usize, values: &mut Vec<(DhtKeyId, DhtValue)>) -> usize {
+        let mut pos = start;
+        while pos + 4 <= data.len() {
+            let len = u32::from_le_bytes(*arrayref::array_ref!(data, pos, 4)) as usize;
+            if pos + 4 + len > data.len() {
+                break;
+            }
+            match Self::decode(&data[pos + 4..pos + 4 + len]) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    log::warn!(target: TARGET, "Bad DHT store log record at {}: {}", pos, e);
+                    break;
+                }
+            }
+            pos += 4 + len;
+        }
+        pos
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        let sender = match self.sender.lock() {
+            Ok(sender) => sender,
+            Err(e) => e.into_inner(),
+        };
+        if sender.send(command).is_err() {
+            fail!("DHT store log writer is stopped")
+        }
+        Ok(())
+    }
+}
+
+impl Writer {
+    const MAX_BATCH: usize = 1024;
+
+    /// Write queued records in batches with one sync per batch, till log is dropped
+    fn run(mut self, receiver: mpsc::Receiver<Command>) {
+        while let Ok(command) = receiver.recv() {
+            let mut compact = false;
+            let mut flushed = Vec::new();
+            let mut written = 0;
+            for command in Some(command)
+                .into_iter()
+                .chain(receiver.try_iter().take(Self::MAX_BATCH))
+            {
+                match command {
+                    Command::Append(record) => match self.file.write_all(&record) {
+                        Ok(()) => written += 1,
+                        Err(e) => log::warn!(target: TARGET, "Cannot write DHT store log: {}", e),
+                    },
+                    Command::Compact => compact = true,
+                    Command::Flush(sender) => flushed.push(sender),
+                }
+            }
+            if written > 0 {
+                if let Err(e) = self.file.sync_data() {
+                    log::warn!(target: TARGET, "Cannot sync DHT store log: {}", e)
+                }
+                self.appended.fetch_add(written, Ordering::Relaxed);
+            }
+            if compact {
+                if let Err(e) = self.compact() {
+                    log::warn!(target: TARGET, "Cannot compact DHT store log: {}", e)
+                }
+                self.compacting.store(false, Ordering::Release);
+            }
+            for sender in flushed {
+                sender.send(()).ok();
+            }
+        }
+    }
+
+    /// Rewrite log keeping last record of each key if it is not expired yet
+    fn compact(&mut self) -> Result<usize> {
+        let mut data = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut data)?;
+        let (start, _) =
+            FileFormat::StoreLog.read_header(&data, &self.path, StoreWal::is_legacy)?;
+        let mut records = Vec::new();
+        StoreWal::read_records(&data, start, &mut records);
+        let mut values = HashMap::new();
+        for (key, value) in records {
+            values.insert(key, value);
+        }
+        let version = now();
+        let mut compacted = Vec::new();
+        let mut count = 0;
+        for value in values.values() {
+            if value.ttl > version {
+                compacted.extend_from_slice(&StoreWal::encode(value)?);
+                count += 1;
+            }
+        }
+        FileFormat::StoreLog.write(&self.path, &compacted)?;
+        self.file = StoreWal::open_for_append(&self.path)?;
+        self.appended.store(count, Ordering::Relaxed);
+        log::debug!(
+            target: TARGET,
+            "Compacted DHT store log {}: {} values",
+            self.path.display(),
+            count
+        );
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use ton_api::ton::dht::key::Key as DhtKey;
+    use ton_api::ton::dht::keydescription::KeyDescription as DhtKeyDescription;
+    use ton_api::ton::dht::UpdateRule;
+    use ton_api::ton::pub_::publickey::Ed25519;
+
+    fn value(name: &str, ttl: i32, data: &[u8]) -> DhtValue {
+        DhtValue {
+            key: DhtKeyDescription {
+                id: Ed25519 {
+                    key: ton::int256([1; 32]),
+                }
+                .into_boxed(),
+                key: DhtKey {
+                    id: ton::int256([2; 32]),
+                    idx: 0,
+                    name: ton::bytes(name.as_bytes().to_vec()),
+                },
+                signature: ton::bytes::default(),
+                update_rule: UpdateRule::Dht_UpdateRule_Signature,
+            },
+            ttl,
+            signature: ton::bytes::default(),
+            value: ton::bytes(data.to_vec()),
+        }
+    }
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dht-wal-{}-{}", name, std::process::id()));
+        fs::remove_file(&path).ok();
+        path
+    }
+
+    /// Replay log like node constructor does, returns live values by key
+    fn replay(values: Vec<(DhtKeyId, DhtValue)>) -> HashMap<DhtKeyId, DhtValue> {
+        let mut ret = HashMap::new();
+        let version = now();
+        for (key, value) in values {
+            if value.ttl > version {
+                ret.insert(key, value);
+            } else {
+                ret.remove(&key);
+            }
+        }
+        ret
+    }
+
+    #[tokio::test]
+    async fn appended_values_are_replayed() {
+        let path = log_path("replay");
+        let (wal, values) = StoreWal::open(&path).unwrap();
+        assert!(values.is_empty());
+        wal.append(&value("a", now() + 100, b"1")).unwrap();
+        wal.append(&value("b", now() + 100, b"2")).unwrap();
+        wal.flush().await.unwrap();
+        drop(wal);
+        let (_, values) = StoreWal::open(&path).unwrap();
+        assert_eq!(replay(values).len(), 2);
+        fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn removed_values_are_not_replayed() {
+        let path = log_path("tombstone");
+        let (wal, _) = StoreWal::open(&path).unwrap();
+        let purged = value("a", now() + 100, b"1");
+        wal.append(&purged).unwrap();
+        wal.append(&value("b", now() + 100, b"2")).unwrap();
+        wal.remove(&purged).unwrap();
+        wal.flush().await.unwrap();
+        drop(wal);
+        let (_, values) = StoreWal::open(&path).unwrap();
+        let live = replay(values);
+        assert_eq!(live.len(), 1);
+        assert!(!live.contains_key(&hash(purged.key.key.clone()).unwrap()));
+        fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn compaction_keeps_last_live_records() {
+        let path = log_path("compact");
+        let (wal, _) = StoreWal::open(&path).unwrap();
+        let purged = value("a", now() + 100, b"1");
+        wal.append(&purged).unwrap();
+        wal.remove(&purged).unwrap();
+        wal.append(&value("b", now() + 100, b"old")).unwrap();
+        wal.append(&value("b", now() + 200, b"new")).unwrap();
+        wal.append(&value("c", now() - 1, b"expired")).unwrap();
+        wal.compact().unwrap();
+        wal.flush().await.unwrap();
+        drop(wal);
+        let (_, values) = StoreWal::open(&path).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].1.value.0, b"new".to_vec());
+        fs::remove_file(&path).ok();
+    }
+}